@@ -12,6 +12,7 @@ use std::rc::Rc;
 use blip_buf::BlipBuf;
 
 use crate::clock::Clock;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 // Name Addr 7654 3210 Function
 // -------------------------------------------------------------------
@@ -57,6 +58,7 @@ pub enum Channel {
     Control,
 }
 
+#[derive(Clone)]
 pub struct Register {
     pub channel: Channel,
     pub nrx0: u8,
@@ -403,10 +405,30 @@ impl Register {
     }
 }
 
+impl Stateful for Register {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.nrx0);
+        writer.write_u8(self.nrx1);
+        writer.write_u8(self.nrx2);
+        writer.write_u8(self.nrx3);
+        writer.write_u8(self.nrx4);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.nrx0 = reader.read_u8()?;
+        self.nrx1 = reader.read_u8()?;
+        self.nrx2 = reader.read_u8()?;
+        self.nrx3 = reader.read_u8()?;
+        self.nrx4 = reader.read_u8()?;
+        Ok(())
+    }
+}
+
 pub struct Blip {
     pub from: u32,
     data: BlipBuf,
     amplitude: i32,
+    sample_rate: u32,
 }
 
 impl Blip {
@@ -420,9 +442,18 @@ impl Blip {
             data: blipbuf,
             from: 0x0000_0000,
             amplitude: 0x0000_0000,
+            sample_rate,
         }
     }
 
+    // Samples already in the buffer are discarded, the amplitude restarts from zero as the new buffer
+    // has no previous deltas.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let from = self.from;
+        *self = Blip::new(sample_rate);
+        self.from = from;
+    }
+
     pub fn set(&mut self, time: u32, ampl: i32) {
         self.from = time;
         let delta = ampl - self.amplitude;
@@ -430,6 +461,13 @@ impl Blip {
         self.data.add_delta(time, delta);
     }
 
+    // Bring the output to zero at the start of the frame, used while the APU is powered off. The time of the last
+    // change is kept, as the channel does not run.
+    pub fn silence(&mut self) {
+        self.data.add_delta(0, -self.amplitude);
+        self.amplitude = 0;
+    }
+
     pub fn end_frame(&mut self, clock_duration: u32) {
         self.data.end_frame(clock_duration)
     }
@@ -443,6 +481,32 @@ impl Blip {
     }
 }
 
+// Samples already in the buffer are not part of the machine state, a copy starts with an empty buffer.
+impl Clone for Blip {
+    fn clone(&self) -> Blip {
+        Blip {
+            from: self.from,
+            amplitude: self.amplitude,
+            ..Blip::new(self.sample_rate)
+        }
+    }
+}
+
+// Samples already in the buffer are not part of the machine state, so they are discarded when loading.
+impl Stateful for Blip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.from);
+        writer.write_u32(self.amplitude as u32);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.from = reader.read_u32()?;
+        self.amplitude = reader.read_u32()? as i32;
+        self.data.clear();
+        Ok(())
+    }
+}
+
 // A volume envelope has a volume counter and an internal timer clocked at 64 Hz by the frame sequencer.
 // When the timer generates a clock and the envelope period is not zero, a new volume is calculated by adding
 // or subtracting (as set by NRx2) one from the current volume. If this new volume within the 0 to 15 range,
@@ -469,6 +533,15 @@ impl VolumeEnvelope {
         }
     }
 
+    // A copy of the envelope that uses the registers of a copied channel.
+    pub fn clone_with(&self, register: Rc<RefCell<Register>>) -> VolumeEnvelope {
+        VolumeEnvelope {
+            register,
+            clock: self.clock,
+            volume: self.volume,
+        }
+    }
+
     pub fn reload(&mut self) {
         let period = self.register.borrow().get_period();
         // The volume envelope and sweep timers treat a period of 0 as 8.
@@ -496,6 +569,18 @@ impl VolumeEnvelope {
     }
 }
 
+impl Stateful for VolumeEnvelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.volume);
+        self.clock.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.volume = reader.read_u8()?;
+        self.clock.load_state(reader)
+    }
+}
+
 // A length counter disables a channel when it decrements to zero. It contains an internal counter and enabled
 // flag. Writing a byte to NRx1 loads the counter with 64-data (256-data for wave channel). The counter can be
 // reloaded at any time. A channel is said to be disabled when the internal enabled flag is clear. When a channel
@@ -516,6 +601,14 @@ impl LengthCounter {
         }
     }
 
+    // A copy of the counter that uses the registers of a copied channel.
+    pub fn clone_with(&self, register: Rc<RefCell<Register>>) -> LengthCounter {
+        LengthCounter {
+            register,
+            n: self.n,
+        }
+    }
+
     pub fn next(&mut self) {
         if self.register.borrow().get_length_enable() && self.n != 0x0000 {
             self.n -= 1;
@@ -536,6 +629,17 @@ impl LengthCounter {
     }
 }
 
+impl Stateful for LengthCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.n);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.n = reader.read_u16()?;
+        Ok(())
+    }
+}
+
 // Frame Sequencer
 // The frame sequencer generates low frequency clocks for the modulation units. It is clocked by a 512 Hz timer.
 //
@@ -579,3 +683,14 @@ impl FrameSequencer {
         self.step == 2 || self.step == 6
     }
 }
+
+impl Stateful for FrameSequencer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.step = reader.read_u8()? % 8;
+        Ok(())
+    }
+}
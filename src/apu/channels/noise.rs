@@ -4,6 +4,7 @@ use std::rc::Rc;
 use crate::apu::channels::{Blip, Channel, LengthCounter, Register, VolumeEnvelope};
 use crate::clock::Clock;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 // The noise channel's frequency timer period is set by a base divisor shifted left some number of bits.
 //
@@ -112,6 +113,45 @@ impl Memory for NoiseChannel {
     }
 }
 
+// The units of a copy share the copy's registers, not those of the original channel.
+impl Clone for NoiseChannel {
+    fn clone(&self) -> NoiseChannel {
+        let register = Rc::new(RefCell::new(self.register.borrow().clone()));
+        NoiseChannel {
+            register: register.clone(),
+            length_counter: self.length_counter.clone_with(register.clone()),
+            volume_envelope: self.volume_envelope.clone_with(register.clone()),
+            blip: self.blip.clone(),
+            clock: self.clock,
+            lfsr: Lfsr {
+                register,
+                n: self.lfsr.n,
+            },
+        }
+    }
+}
+
+impl Stateful for NoiseChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.register.borrow().save_state(writer);
+        self.length_counter.save_state(writer);
+        self.volume_envelope.save_state(writer);
+        self.blip.save_state(writer);
+        self.clock.save_state(writer);
+        writer.write_u16(self.lfsr.n);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.register.borrow_mut().load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.volume_envelope.load_state(reader)?;
+        self.blip.load_state(reader)?;
+        self.clock.load_state(reader)?;
+        self.lfsr.n = reader.read_u16()?;
+        Ok(())
+    }
+}
+
 // The linear feedback shift register (LFSR) generates a pseudo-random bit sequence. It has a 15-bit shift
 // register with feedback. When clocked by the frequency timer, the low two bits (0 and 1) are XORed, all
 // bits are shifted right by one, and the result of the XOR is put into the now-empty high bit. If width mode
@@ -4,6 +4,7 @@ use std::rc::Rc;
 use crate::apu::channels::{Blip, Channel, LengthCounter, Register, VolumeEnvelope};
 use crate::clock::Clock;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 // A square channel's frequency timer period is set to (2048-frequency)*4. Four duty cycles are available,
 // each waveform taking 8 frequency timer clocks to cycle through:
@@ -149,6 +150,48 @@ impl Memory for SquareChannel {
     }
 }
 
+// The units of a copy share the copy's registers, not those of the original channel.
+impl Clone for SquareChannel {
+    fn clone(&self) -> SquareChannel {
+        let register = Rc::new(RefCell::new(self.register.borrow().clone()));
+        SquareChannel {
+            register: register.clone(),
+            clock: self.clock,
+            length_counter: self.length_counter.clone_with(register.clone()),
+            volume_envelope: self.volume_envelope.clone_with(register.clone()),
+            frequency_sweep: FrequencySweep {
+                register,
+                ..self.frequency_sweep
+            },
+            blip: self.blip.clone(),
+            index: self.index,
+        }
+    }
+}
+
+impl Stateful for SquareChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.register.borrow().save_state(writer);
+        self.clock.save_state(writer);
+        self.length_counter.save_state(writer);
+        self.volume_envelope.save_state(writer);
+        self.frequency_sweep.save_state(writer);
+        self.blip.save_state(writer);
+        writer.write_u8(self.index);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.register.borrow_mut().load_state(reader)?;
+        self.clock.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.volume_envelope.load_state(reader)?;
+        self.frequency_sweep.load_state(reader)?;
+        self.blip.load_state(reader)?;
+        self.index = reader.read_u8()? % 8;
+        Ok(())
+    }
+}
+
 // The first square channel has a frequency sweep unit, controlled by NR10. This has a timer, internal enabled flag,
 // and frequency shadow register. It can periodically adjust square 1's frequency up or down.
 // During a trigger event, several things occur:
@@ -238,3 +281,20 @@ impl FrequencySweep {
         }
     }
 }
+
+impl Stateful for FrequencySweep {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.clock.save_state(writer);
+        writer.write_bool(self.enable);
+        writer.write_u16(self.shadow);
+        writer.write_u16(self.new_frequency);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.clock.load_state(reader)?;
+        self.enable = reader.read_bool()?;
+        self.shadow = reader.read_u16()?;
+        self.new_frequency = reader.read_u16()?;
+        Ok(())
+    }
+}
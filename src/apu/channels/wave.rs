@@ -4,6 +4,7 @@ use std::rc::Rc;
 use crate::apu::channels::{Channel, Register};
 use crate::clock::Clock;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 use crate::apu::channels::{Blip, LengthCounter};
 
@@ -148,3 +149,39 @@ impl Memory for WaveChannel {
         }
     }
 }
+
+// The length counter of a copy shares the copy's registers, not those of the original channel.
+impl Clone for WaveChannel {
+    fn clone(&self) -> WaveChannel {
+        let register = Rc::new(RefCell::new(self.register.borrow().clone()));
+        WaveChannel {
+            register: register.clone(),
+            length_counter: self.length_counter.clone_with(register),
+            blip: self.blip.clone(),
+            clock: self.clock,
+            wave_ram: self.wave_ram,
+            wave_index: self.wave_index,
+        }
+    }
+}
+
+impl Stateful for WaveChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.register.borrow().save_state(writer);
+        self.length_counter.save_state(writer);
+        self.blip.save_state(writer);
+        self.clock.save_state(writer);
+        writer.write_bytes(&self.wave_ram);
+        writer.write_u8(self.wave_index as u8);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.register.borrow_mut().load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.blip.load_state(reader)?;
+        self.clock.load_state(reader)?;
+        reader.read_bytes_into(&mut self.wave_ram)?;
+        self.wave_index = usize::from(reader.read_u8()?) % 32;
+        Ok(())
+    }
+}
@@ -13,24 +13,85 @@
 //  - https://problemkaputt.de/pandocs.htm#soundcontroller
 
 mod channels;
+mod sink;
+mod wav;
 
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+pub use crate::apu::sink::{AudioSink, BufferSink, CpalSink};
 
+use crate::GameboyError;
 use crate::apu::channels::{
     Channel, FrameSequencer, NoiseChannel, Register, SquareChannel, WaveChannel,
 };
+use crate::apu::wav::WavWriter;
 use crate::clock::Clock;
 use crate::cpu;
 use crate::memory::Memory;
+use crate::pacer;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
-const DESIRED_CHANNELS: u16 = 2;
-const DESIRED_SAMPLE_FORMAT: cpal::SampleFormat = cpal::SampleFormat::F32;
-const DESIRED_SAMPLE_RATE: cpal::SampleRate = 44100;
+// The sample rate used while no sink is connected. The samples are still generated so that the state of the
+// channels is the same with or without a sink.
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// Channel output ranges from -15 to 15, stems are scaled up to use most of the 16 bit range while leaving
+// headroom for the overshoot of the band limited synthesis.
+const STEM_GAIN: i16 = 1536;
+const STEM_NAMES: [&str; 4] = ["square1", "square2", "wave", "noise"];
+
+/// AudioChannel identifies one of the four sound channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioChannel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+// Convert left and right samples to interleaved 16 bit PCM.
+fn to_pcm(left: &[f32], right: &[f32]) -> Vec<i16> {
+    let to_i16 = |s: f32| (s.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+    left.iter()
+        .zip(right)
+        .flat_map(|(l, r)| [to_i16(*l), to_i16(*r)])
+        .collect()
+}
+
+// A WAV recording of the mixed output, and optionally of each channel before mixing (stems).
+struct Recording {
+    mix: (PathBuf, WavWriter),
+    stems: Vec<(PathBuf, WavWriter)>,
+}
+
+impl Recording {
+    fn write_stem(&mut self, channel: usize, samples: &[i16]) {
+        if let Some((_, stem)) = self.stems.get_mut(channel) {
+            let samples: Vec<i16> = samples
+                .iter()
+                .map(|s| s.saturating_mul(STEM_GAIN))
+                .collect();
+            stem.write_samples(&samples);
+        }
+    }
+
+    fn write_mix(&mut self, left: &[f32], right: &[f32]) {
+        self.mix.1.write_samples(&to_pcm(left, right));
+    }
+
+    fn finish(self) -> Result<(), GameboyError> {
+        let mut result = Ok(());
+        for (path, writer) in std::iter::once(self.mix).chain(self.stems) {
+            if let Err(error) = writer.finish()
+                && result.is_ok()
+            {
+                result = Err(GameboyError::RecordingIo { path, error });
+            }
+        }
+        result
+    }
+}
 
 pub struct Apu {
-    pub buffer: Arc<Mutex<Vec<(f32, f32)>>>,
     register: Register,
     clock: Clock,
     frame_sequencer: FrameSequencer,
@@ -38,37 +99,145 @@ pub struct Apu {
     channel2: SquareChannel,
     channel3: WaveChannel,
     channel4: NoiseChannel,
+    sink: Option<Box<dyn AudioSink>>,
     sample_rate: u32,
-    audio_stream: cpal::Stream,
+    recording: Option<Recording>,
+    // Captured output as interleaved 16 bit PCM, taken by the Gameboy while recording video.
+    capture: Option<Vec<i16>>,
+    // Muting channels and the master volume only affect the mixed output, the emulated hardware (and
+    // stems) are not affected.
+    channels_enabled: [bool; 4],
+    volume: f32,
+    // The emulation speed relative to real hardware (0.0 means unlimited). Samples are dropped or
+    // duplicated so that audio is played at the same rate it is generated.
+    speed: f64,
+    resample_position: f64,
 }
 
 impl Apu {
-    pub fn new() -> Option<Apu> {
-        let buffer = Arc::new(Mutex::new(Vec::new()));
-        let (stream, sample_rate) = get_audio_stream(buffer.clone())?;
-        let apu = Apu {
-            buffer,
+    pub fn new() -> Apu {
+        Apu {
             register: Register::new(Channel::Control),
             clock: Clock::new(cpu::CLOCK_FREQUENCY / 512),
             frame_sequencer: FrameSequencer::new(),
-            channel1: SquareChannel::new(sample_rate, Channel::Square1),
-            channel2: SquareChannel::new(sample_rate, Channel::Square2),
-            channel3: WaveChannel::new(sample_rate),
-            channel4: NoiseChannel::new(sample_rate),
-            sample_rate,
-            audio_stream: stream,
+            channel1: SquareChannel::new(DEFAULT_SAMPLE_RATE, Channel::Square1),
+            channel2: SquareChannel::new(DEFAULT_SAMPLE_RATE, Channel::Square2),
+            channel3: WaveChannel::new(DEFAULT_SAMPLE_RATE),
+            channel4: NoiseChannel::new(DEFAULT_SAMPLE_RATE),
+            sink: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            recording: None,
+            capture: None,
+            channels_enabled: [true; 4],
+            volume: 1.0,
+            speed: 1.0,
+            resample_position: 0.0,
+        }
+    }
+
+    pub fn set_sink(&mut self, sink: Option<Box<dyn AudioSink>>) {
+        let sample_rate = sink
+            .as_ref()
+            .map_or(DEFAULT_SAMPLE_RATE, |sink| sink.sample_rate());
+        self.channel1.blip.set_sample_rate(sample_rate);
+        self.channel2.blip.set_sample_rate(sample_rate);
+        self.channel3.blip.set_sample_rate(sample_rate);
+        self.channel4.blip.set_sample_rate(sample_rate);
+        self.sample_rate = sample_rate;
+        self.resample_position = 0.0;
+        self.sink = sink;
+    }
+
+    // Start recording the output to a WAV file at `path`, stopping any previous recording. The recording
+    // uses the sample rate of the sink at the time it is started, and is not affected by the speed.
+    pub fn start_recording(&mut self, path: &Path, stems: bool) -> Result<(), GameboyError> {
+        self.stop_recording()?;
+        let create = |path: PathBuf, channels: u16| match WavWriter::create(
+            &path,
+            channels,
+            self.sample_rate,
+        ) {
+            Ok(writer) => Ok((path, writer)),
+            Err(error) => Err(GameboyError::RecordingIo { path, error }),
+        };
+        let mix = create(path.to_path_buf(), 2)?;
+        let stems = if stems {
+            STEM_NAMES
+                .iter()
+                .map(|name| create(path.with_extension(format!("{}.wav", name)), 1))
+                .collect::<Result<_, _>>()?
+        } else {
+            Vec::new()
         };
-        apu.audio_stream.play().ok()?;
-        Some(apu)
+        self.recording = Some(Recording { mix, stems });
+        Ok(())
     }
 
-    pub fn run_cycles(&mut self, cycles: u32) {
-        // If the APU is powered off, do nothing
-        if !self.register.get_power_status() {
-            return;
+    pub fn stop_recording(&mut self) -> Result<(), GameboyError> {
+        match self.recording.take() {
+            Some(recording) => recording.finish(),
+            None => Ok(()),
         }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Start (or stop) capturing the output, the samples are collected until taken using `take_capture`.
+    pub fn set_capture(&mut self, enabled: bool) {
+        self.capture = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn take_capture(&mut self) -> Vec<i16> {
+        self.capture
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
 
+    pub fn set_channel_enabled(&mut self, channel: AudioChannel, enabled: bool) {
+        self.channels_enabled[channel as usize] = enabled;
+    }
+
+    pub fn is_channel_enabled(&self, channel: AudioChannel) -> bool {
+        self.channels_enabled[channel as usize]
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn get_volume(&self) -> f32 {
+        self.volume
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = pacer::clamp_speed(speed);
+        self.resample_position = 0.0;
+    }
+
+    pub fn run_cycles(&mut self, cycles: u32) {
+        // If the APU is powered off the channels and frame sequencer do not run, but silence is still output so
+        // that the sink and recordings keep up with the emulated time.
+        let powered = self.register.get_power_status();
         for _ in 0..self.clock.run_cycles(cycles) {
+            if !powered {
+                self.channel1.blip.silence();
+                self.channel2.blip.silence();
+                self.channel3.blip.silence();
+                self.channel4.blip.silence();
+                self.channel1.blip.end_frame(self.clock.period);
+                self.channel2.blip.end_frame(self.clock.period);
+                self.channel3.blip.end_frame(self.clock.period);
+                self.channel4.blip.end_frame(self.clock.period);
+                self.mix();
+                continue;
+            }
             self.channel1.run_cycles(self.clock.period);
             self.channel2.run_cycles(self.clock.period);
             self.channel3.run_cycles(self.clock.period);
@@ -101,6 +270,18 @@ impl Apu {
         }
     }
 
+    // A channel is output to the left/right side when it is enabled in NR51 and has not been muted.
+    fn get_output_enables(&self, channel: AudioChannel) -> (bool, bool) {
+        let (left_enabled, right_enabled) = match channel {
+            AudioChannel::Square1 => self.register.get_square_enables(Channel::Square1),
+            AudioChannel::Square2 => self.register.get_square_enables(Channel::Square2),
+            AudioChannel::Wave => self.register.get_wave_enables(),
+            AudioChannel::Noise => self.register.get_noise_enables(),
+        };
+        let enabled = self.channels_enabled[channel as usize];
+        (left_enabled && enabled, right_enabled && enabled)
+    }
+
     fn mix(&mut self) {
         let sc1 = self.channel1.blip.samples_available();
         let sc2 = self.channel2.blip.samples_available();
@@ -114,9 +295,10 @@ impl Apu {
         }
         let sample_count = sc1 as usize;
 
-        let left_volume = (f32::from(self.register.get_left_volume()) / 7.0) * (1.0 / 15.0) * 0.25;
+        let left_volume =
+            (f32::from(self.register.get_left_volume()) / 7.0) * (1.0 / 15.0) * 0.25 * self.volume;
         let right_volume =
-            (f32::from(self.register.get_right_volume()) / 7.0) * (1.0 / 15.0) * 0.25;
+            (f32::from(self.register.get_right_volume()) / 7.0) * (1.0 / 15.0) * 0.25 * self.volume;
 
         let mut sum = 0;
 
@@ -126,9 +308,11 @@ impl Apu {
             let buffer = &mut [0i16; 2048];
 
             let sr1 = self.channel1.blip.read_samples(buffer);
+            if let Some(recording) = self.recording.as_mut() {
+                recording.write_stem(0, &buffer[..sr1]);
+            }
+            let (left_enabled, right_enabled) = self.get_output_enables(AudioChannel::Square1);
             for (index, value) in buffer[..sr1].iter().enumerate() {
-                let (left_enabled, right_enabled) =
-                    self.register.get_square_enables(Channel::Square1);
                 if left_enabled {
                     left_buffer[index] += f32::from(*value) * left_volume;
                 }
@@ -138,9 +322,11 @@ impl Apu {
             }
 
             let sr2 = self.channel2.blip.read_samples(buffer);
+            if let Some(recording) = self.recording.as_mut() {
+                recording.write_stem(1, &buffer[..sr2]);
+            }
+            let (left_enabled, right_enabled) = self.get_output_enables(AudioChannel::Square2);
             for (index, value) in buffer[..sr2].iter().enumerate() {
-                let (left_enabled, right_enabled) =
-                    self.register.get_square_enables(Channel::Square1);
                 if left_enabled {
                     left_buffer[index] += f32::from(*value) * left_volume;
                 }
@@ -150,8 +336,11 @@ impl Apu {
             }
 
             let sr3 = self.channel3.blip.read_samples(buffer);
+            if let Some(recording) = self.recording.as_mut() {
+                recording.write_stem(2, &buffer[..sr3]);
+            }
+            let (left_enabled, right_enabled) = self.get_output_enables(AudioChannel::Wave);
             for (index, value) in buffer[..sr3].iter().enumerate() {
-                let (left_enabled, right_enabled) = self.register.get_wave_enables();
                 if left_enabled {
                     left_buffer[index] += f32::from(*value) * left_volume;
                 }
@@ -161,8 +350,11 @@ impl Apu {
             }
 
             let sr4 = self.channel4.blip.read_samples(buffer);
+            if let Some(recording) = self.recording.as_mut() {
+                recording.write_stem(3, &buffer[..sr4]);
+            }
+            let (left_enabled, right_enabled) = self.get_output_enables(AudioChannel::Noise);
             for (index, value) in buffer[..sr4].iter().enumerate() {
-                let (left_enabled, right_enabled) = self.register.get_noise_enables();
                 if left_enabled {
                     left_buffer[index] += f32::from(*value) * left_volume;
                 }
@@ -179,6 +371,15 @@ impl Apu {
             }
 
             let samples_read = sr1;
+            if let Some(recording) = self.recording.as_mut() {
+                recording.write_mix(&left_buffer[..samples_read], &right_buffer[..samples_read]);
+            }
+            if let Some(capture) = self.capture.as_mut() {
+                capture.extend(to_pcm(
+                    &left_buffer[..samples_read],
+                    &right_buffer[..samples_read],
+                ));
+            }
             self.play(&left_buffer[..samples_read], &right_buffer[..samples_read]);
             sum += samples_read;
         }
@@ -192,14 +393,30 @@ impl Apu {
             left.len(),
             right.len()
         );
-        let mut buffer = self.buffer.lock().unwrap();
+        let Some(sink) = self.sink.as_mut() else {
+            return;
+        };
+        // At 2x speed every other sample is played, while at 0.5x speed every sample is played twice.
+        let step = if self.speed > 0.0 {
+            1.0 / self.speed
+        } else {
+            1.0
+        };
+        let mut frames = Vec::with_capacity(left.len());
         for (l, r) in left.iter().zip(right) {
-            // Ensure there is never more than 1 second of data in the buffer.
-            if buffer.len() > self.sample_rate as usize {
-                return;
+            self.resample_position += step;
+            while self.resample_position >= 1.0 {
+                frames.push((*l, *r));
+                self.resample_position -= 1.0;
             }
-            buffer.push((*l, *r));
         }
+        sink.push(&frames);
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
     }
 }
 
@@ -296,74 +513,212 @@ impl Memory for Apu {
     }
 }
 
-// Get audio stream and sample rate to use when processing audio. We pass the shared
-// buffer which will be used by the APU.
-fn get_audio_stream(
-    buffer: Arc<Mutex<Vec<(f32, f32)>>>,
-) -> Option<(cpal::Stream, cpal::SampleRate)> {
-    let device = cpal::default_host().default_output_device()?;
-    let supported_configs = device.supported_output_configs().ok()?;
-    let mut supported_config = None;
-    for c in supported_configs {
-        if c.channels() == DESIRED_CHANNELS && c.sample_format() == DESIRED_SAMPLE_FORMAT {
-            if c.min_sample_rate() <= DESIRED_SAMPLE_RATE
-                && DESIRED_SAMPLE_RATE <= c.max_sample_rate()
-            {
-                supported_config = Some(c.with_sample_rate(DESIRED_SAMPLE_RATE));
-            } else {
-                supported_config = Some(c.with_max_sample_rate());
-            }
-            break;
-        }
+// The state of the APU hardware read from a save state, restored once the complete state has been read.
+pub struct ApuState {
+    register: Register,
+    clock: Clock,
+    frame_sequencer: FrameSequencer,
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+}
+
+impl Apu {
+    // Read the state into copies of the hardware, the APU is left unchanged.
+    pub fn read_state(&self, reader: &mut StateReader) -> Result<ApuState, StateError> {
+        let mut state = ApuState {
+            register: self.register.clone(),
+            clock: self.clock,
+            frame_sequencer: self.frame_sequencer,
+            channel1: self.channel1.clone(),
+            channel2: self.channel2.clone(),
+            channel3: self.channel3.clone(),
+            channel4: self.channel4.clone(),
+        };
+        state.register.load_state(reader)?;
+        state.clock.load_state(reader)?;
+        state.frame_sequencer.load_state(reader)?;
+        state.channel1.load_state(reader)?;
+        state.channel2.load_state(reader)?;
+        state.channel3.load_state(reader)?;
+        state.channel4.load_state(reader)?;
+        Ok(state)
+    }
+
+    pub fn restore_state(&mut self, state: ApuState) {
+        self.register = state.register;
+        self.clock = state.clock;
+        self.frame_sequencer = state.frame_sequencer;
+        self.channel1 = state.channel1;
+        self.channel2 = state.channel2;
+        self.channel3 = state.channel3;
+        self.channel4 = state.channel4;
     }
-    let selected_config = supported_config?;
-    let sample_rate = selected_config.sample_rate();
-    let sample_format = selected_config.sample_format();
-    let config: cpal::StreamConfig = selected_config.into();
-    let error_function = |err| eprintln!("apu: error playing audio: {}", err);
-    let stream = match sample_format {
-        cpal::SampleFormat::F32 => device.build_output_stream(
-            &config,
-            move |data: &mut [f32], _cb: &cpal::OutputCallbackInfo| {
-                write_audio_data_to_buffer(&buffer, data)
-            },
-            error_function,
-            None,
-        ),
-        cpal::SampleFormat::U16 => device.build_output_stream(
-            &config,
-            move |data: &mut [u16], _cb: &cpal::OutputCallbackInfo| {
-                write_audio_data_to_buffer(&buffer, data)
-            },
-            error_function,
-            None,
-        ),
-        cpal::SampleFormat::I16 => device.build_output_stream(
-            &config,
-            move |data: &mut [i16], _cb: &cpal::OutputCallbackInfo| {
-                write_audio_data_to_buffer(&buffer, data)
-            },
-            error_function,
-            None,
-        ),
-        _ => panic!("apu: unsupported audio sample format (supported options: F32, U16, I16)"),
-    }
-    .ok()?;
-    Some((stream, sample_rate))
 }
 
-// Write audio buffer data to output.
-fn write_audio_data_to_buffer<T: cpal::Sample + cpal::FromSample<f32>>(
-    buffer: &Arc<Mutex<Vec<(f32, f32)>>>,
-    output: &mut [T],
-) {
-    let mut buffer = buffer.lock().unwrap();
-    // The buffer contains pairs of left and right audio samples, while the output is one long
-    // array. Calculate length of buffer to write to output based on this.
-    let length = std::cmp::min(output.len() / 2, buffer.len());
-    for (i, (left, right)) in buffer.drain(..length).enumerate() {
-        let left_index = i * 2;
-        output[left_index] = T::from_sample(left);
-        output[left_index + 1] = T::from_sample(right);
+impl Stateful for Apu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.register.save_state(writer);
+        self.clock.save_state(writer);
+        self.frame_sequencer.save_state(writer);
+        self.channel1.save_state(writer);
+        self.channel2.save_state(writer);
+        self.channel3.save_state(writer);
+        self.channel4.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let state = self.read_state(reader)?;
+        self.restore_state(state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Apu, AudioChannel, BufferSink, DEFAULT_SAMPLE_RATE, STEM_NAMES};
+    use crate::cpu::CLOCK_FREQUENCY;
+    use crate::memory::Memory;
+
+    // An APU playing square 2 with the given NR51, output to `sink`.
+    fn square2(nr51: u8, sink: &BufferSink) -> Apu {
+        let mut apu = Apu::new();
+        apu.set_sink(Some(Box::new(sink.clone())));
+        apu.set_byte(0xFF26, 0x80);
+        apu.set_byte(0xFF24, 0x77);
+        apu.set_byte(0xFF25, nr51);
+        apu.set_byte(0xFF16, 0x80);
+        apu.set_byte(0xFF17, 0xF0);
+        apu.set_byte(0xFF19, 0x87);
+        apu
+    }
+
+    // Run the APU, returning the loudest sample on the left and right side.
+    fn play(apu: &mut Apu, sink: &BufferSink) -> (f32, f32) {
+        apu.run_cycles(CLOCK_FREQUENCY / 10);
+        sink.take_frames()
+            .iter()
+            .fold((0.0, 0.0), |(left, right), (l, r)| {
+                (f32::max(left, l.abs()), f32::max(right, r.abs()))
+            })
+    }
+
+    fn play_square2(nr51: u8) -> (f32, f32) {
+        let sink = BufferSink::new(44100);
+        play(&mut square2(nr51, &sink), &sink)
+    }
+
+    #[test]
+    fn square2_uses_its_own_output_enables() {
+        let (left, right) = play_square2(0x20);
+        assert!(left > 0.0);
+        assert_eq!(right, 0.0);
+        let (left, right) = play_square2(0x02);
+        assert_eq!(left, 0.0);
+        assert!(right > 0.0);
+        // Square 1 is enabled on both sides, square 2 on neither.
+        assert_eq!(play_square2(0x11), (0.0, 0.0));
+    }
+
+    #[test]
+    fn mute_channels() {
+        let sink = BufferSink::new(44100);
+        let mut apu = square2(0x02, &sink);
+        apu.set_channel_enabled(AudioChannel::Square2, false);
+        assert!(!apu.is_channel_enabled(AudioChannel::Square2));
+        assert_eq!(play(&mut apu, &sink), (0.0, 0.0));
+        // Muting only affects the output, the channel is still on.
+        assert_eq!(apu.get_byte(0xFF26) & 0x02, 0x02);
+
+        apu.set_channel_enabled(AudioChannel::Square2, true);
+        apu.set_volume(0.0);
+        assert_eq!(play(&mut apu, &sink), (0.0, 0.0));
+        apu.set_volume(0.5);
+        let (_, half) = play(&mut apu, &sink);
+        apu.set_volume(1.0);
+        let (_, full) = play(&mut apu, &sink);
+        assert!(half > 0.0 && half < full);
+    }
+
+    #[test]
+    fn powered_off_outputs_silence() {
+        let mut apu = Apu::new();
+        apu.set_capture(true);
+        apu.set_byte(0xFF26, 0x00);
+        apu.run_cycles(CLOCK_FREQUENCY);
+        let capture = apu.take_capture();
+        // One second of interleaved stereo samples.
+        let expected = DEFAULT_SAMPLE_RATE as usize * 2;
+        assert!(
+            capture.len().abs_diff(expected) <= 2 * 100,
+            "samples: {}",
+            capture.len()
+        );
+        assert!(capture.iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn channel_copies_have_own_registers() {
+        let apu = Apu::new();
+        let mut channel = apu.channel1.clone();
+        channel.set_byte(0xFF12, 0xF0);
+        channel.set_byte(0xFF14, 0x80);
+        // The envelope of the copy is reloaded from the copy's NR12.
+        assert_eq!(channel.volume_envelope.volume, 0x0F);
+        assert_eq!(apu.channel1.register.borrow().nrx2, 0x00);
+        assert_eq!(apu.channel1.volume_envelope.volume, 0x00);
+    }
+
+    #[test]
+    fn audio_sink() {
+        let mut apu = Apu::new();
+        // The APU is emulated without a sink.
+        apu.set_byte(0xFF26, 0x80);
+        apu.set_byte(0xFF24, 0x35);
+        assert_eq!(apu.get_byte(0xFF24), 0x35);
+
+        let sink = BufferSink::new(48000);
+        apu.set_sink(Some(Box::new(sink.clone())));
+        assert_eq!(apu.get_sample_rate(), 48000);
+        apu.run_cycles(CLOCK_FREQUENCY);
+        let frames = sink.take_frames().len();
+        assert!(frames.abs_diff(48000) <= 100, "frames: {}", frames);
+
+        apu.set_speed(2.0);
+        apu.run_cycles(CLOCK_FREQUENCY);
+        let frames = sink.take_frames().len();
+        assert!(frames.abs_diff(24000) <= 100, "frames: {}", frames);
+
+        apu.set_sink(None);
+        assert_eq!(apu.get_sample_rate(), DEFAULT_SAMPLE_RATE);
+        apu.run_cycles(CLOCK_FREQUENCY / 60);
+        assert!(sink.take_frames().is_empty());
+    }
+
+    #[test]
+    fn wav_recording() {
+        let mut apu = Apu::new();
+        let path = std::env::temp_dir().join(format!("gameboyr-apu-{}.wav", std::process::id()));
+        apu.start_recording(&path, true).unwrap();
+        assert!(apu.is_recording());
+        apu.run_cycles(CLOCK_FREQUENCY / 10);
+        apu.stop_recording().unwrap();
+        assert!(!apu.is_recording());
+
+        let mix = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mix_samples = (mix.len() - 44) / 4;
+        assert!(
+            mix_samples.abs_diff(4410) <= 100,
+            "samples: {}",
+            mix_samples
+        );
+        for name in STEM_NAMES {
+            let stem_path = path.with_extension(format!("{}.wav", name));
+            let stem = std::fs::read(&stem_path).unwrap();
+            std::fs::remove_file(&stem_path).unwrap();
+            assert_eq!((stem.len() - 44) / 2, mix_samples);
+        }
     }
 }
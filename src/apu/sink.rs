@@ -0,0 +1,169 @@
+// Audio Sinks
+// The APU produces stereo frames (pairs of left and right samples in the range -1.0 to 1.0) at the sample rate
+// of the sink it is connected to. What happens to those frames (playing them, collecting them for tests, etc)
+// is up to the sink.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+const DESIRED_CHANNELS: u16 = 2;
+const DESIRED_SAMPLE_FORMAT: cpal::SampleFormat = cpal::SampleFormat::F32;
+const DESIRED_SAMPLE_RATE: cpal::SampleRate = 44100;
+// The most audio (in milliseconds) which may be waiting to be played. When the emulator runs faster than the
+// audio is played, the oldest samples are dropped to keep the latency below this.
+const MAX_LATENCY_MS: u32 = 100;
+
+/// AudioSink receives the audio produced by the Gameboy.
+pub trait AudioSink {
+    /// The number of frames per second the sink expects.
+    fn sample_rate(&self) -> u32;
+
+    /// Receive stereo frames as pairs of left and right samples (-1.0 to 1.0).
+    fn push(&mut self, frames: &[(f32, f32)]);
+}
+
+/// CpalSink plays audio using the default output device of the host.
+pub struct CpalSink {
+    buffer: Arc<Mutex<Vec<(f32, f32)>>>,
+    sample_rate: u32,
+    _stream: cpal::Stream,
+}
+
+impl CpalSink {
+    /// Create a CpalSink playing to the default output device. Returns None when
+    /// there is no suitable output device.
+    pub fn new() -> Option<CpalSink> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (stream, sample_rate) = get_audio_stream(buffer.clone())?;
+        stream.play().ok()?;
+        Some(CpalSink {
+            buffer,
+            sample_rate,
+            _stream: stream,
+        })
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push(&mut self, frames: &[(f32, f32)]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.extend_from_slice(frames);
+        let max_frames = (self.sample_rate * MAX_LATENCY_MS / 1000) as usize;
+        if buffer.len() > max_frames {
+            let excess = buffer.len() - max_frames;
+            buffer.drain(..excess);
+        }
+    }
+}
+
+/// BufferSink collects all audio in memory, which is useful for tests and tools.
+/// Clones of a BufferSink share the same buffer.
+#[derive(Clone)]
+pub struct BufferSink {
+    frames: Rc<RefCell<Vec<(f32, f32)>>>,
+    sample_rate: u32,
+}
+
+impl BufferSink {
+    /// Create a BufferSink collecting audio at the given sample rate.
+    pub fn new(sample_rate: u32) -> BufferSink {
+        BufferSink {
+            frames: Rc::new(RefCell::new(Vec::new())),
+            sample_rate,
+        }
+    }
+
+    /// Take all frames collected so far, leaving the buffer empty.
+    pub fn take_frames(&self) -> Vec<(f32, f32)> {
+        self.frames.take()
+    }
+}
+
+impl AudioSink for BufferSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push(&mut self, frames: &[(f32, f32)]) {
+        self.frames.borrow_mut().extend_from_slice(frames);
+    }
+}
+
+// Get audio stream and sample rate to use when processing audio. We pass the shared
+// buffer which will be used by the APU.
+fn get_audio_stream(
+    buffer: Arc<Mutex<Vec<(f32, f32)>>>,
+) -> Option<(cpal::Stream, cpal::SampleRate)> {
+    let device = cpal::default_host().default_output_device()?;
+    let supported_configs = device.supported_output_configs().ok()?;
+    let mut supported_config = None;
+    for c in supported_configs {
+        if c.channels() == DESIRED_CHANNELS && c.sample_format() == DESIRED_SAMPLE_FORMAT {
+            if c.min_sample_rate() <= DESIRED_SAMPLE_RATE
+                && DESIRED_SAMPLE_RATE <= c.max_sample_rate()
+            {
+                supported_config = Some(c.with_sample_rate(DESIRED_SAMPLE_RATE));
+            } else {
+                supported_config = Some(c.with_max_sample_rate());
+            }
+            break;
+        }
+    }
+    let selected_config = supported_config?;
+    let sample_rate = selected_config.sample_rate();
+    let sample_format = selected_config.sample_format();
+    let config: cpal::StreamConfig = selected_config.into();
+    let error_function = |err| eprintln!("apu: error playing audio: {}", err);
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _cb: &cpal::OutputCallbackInfo| {
+                write_audio_data_to_buffer(&buffer, data)
+            },
+            error_function,
+            None,
+        ),
+        cpal::SampleFormat::U16 => device.build_output_stream(
+            &config,
+            move |data: &mut [u16], _cb: &cpal::OutputCallbackInfo| {
+                write_audio_data_to_buffer(&buffer, data)
+            },
+            error_function,
+            None,
+        ),
+        cpal::SampleFormat::I16 => device.build_output_stream(
+            &config,
+            move |data: &mut [i16], _cb: &cpal::OutputCallbackInfo| {
+                write_audio_data_to_buffer(&buffer, data)
+            },
+            error_function,
+            None,
+        ),
+        _ => panic!("apu: unsupported audio sample format (supported options: F32, U16, I16)"),
+    }
+    .ok()?;
+    Some((stream, sample_rate))
+}
+
+// Write audio buffer data to output.
+fn write_audio_data_to_buffer<T: cpal::Sample + cpal::FromSample<f32>>(
+    buffer: &Arc<Mutex<Vec<(f32, f32)>>>,
+    output: &mut [T],
+) {
+    let mut buffer = buffer.lock().unwrap();
+    // The buffer contains pairs of left and right audio samples, while the output is one long
+    // array. Calculate length of buffer to write to output based on this.
+    let length = std::cmp::min(output.len() / 2, buffer.len());
+    for (i, (left, right)) in buffer.drain(..length).enumerate() {
+        let left_index = i * 2;
+        output[left_index] = T::from_sample(left);
+        output[left_index + 1] = T::from_sample(right);
+    }
+}
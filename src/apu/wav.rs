@@ -0,0 +1,97 @@
+// WAV
+// Audio is recorded as 16 bit PCM in a RIFF WAVE file:
+//   "RIFF" chunk (size of the rest of the file), format "WAVE"
+//     "fmt " chunk: format (1 = PCM), channels, sample rate, byte rate, block align, bits per sample
+//     "data" chunk: interleaved little endian samples
+// The sizes are not known until recording stops, so they are written as zero and patched by `finish`.
+//
+// References:
+//  - http://soundfile.sapp.org/doc/WaveFormat/
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const BITS_PER_SAMPLE: u16 = 16;
+
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    data_size: u32,
+    // The first error while writing, writing stops after an error and it is returned by `finish`.
+    error: Option<io::Error>,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, channels: u16, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+            error: None,
+        })
+    }
+
+    // Write interleaved samples (left, right, left, ... for stereo).
+    pub fn write_samples(&mut self, samples: &[i16]) {
+        if self.error.is_some() {
+            return;
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        match self.writer.write_all(&bytes) {
+            Ok(()) => self.data_size = self.data_size.saturating_add(bytes.len() as u32),
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    // Patch the chunk sizes and flush the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&self.data_size.saturating_add(36).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WavWriter;
+
+    #[test]
+    fn write_wav() {
+        let path = std::env::temp_dir().join(format!("gameboyr-test-{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path, 2, 44100).unwrap();
+        wav.write_samples(&[1, -1, 0x1234, 0x5678]);
+        wav.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(data[4..8], 44u32.to_le_bytes());
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(data[22..24], 2u16.to_le_bytes());
+        assert_eq!(data[24..28], 44100u32.to_le_bytes());
+        assert_eq!(data[28..32], (44100u32 * 4).to_le_bytes());
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(data[40..44], 8u32.to_le_bytes());
+        assert_eq!(data[44..], [0x01, 0x00, 0xFF, 0xFF, 0x34, 0x12, 0x78, 0x56]);
+    }
+}
@@ -0,0 +1,313 @@
+// AVI
+// Video is recorded as an uncompressed AVI, with 24 bit RGB frames and 16 bit stereo PCM audio:
+//   RIFF 'AVI '
+//     LIST 'hdrl'
+//       'avih'  Main header (frame time, number of frames, streams, dimensions)
+//       LIST 'strl'
+//         'strh'  Video stream header ('vids', frame rate as rate / scale, number of frames)
+//         'strf'  BITMAPINFOHEADER (24 bits per pixel, no compression)
+//       LIST 'strl'
+//         'strh'  Audio stream header ('auds', sample rate, number of samples)
+//         'strf'  WAVEFORMATEX (PCM, 2 channels, 16 bits per sample)
+//     LIST 'movi'
+//       '00db'  Frame, rows from bottom to top with pixels stored as BGR
+//       '01wb'  Audio played during the frame
+//       ...
+//     'idx1'  Index with the position of every chunk in 'movi'
+// All values are little endian. The number of frames and samples and the chunk sizes are not known until
+// recording stops, so they are written as zero and patched by `finish`. The RIFF size is 32 bits, limiting a
+// recording to 4GB (about 15 minutes).
+//
+// References:
+//  - https://learn.microsoft.com/en-us/windows/win32/directshow/avi-riff-file-reference
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::cpu::CLOCK_FREQUENCY;
+use crate::ppu::FRAME_DOTS;
+
+const AVIF_HASINDEX: u32 = 0x0000_0010;
+const AVIIF_KEYFRAME: u32 = 0x0000_0010;
+const BYTES_PER_AUDIO_SAMPLE: u32 = 4;
+
+// Positions of the values patched by `finish`.
+const RIFF_SIZE_POSITION: u64 = 4;
+const TOTAL_FRAMES_POSITION: u64 = 48;
+const VIDEO_LENGTH_POSITION: u64 = 140;
+const AUDIO_LENGTH_POSITION: u64 = 264;
+
+pub struct AviWriter {
+    writer: BufWriter<File>,
+    width: usize,
+    height: usize,
+    // Position of the 'movi' list size, the index offsets are relative to the position after it.
+    movi_position: u64,
+    position: u64,
+    frames: u32,
+    samples: u32,
+    index: Vec<u8>,
+    // The first error while writing, writing stops after an error and it is returned by `finish`.
+    error: Option<io::Error>,
+}
+
+impl AviWriter {
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        sample_rate: u32,
+    ) -> io::Result<AviWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let frame_size = (width * height * 3) as u32;
+        // The Gameboy runs at CLOCK_FREQUENCY / FRAME_DOTS (~59.73) frames per second.
+        let rate = CLOCK_FREQUENCY;
+        let scale = FRAME_DOTS;
+        let audio_bytes_per_second = sample_rate * BYTES_PER_AUDIO_SAMPLE;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"AVI ");
+        header.extend_from_slice(b"LIST");
+        header.extend_from_slice(&294u32.to_le_bytes());
+        header.extend_from_slice(b"hdrl");
+
+        header.extend_from_slice(b"avih");
+        header.extend_from_slice(&56u32.to_le_bytes());
+        let micro_seconds_per_frame = (u64::from(scale) * 1_000_000 / u64::from(rate)) as u32;
+        header.extend_from_slice(&micro_seconds_per_frame.to_le_bytes());
+        let bytes_per_second = (u64::from(frame_size) * u64::from(rate) / u64::from(scale)) as u32
+            + audio_bytes_per_second;
+        header.extend_from_slice(&bytes_per_second.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // Padding granularity
+        header.extend_from_slice(&AVIF_HASINDEX.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // Total frames
+        header.extend_from_slice(&0u32.to_le_bytes()); // Initial frames
+        header.extend_from_slice(&2u32.to_le_bytes()); // Streams
+        header.extend_from_slice(&frame_size.to_le_bytes()); // Suggested buffer size
+        header.extend_from_slice(&(width as u32).to_le_bytes());
+        header.extend_from_slice(&(height as u32).to_le_bytes());
+        header.extend_from_slice(&[0x00; 16]); // Reserved
+
+        header.extend_from_slice(b"LIST");
+        header.extend_from_slice(&116u32.to_le_bytes());
+        header.extend_from_slice(b"strl");
+        header.extend_from_slice(b"strh");
+        header.extend_from_slice(&56u32.to_le_bytes());
+        header.extend_from_slice(b"vids");
+        header.extend_from_slice(b"DIB ");
+        header.extend_from_slice(&0u32.to_le_bytes()); // Flags
+        header.extend_from_slice(&0u32.to_le_bytes()); // Priority and language
+        header.extend_from_slice(&0u32.to_le_bytes()); // Initial frames
+        header.extend_from_slice(&scale.to_le_bytes());
+        header.extend_from_slice(&rate.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // Start
+        header.extend_from_slice(&0u32.to_le_bytes()); // Length
+        header.extend_from_slice(&frame_size.to_le_bytes()); // Suggested buffer size
+        header.extend_from_slice(&u32::MAX.to_le_bytes()); // Quality (default)
+        header.extend_from_slice(&0u32.to_le_bytes()); // Sample size (varies)
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&(width as u16).to_le_bytes());
+        header.extend_from_slice(&(height as u16).to_le_bytes());
+        header.extend_from_slice(b"strf");
+        header.extend_from_slice(&40u32.to_le_bytes());
+        header.extend_from_slice(&40u32.to_le_bytes());
+        header.extend_from_slice(&(width as u32).to_le_bytes());
+        header.extend_from_slice(&(height as u32).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // Planes
+        header.extend_from_slice(&24u16.to_le_bytes()); // Bits per pixel
+        header.extend_from_slice(&0u32.to_le_bytes()); // Compression (none)
+        header.extend_from_slice(&frame_size.to_le_bytes());
+        header.extend_from_slice(&[0x00; 16]); // Resolution and colors
+
+        header.extend_from_slice(b"LIST");
+        header.extend_from_slice(&94u32.to_le_bytes());
+        header.extend_from_slice(b"strl");
+        header.extend_from_slice(b"strh");
+        header.extend_from_slice(&56u32.to_le_bytes());
+        header.extend_from_slice(b"auds");
+        header.extend_from_slice(&0u32.to_le_bytes()); // Handler
+        header.extend_from_slice(&0u32.to_le_bytes()); // Flags
+        header.extend_from_slice(&0u32.to_le_bytes()); // Priority and language
+        header.extend_from_slice(&0u32.to_le_bytes()); // Initial frames
+        header.extend_from_slice(&1u32.to_le_bytes()); // Scale
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // Start
+        header.extend_from_slice(&0u32.to_le_bytes()); // Length
+        header.extend_from_slice(&audio_bytes_per_second.to_le_bytes()); // Suggested buffer size
+        header.extend_from_slice(&u32::MAX.to_le_bytes()); // Quality (default)
+        header.extend_from_slice(&BYTES_PER_AUDIO_SAMPLE.to_le_bytes());
+        header.extend_from_slice(&[0x00; 8]); // Frame
+        header.extend_from_slice(b"strf");
+        header.extend_from_slice(&18u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&2u16.to_le_bytes()); // Channels
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&audio_bytes_per_second.to_le_bytes());
+        header.extend_from_slice(&(BYTES_PER_AUDIO_SAMPLE as u16).to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+        header.extend_from_slice(&0u16.to_le_bytes()); // Extra size
+
+        header.extend_from_slice(b"LIST");
+        let movi_position = header.len() as u64;
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"movi");
+        writer.write_all(&header)?;
+
+        Ok(AviWriter {
+            writer,
+            width,
+            height,
+            movi_position,
+            position: header.len() as u64,
+            frames: 0,
+            samples: 0,
+            index: Vec::new(),
+            error: None,
+        })
+    }
+
+    // Write a frame of RGB pixels (3 bytes per pixel, rows from top to bottom).
+    pub fn write_frame(&mut self, rgb: &[u8]) {
+        let row_size = self.width * 3;
+        assert_eq!(
+            rgb.len(),
+            row_size * self.height,
+            "avi: frame does not match dimensions (width: {:?}, height: {:?})",
+            self.width,
+            self.height
+        );
+        let mut bgr = Vec::with_capacity(rgb.len());
+        for row in rgb.chunks(row_size).rev() {
+            for pixel in row.chunks(3) {
+                bgr.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+        if self.write_chunk(b"00db", &bgr) {
+            self.frames += 1;
+        }
+    }
+
+    // Write interleaved stereo samples (left, right, left, ...).
+    pub fn write_audio(&mut self, samples: &[i16]) {
+        if samples.is_empty() {
+            return;
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        if self.write_chunk(b"01wb", &bytes) {
+            self.samples += (samples.len() / 2) as u32;
+        }
+    }
+
+    fn write_chunk(&mut self, id: &[u8; 4], data: &[u8]) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+        // Leave room for the index
+        let size = 8 + data.len() as u64 + data.len() as u64 % 2;
+        let index_size = 8 + self.index.len() as u64 + 16;
+        if self.position + size + index_size > u64::from(u32::MAX) {
+            self.error = Some(io::Error::other("avi: recording exceeds the 4GB limit"));
+            return false;
+        }
+        let offset = (self.position - self.movi_position - 4) as u32;
+        let result = self
+            .writer
+            .write_all(id)
+            .and_then(|_| self.writer.write_all(&(data.len() as u32).to_le_bytes()))
+            .and_then(|_| self.writer.write_all(data))
+            .and_then(|_| {
+                if !data.len().is_multiple_of(2) {
+                    self.writer.write_all(&[0x00])
+                } else {
+                    Ok(())
+                }
+            });
+        if let Err(err) = result {
+            self.error = Some(err);
+            return false;
+        }
+        self.position += size;
+        self.index.extend_from_slice(id);
+        self.index.extend_from_slice(&AVIIF_KEYFRAME.to_le_bytes());
+        self.index.extend_from_slice(&offset.to_le_bytes());
+        self.index
+            .extend_from_slice(&(data.len() as u32).to_le_bytes());
+        true
+    }
+
+    // Write the index, patch the sizes and lengths, and flush the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let movi_size = (self.position - self.movi_position - 4) as u32;
+        self.writer.write_all(b"idx1")?;
+        self.writer
+            .write_all(&(self.index.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&self.index)?;
+        let riff_size = (self.position + 8 + self.index.len() as u64 - 8) as u32;
+
+        let patches = [
+            (RIFF_SIZE_POSITION, riff_size),
+            (TOTAL_FRAMES_POSITION, self.frames),
+            (VIDEO_LENGTH_POSITION, self.frames),
+            (AUDIO_LENGTH_POSITION, self.samples),
+            (self.movi_position, movi_size),
+        ];
+        for (position, value) in patches {
+            self.writer.seek(SeekFrom::Start(position))?;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AviWriter;
+
+    fn read_u32(data: &[u8], position: usize) -> u32 {
+        u32::from_le_bytes(data[position..position + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn write_avi() {
+        let path = std::env::temp_dir().join(format!("gameboyr-test-{}.avi", std::process::id()));
+        let mut avi = AviWriter::create(&path, 2, 2, 44100).unwrap();
+        // Top row red and green, bottom row blue and white
+        avi.write_frame(&[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+        avi.write_audio(&[1, 2, 3, 4]);
+        avi.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+        assert_eq!(&data[24..28], b"avih");
+        assert_eq!(read_u32(&data, 48), 1);
+        assert_eq!(&data[108..112], b"vids");
+        assert_eq!(read_u32(&data, 140), 1);
+        assert_eq!(&data[232..236], b"auds");
+        assert_eq!(read_u32(&data, 264), 2);
+        assert_eq!(&data[314..318], b"LIST");
+        assert_eq!(read_u32(&data, 318), 4 + 20 + 16);
+        assert_eq!(&data[322..326], b"movi");
+        // Bottom row first, stored as BGR
+        assert_eq!(&data[326..334], b"00db\x0C\x00\x00\x00");
+        assert_eq!(
+            data[334..346],
+            [255, 0, 0, 255, 255, 255, 0, 0, 255, 0, 255, 0]
+        );
+        assert_eq!(&data[346..354], b"01wb\x08\x00\x00\x00");
+        assert_eq!(&data[362..366], b"idx1");
+        assert_eq!(read_u32(&data, 366), 32);
+        // Offsets are relative to 'movi'
+        assert_eq!(read_u32(&data, 378), 4);
+        assert_eq!(read_u32(&data, 394), 24);
+    }
+}
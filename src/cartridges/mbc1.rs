@@ -47,17 +47,22 @@
 // used during Mode 0, and only ROM Banks 00-1Fh can be used during Mode 1.
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::cartridges::{Cartridge, Stable};
+use crate::cartridges::{Cartridge, Stable, check_ram_bank, check_rom_bank};
+use crate::error::GameboyError;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
+#[derive(Clone)]
 pub enum BankMode {
     Rom,
     Ram,
 }
 
+#[derive(Clone)]
 pub struct Mbc1 {
-    rom: Vec<u8>,
+    rom: Arc<[u8]>,
     ram: Vec<u8>,
     bank_mode: BankMode,
     bank: u8,
@@ -68,7 +73,7 @@ pub struct Mbc1 {
 impl Mbc1 {
     pub fn new(rom: Vec<u8>, ram: Vec<u8>, save_path: impl AsRef<Path>) -> Mbc1 {
         Mbc1 {
-            rom,
+            rom: Arc::from(rom),
             ram,
             // The MBC1 defaults to 16Mbit ROM/8KByte RAM mode on power up.
             bank_mode: BankMode::Rom,
@@ -157,9 +162,55 @@ impl Memory for Mbc1 {
 }
 
 impl Stable for Mbc1 {
-    fn save(&self) {
-        self.save_to_file(self.save_path.clone(), &self.ram);
+    fn save(&self) -> Result<(), GameboyError> {
+        self.save_to_file(self.save_path.clone(), &self.ram)
     }
 }
 
-impl Cartridge for Mbc1 {}
+impl Stateful for Mbc1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_bool(matches!(self.bank_mode, BankMode::Ram));
+        writer.write_u8(self.bank);
+        writer.write_bool(self.ram_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes_into(&mut self.ram)?;
+        self.bank_mode = if reader.read_bool()? {
+            BankMode::Ram
+        } else {
+            BankMode::Rom
+        };
+        self.bank = reader.read_u8()?;
+        self.ram_enabled = reader.read_bool()?;
+        check_rom_bank(&self.rom, self.get_rom_bank())?;
+        check_ram_bank(&self.ram, self.get_ram_bank())
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn get_rom_bank(&self) -> usize {
+        Mbc1::get_rom_bank(self)
+    }
+
+    fn get_rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn get_rom_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.rom)
+    }
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn clone_box(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+}
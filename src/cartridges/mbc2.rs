@@ -24,12 +24,16 @@
 // range to use for MBC2 rom bank selection is 2100-21FF.
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::cartridges::{Cartridge, Stable};
+use crate::cartridges::{Cartridge, Stable, check_rom_bank};
+use crate::error::GameboyError;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
+#[derive(Clone)]
 pub struct Mbc2 {
-    rom: Vec<u8>,
+    rom: Arc<[u8]>,
     ram: Vec<u8>,
     rom_bank: usize,
     ram_enabled: bool,
@@ -39,7 +43,7 @@ pub struct Mbc2 {
 impl Mbc2 {
     pub fn new(rom: Vec<u8>, ram: Vec<u8>, save_path: impl AsRef<Path>) -> Mbc2 {
         Mbc2 {
-            rom,
+            rom: Arc::from(rom),
             ram,
             rom_bank: 1,
             ram_enabled: false,
@@ -86,9 +90,48 @@ impl Memory for Mbc2 {
 }
 
 impl Stable for Mbc2 {
-    fn save(&self) {
-        self.save_to_file(self.save_path.clone(), &self.ram);
+    fn save(&self) -> Result<(), GameboyError> {
+        self.save_to_file(self.save_path.clone(), &self.ram)
     }
 }
 
-impl Cartridge for Mbc2 {}
+impl Stateful for Mbc2 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_u16(self.rom_bank as u16);
+        writer.write_bool(self.ram_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes_into(&mut self.ram)?;
+        self.rom_bank = usize::from(reader.read_u16()?);
+        self.ram_enabled = reader.read_bool()?;
+        check_rom_bank(&self.rom, self.rom_bank)
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn get_rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn get_rom_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.rom)
+    }
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn clone_box(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+}
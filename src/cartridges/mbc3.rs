@@ -55,12 +55,17 @@
 // When accessing the RTC Registers it is recommended to execute a 4ms delay (4 Cycles in Normal Speed Mode) between the
 // separate accesses.
 
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::SystemTime;
 
-use crate::cartridges::{Cartridge, Stable};
+use crate::cartridges::{Cartridge, Stable, check_ram_bank, check_rom_bank};
+use crate::error::GameboyError;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
+#[derive(Clone)]
 struct RealTimeClock {
     s: u8,
     m: u8,
@@ -72,19 +77,34 @@ struct RealTimeClock {
 }
 
 impl RealTimeClock {
-    fn new(save_path: impl AsRef<Path>) -> RealTimeClock {
+    fn new(save_path: impl AsRef<Path>) -> Result<RealTimeClock, GameboyError> {
+        let save_error = |error| GameboyError::SaveIo {
+            path: PathBuf::from(save_path.as_ref()),
+            error,
+        };
         let zero = match std::fs::read(save_path.as_ref()) {
             Ok(ok) => {
-                let mut b: [u8; 8] = Default::default();
-                b.copy_from_slice(&ok);
+                // The rtc save file only contains the zero time as a big endian u64.
+                let b: [u8; 8] = ok.as_slice().try_into().map_err(|_| {
+                    save_error(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "rtc save file must be 8 bytes",
+                    ))
+                })?;
                 u64::from_be_bytes(b)
             }
-            Err(_) => SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            Err(err)
+                if err.kind() == io::ErrorKind::NotFound
+                    || save_path.as_ref().as_os_str().is_empty() =>
+            {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            }
+            Err(err) => return Err(save_error(err)),
         };
-        RealTimeClock {
+        Ok(RealTimeClock {
             zero,
             s: 0,
             m: 0,
@@ -92,7 +112,7 @@ impl RealTimeClock {
             dl: 0,
             dh: 0,
             save_path: PathBuf::from(save_path.as_ref()),
-        }
+        })
     }
 
     fn tic(&mut self) {
@@ -161,13 +181,35 @@ impl Memory for RealTimeClock {
 }
 
 impl Stable for RealTimeClock {
-    fn save(&self) {
-        self.save_to_file(self.save_path.clone(), &self.zero.to_be_bytes());
+    fn save(&self) -> Result<(), GameboyError> {
+        self.save_to_file(self.save_path.clone(), &self.zero.to_be_bytes())
+    }
+}
+
+impl Stateful for RealTimeClock {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.s);
+        writer.write_u8(self.m);
+        writer.write_u8(self.h);
+        writer.write_u8(self.dl);
+        writer.write_u8(self.dh);
+        writer.write_u64(self.zero);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.s = reader.read_u8()?;
+        self.m = reader.read_u8()?;
+        self.h = reader.read_u8()?;
+        self.dl = reader.read_u8()?;
+        self.dh = reader.read_u8()?;
+        self.zero = reader.read_u64()?;
+        Ok(())
     }
 }
 
+#[derive(Clone)]
 pub struct Mbc3 {
-    rom: Vec<u8>,
+    rom: Arc<[u8]>,
     ram: Vec<u8>,
     rtc: RealTimeClock,
     rom_bank: usize,
@@ -182,16 +224,16 @@ impl Mbc3 {
         ram: Vec<u8>,
         save_path: impl AsRef<Path>,
         rtc_save_path: impl AsRef<Path>,
-    ) -> Mbc3 {
-        Mbc3 {
-            rom,
+    ) -> Result<Mbc3, GameboyError> {
+        Ok(Mbc3 {
+            rom: Arc::from(rom),
             ram,
-            rtc: RealTimeClock::new(rtc_save_path),
+            rtc: RealTimeClock::new(rtc_save_path)?,
             rom_bank: 1,
             ram_bank: 0,
             ram_enable: false,
             save_path: PathBuf::from(save_path.as_ref()),
-        }
+        })
     }
 }
 
@@ -263,10 +305,59 @@ impl Memory for Mbc3 {
 }
 
 impl Stable for Mbc3 {
-    fn save(&self) {
-        self.rtc.save();
-        self.save_to_file(self.save_path.clone(), &self.ram);
+    fn save(&self) -> Result<(), GameboyError> {
+        self.rtc.save()?;
+        self.save_to_file(self.save_path.clone(), &self.ram)
     }
 }
 
-impl Cartridge for Mbc3 {}
+impl Stateful for Mbc3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        self.rtc.save_state(writer);
+        writer.write_u16(self.rom_bank as u16);
+        writer.write_u16(self.ram_bank as u16);
+        writer.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes_into(&mut self.ram)?;
+        self.rtc.load_state(reader)?;
+        self.rom_bank = usize::from(reader.read_u16()?);
+        self.ram_bank = usize::from(reader.read_u16()?);
+        self.ram_enable = reader.read_bool()?;
+        check_rom_bank(&self.rom, self.rom_bank)?;
+        match self.ram_bank {
+            0x00..=0x03 => check_ram_bank(&self.ram, self.ram_bank),
+            // RTC Register 08-0C
+            0x08..=0x0C => Ok(()),
+            _ => Err(StateError::InvalidFormat),
+        }
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn get_rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn get_rom_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.rom)
+    }
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn clone_box(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+}
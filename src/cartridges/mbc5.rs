@@ -25,12 +25,16 @@
 // RAM Bank (if any) into memory at A000-BFFF.
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::cartridges::{Cartridge, Stable};
+use crate::cartridges::{Cartridge, Stable, check_ram_bank, check_rom_bank};
+use crate::error::GameboyError;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
+#[derive(Clone)]
 pub struct Mbc5 {
-    rom: Vec<u8>,
+    rom: Arc<[u8]>,
     ram: Vec<u8>,
     rom_bank: usize,
     ram_bank: usize,
@@ -41,7 +45,7 @@ pub struct Mbc5 {
 impl Mbc5 {
     pub fn new(rom: Vec<u8>, ram: Vec<u8>, save_path: impl AsRef<Path>) -> Mbc5 {
         Mbc5 {
-            rom,
+            rom: Arc::from(rom),
             ram,
             rom_bank: 1,
             ram_bank: 0,
@@ -96,9 +100,51 @@ impl Memory for Mbc5 {
 }
 
 impl Stable for Mbc5 {
-    fn save(&self) {
-        self.save_to_file(self.save_path.clone(), &self.ram);
+    fn save(&self) -> Result<(), GameboyError> {
+        self.save_to_file(self.save_path.clone(), &self.ram)
     }
 }
 
-impl Cartridge for Mbc5 {}
+impl Stateful for Mbc5 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_u16(self.rom_bank as u16);
+        writer.write_u16(self.ram_bank as u16);
+        writer.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes_into(&mut self.ram)?;
+        self.rom_bank = usize::from(reader.read_u16()?);
+        self.ram_bank = usize::from(reader.read_u16()?);
+        self.ram_enable = reader.read_bool()?;
+        check_rom_bank(&self.rom, self.rom_bank)?;
+        check_ram_bank(&self.ram, self.ram_bank)
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn get_rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn get_rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn get_rom_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.rom)
+    }
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn clone_box(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+}
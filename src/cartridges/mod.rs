@@ -6,12 +6,15 @@ mod rom;
 
 use std::path::{Path, PathBuf};
 
+use crate::LoadOptions;
 use crate::cartridges::mbc1::Mbc1;
 use crate::cartridges::mbc2::Mbc2;
 use crate::cartridges::mbc3::Mbc3;
 use crate::cartridges::mbc5::Mbc5;
 use crate::cartridges::rom::RomOnly;
+use crate::error::GameboyError;
 use crate::memory::Memory;
+use crate::state::{StateError, Stateful};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CartridgeMode {
@@ -20,19 +23,15 @@ pub enum CartridgeMode {
 }
 
 pub trait Stable {
-    fn save(&self) {}
+    fn save(&self) -> Result<(), GameboyError> {
+        Ok(())
+    }
 
-    fn save_to_file(&self, path: PathBuf, contents: &[u8]) {
+    fn save_to_file(&self, path: PathBuf, contents: &[u8]) -> Result<(), GameboyError> {
         if path.as_os_str().is_empty() {
-            return;
+            return Ok(());
         }
-        std::fs::write(&path, contents).unwrap_or_else(|err| {
-            eprintln!(
-                "cartridge: failed to write save file '{}': {}",
-                path.display(),
-                err
-            );
-        });
+        std::fs::write(&path, contents).map_err(|error| GameboyError::SaveIo { path, error })
     }
 }
 
@@ -47,7 +46,7 @@ const NINTENDO_LOGO: [u8; 48] = [
     0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
 ];
 
-pub trait Cartridge: Memory + Stable + Send {
+pub trait Cartridge: Memory + Stable + Stateful + Send {
     // 0134-0143 - Title
     // Title of the game in UPPER CASE ASCII. If it is less than 16 characters then the
     // remaining bytes are filled with 00's. When inventing the CGB, Nintendo has reduced
@@ -87,12 +86,13 @@ pub trait Cartridge: Memory + Stable + Send {
     // The Gameboy's boot procedure verifies the content of this bitmap (after it has displayed it), and
     // LOCKS ITSELF UP if these bytes are incorrect. A CGB verifies only the first 18h bytes of the bitmap,
     // but others (for example a pocket gameboy) verify all 30h bytes.
-    fn verify_nintendo_logo(&self) {
+    fn verify_nintendo_logo(&self) -> Result<(), GameboyError> {
         for addr in 0x00..48 {
             if self.get_byte(0x0104 + addr as u16) != NINTENDO_LOGO[addr as usize] {
-                panic!("cartridge: could not validate nintendo logo")
+                return Err(GameboyError::InvalidNintendoLogo);
             }
         }
+        Ok(())
     }
 
     // 014D - Header Checksum
@@ -100,14 +100,15 @@ pub trait Cartridge: Memory + Stable + Send {
     //  x=0:FOR i=0134h TO 014Ch:x=x-MEM[i]-1:NEXT
     // The lower 8 bits of the result must be the same than the value in this entry. The GAME WON'T WORK if
     // this checksum is incorrect.
-    fn verify_header_checksum(&self) {
+    fn verify_header_checksum(&self) -> Result<(), GameboyError> {
         let mut checksum: u8 = 0;
         for addr in 0x0134..=0x014C {
             checksum = checksum.wrapping_sub(self.get_byte(addr)).wrapping_sub(1);
         }
         if self.get_byte(0x014D) != checksum {
-            panic!("cartridge: could not validate header checksum")
+            return Err(GameboyError::InvalidHeaderChecksum);
         }
+        Ok(())
     }
 
     // 0143 - CGB Flag
@@ -126,6 +127,45 @@ pub trait Cartridge: Memory + Stable + Send {
             _ => CartridgeMode::Gb,
         }
     }
+
+    // The ROM bank mapped to 4000-7FFF, 0000-3FFF always maps bank 0.
+    fn get_rom_bank(&self) -> usize {
+        1
+    }
+
+    // The complete ROM and RAM, regardless of the selected banks.
+    fn get_rom(&self) -> &[u8];
+
+    fn get_rom_mut(&mut self) -> &mut [u8];
+
+    fn get_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // A copy of the cartridge, including the state of its registers and RAM. The ROM is shared with the copy until
+    // either of them writes to it.
+    fn clone_box(&self) -> Box<dyn Cartridge>;
+}
+
+// The banks restored from a save state must be within the cartridge, as reading a bank outside of it would
+// index out of bounds. RAM bank 0 is always accepted, it is selected even when there is no (or less than
+// 8KByte of) RAM.
+pub fn check_rom_bank(rom: &[u8], bank: usize) -> Result<(), StateError> {
+    if bank * 0x4000 >= rom.len() {
+        return Err(StateError::InvalidFormat);
+    }
+    Ok(())
+}
+
+pub fn check_ram_bank(ram: &[u8], bank: usize) -> Result<(), StateError> {
+    if bank != 0 && bank * 0x2000 >= ram.len() {
+        return Err(StateError::InvalidFormat);
+    }
+    Ok(())
 }
 
 // Specifies which Memory Bank Controller (if any) is used in the cartridge, and
@@ -146,14 +186,30 @@ pub trait Cartridge: Memory + Stable + Send {
 //  11h  MBC3                     FDh  BANDAI TAMA5
 //  12h  MBC3+RAM                 FEh  HuC3
 //  13h  MBC3+RAM+BATTERY         FFh  HuC1+RAM+BATTERY
-pub fn new(rom: Vec<u8>, path: impl AsRef<Path>, skip_checks: bool) -> Box<dyn Cartridge> {
+pub fn new(
+    rom: Vec<u8>,
+    path: impl AsRef<Path>,
+    options: &LoadOptions,
+) -> Result<Box<dyn Cartridge>, GameboyError> {
     // An internal information area is located at 0100-014F in each cartridge.
     if rom.len() < 0x150 {
-        panic!("cartridge: invalid rom size")
+        return Err(GameboyError::TruncatedRom {
+            expected: 0x150,
+            actual: rom.len(),
+        });
     }
-    let rom_max_size = get_rom_size(rom.as_ref());
+    let rom_max_size = get_rom_size(rom.as_ref())?;
     if rom.len() > rom_max_size {
-        panic!("cartridge: rom size more than max (max: {})", rom_max_size);
+        return Err(GameboyError::RomTooLarge {
+            expected: rom_max_size,
+            actual: rom.len(),
+        });
+    }
+    if rom.len() < rom_max_size {
+        return Err(GameboyError::TruncatedRom {
+            expected: rom_max_size,
+            actual: rom.len(),
+        });
     }
     // In each cartridge, the required (or preferred) MBC type should
     // be specified in the byte at 0147h of the ROM, as described in
@@ -162,13 +218,13 @@ pub fn new(rom: Vec<u8>, path: impl AsRef<Path>, skip_checks: bool) -> Box<dyn C
         0x00 => Box::new(RomOnly::new(rom)),
         0x01 => Box::new(Mbc1::new(rom, vec![], "")),
         0x02 => {
-            let ram_size = get_ram_size(rom.as_ref());
+            let ram_size = get_ram_size(rom.as_ref())?;
             Box::new(Mbc1::new(rom, vec![0; ram_size], ""))
         }
         0x03 => {
             let (save_path, _) = get_save_paths(path);
-            let ram_size = get_ram_size(rom.as_ref());
-            let ram = read_ram_from_save(save_path.clone(), ram_size);
+            let ram_size = get_ram_size(rom.as_ref())?;
+            let ram = read_ram_from_save(save_path.clone(), ram_size)?;
             Box::new(Mbc1::new(rom, ram, save_path))
         }
         0x05 => {
@@ -178,48 +234,50 @@ pub fn new(rom: Vec<u8>, path: impl AsRef<Path>, skip_checks: bool) -> Box<dyn C
         0x06 => {
             let (save_path, _) = get_save_paths(path);
             let ram_size = 512;
-            let ram = read_ram_from_save(save_path.clone(), ram_size);
+            let ram = read_ram_from_save(save_path.clone(), ram_size)?;
             Box::new(Mbc2::new(rom, ram, save_path))
         }
         0x0F => {
             let (save_path, rtc_save_path) = get_save_paths(path);
-            Box::new(Mbc3::new(rom, vec![], save_path, rtc_save_path))
+            Box::new(Mbc3::new(rom, vec![], save_path, rtc_save_path)?)
         }
         0x10 => {
             let (save_path, rtc_save_path) = get_save_paths(path);
-            let ram_size = get_ram_size(rom.as_ref());
-            let ram = read_ram_from_save(save_path.clone(), ram_size);
-            Box::new(Mbc3::new(rom, ram, save_path, rtc_save_path))
+            let ram_size = get_ram_size(rom.as_ref())?;
+            let ram = read_mbc3_ram_from_save(save_path.clone(), ram_size)?;
+            Box::new(Mbc3::new(rom, ram, save_path, rtc_save_path)?)
         }
-        0x11 => Box::new(Mbc3::new(rom, vec![], "", "")),
+        0x11 => Box::new(Mbc3::new(rom, vec![], "", "")?),
         0x12 => {
-            let ram_size = get_ram_size(rom.as_ref());
-            Box::new(Mbc3::new(rom, vec![0; ram_size], "", ""))
+            let ram_size = get_ram_size(rom.as_ref())?;
+            Box::new(Mbc3::new(rom, vec![0; ram_size], "", "")?)
         }
         0x13 => {
             let (save_path, _) = get_save_paths(path);
-            let ram_size = get_ram_size(rom.as_ref());
-            let ram = read_ram_from_save(save_path.clone(), ram_size);
-            Box::new(Mbc3::new(rom, ram, save_path, ""))
+            let ram_size = get_ram_size(rom.as_ref())?;
+            let ram = read_mbc3_ram_from_save(save_path.clone(), ram_size)?;
+            Box::new(Mbc3::new(rom, ram, save_path, "")?)
         }
         0x19 => Box::new(Mbc5::new(rom, vec![], "")),
         0x1A => {
-            let ram_size = get_ram_size(rom.as_ref());
+            let ram_size = get_ram_size(rom.as_ref())?;
             Box::new(Mbc5::new(rom, vec![0; ram_size], ""))
         }
         0x1B => {
             let (save_path, _) = get_save_paths(path);
-            let ram_size = get_ram_size(rom.as_ref());
-            let ram = read_ram_from_save(save_path.clone(), ram_size);
+            let ram_size = get_ram_size(rom.as_ref())?;
+            let ram = read_ram_from_save(save_path.clone(), ram_size)?;
             Box::new(Mbc5::new(rom, ram, save_path))
         }
-        byte => panic!("cartridge: unsupported type {:#04X?}", byte),
+        byte => return Err(GameboyError::UnsupportedCartridgeType(byte)),
     };
-    if !skip_checks {
-        cartridge.verify_nintendo_logo();
-        cartridge.verify_header_checksum();
+    if !options.skip_logo_check {
+        cartridge.verify_nintendo_logo()?;
     }
-    cartridge
+    if !options.skip_checksum_check {
+        cartridge.verify_header_checksum()?;
+    }
+    Ok(cartridge)
 }
 
 // 0148 - ROM Size
@@ -236,8 +294,8 @@ pub fn new(rom: Vec<u8>, path: impl AsRef<Path>, skip_checks: bool) -> Box<dyn C
 //  52h - 1.1MByte (72 banks)
 //  53h - 1.2MByte (80 banks)
 //  54h - 1.5MByte (96 banks)
-pub fn get_rom_size(rom: &[u8]) -> usize {
-    match rom[0x148] {
+pub fn get_rom_size(rom: &[u8]) -> Result<usize, GameboyError> {
+    let size = match rom[0x148] {
         0x00 => 1024 * 16 * 2,
         0x01 => 1024 * 16 * 4,
         0x02 => 1024 * 16 * 8,
@@ -250,8 +308,9 @@ pub fn get_rom_size(rom: &[u8]) -> usize {
         0x52 => 1024 * 16 * 72,
         0x53 => 1024 * 16 * 80,
         0x54 => 1024 * 16 * 96,
-        byte => panic!("cartridge: unsupported rom size {:#04X?}", byte),
-    }
+        byte => return Err(GameboyError::UnsupportedRomSize(byte)),
+    };
+    Ok(size)
 }
 
 // 0149 - RAM Size
@@ -264,21 +323,60 @@ pub fn get_rom_size(rom: &[u8]) -> usize {
 //  05h - 64 KBytes (8 banks of 8KBytes each)
 // When using a MBC2 chip 00h must be specified in this entry, even though the
 // MBC2 includes a built-in RAM of 512 x 4 bits.
-pub fn get_ram_size(rom: &[u8]) -> usize {
-    match rom[0x149] {
+pub fn get_ram_size(rom: &[u8]) -> Result<usize, GameboyError> {
+    let size = match rom[0x149] {
         0x00 => 0,
         0x01 => 1024 * 2,
         0x02 => 1024 * 8,
         0x03 => 1024 * 32,
         0x04 => 1024 * 128,
         0x05 => 1024 * 64,
-        byte => panic!("cartridge: unsupported ram size {:#04X?}", byte),
-    }
+        byte => return Err(GameboyError::UnsupportedRamSize(byte)),
+    };
+    Ok(size)
 }
 
 // Read RAM data from external sav file when available
-pub fn read_ram_from_save(path: impl AsRef<Path>, size: usize) -> Vec<u8> {
-    std::fs::read(path).unwrap_or_else(|_| vec![0; size])
+pub fn read_ram_from_save(path: impl AsRef<Path>, size: usize) -> Result<Vec<u8>, GameboyError> {
+    read_save(path, size, &[])
+}
+
+// MBC3 saves written by other emulators may be followed by the RTC registers and a 32 or 64 bit timestamp (44 or 48
+// bytes). The RTC is kept in its own rtc file, so this footer is dropped.
+pub fn read_mbc3_ram_from_save(
+    path: impl AsRef<Path>,
+    size: usize,
+) -> Result<Vec<u8>, GameboyError> {
+    read_save(path, size, &[44, 48])
+}
+
+fn read_save(
+    path: impl AsRef<Path>,
+    size: usize,
+    footer_sizes: &[usize],
+) -> Result<Vec<u8>, GameboyError> {
+    if path.as_ref().as_os_str().is_empty() {
+        return Ok(vec![0; size]);
+    }
+    match std::fs::read(path.as_ref()) {
+        Ok(mut ram)
+            if ram.len() == size
+                || footer_sizes.iter().any(|footer| ram.len() == size + footer) =>
+        {
+            ram.truncate(size);
+            Ok(ram)
+        }
+        Ok(ram) => Err(GameboyError::InvalidSaveSize {
+            path: path.as_ref().to_path_buf(),
+            expected: size,
+            actual: ram.len(),
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![0; size]),
+        Err(error) => Err(GameboyError::SaveIo {
+            path: path.as_ref().to_path_buf(),
+            error,
+        }),
+    }
 }
 
 // Get path for sav and rtc save files
@@ -290,3 +388,102 @@ fn get_save_paths(rom_path: impl AsRef<Path>) -> (PathBuf, PathBuf) {
     let rtc_path = rom_path.as_ref().to_path_buf().with_extension("rtc");
     (sav_path, rtc_path)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Cartridge, read_mbc3_ram_from_save, read_ram_from_save};
+    use crate::cartridges::mbc3::Mbc3;
+    use crate::cartridges::mbc5::Mbc5;
+    use crate::error::GameboyError;
+    use crate::memory::Memory;
+    use crate::state::{StateError, StateReader, StateWriter, Stateful};
+
+    fn reload(cartridge: &mut impl Stateful) -> Result<(), StateError> {
+        let mut writer = StateWriter::new();
+        cartridge.save_state(&mut writer);
+        let data = writer.into_inner();
+        cartridge.load_state(&mut StateReader::new(&data))
+    }
+
+    #[test]
+    fn load_state_rejects_banks_outside_cartridge() {
+        // 32KByte ROM (banks 0-1) and 8KByte RAM (bank 0).
+        let mut mbc5 = Mbc5::new(vec![0x00; 0x8000], vec![0x00; 0x2000], "");
+        assert_eq!(reload(&mut mbc5), Ok(()));
+        mbc5.set_byte(0x2000, 0x02);
+        assert_eq!(reload(&mut mbc5), Err(StateError::InvalidFormat));
+        mbc5.set_byte(0x2000, 0x01);
+        mbc5.set_byte(0x4000, 0x01);
+        assert_eq!(reload(&mut mbc5), Err(StateError::InvalidFormat));
+
+        let mut mbc3 = Mbc3::new(vec![0x00; 0x8000], vec![], "", "").unwrap();
+        // RAM bank 0 is selected even without RAM, as are the RTC registers.
+        assert_eq!(reload(&mut mbc3), Ok(()));
+        mbc3.set_byte(0x4000, 0x08);
+        assert_eq!(reload(&mut mbc3), Ok(()));
+        mbc3.set_byte(0x4000, 0x05);
+        assert_eq!(reload(&mut mbc3), Err(StateError::InvalidFormat));
+    }
+
+    #[test]
+    fn copy_shares_rom_until_written() {
+        let mbc5 = Mbc5::new(vec![0x00; 0x8000], vec![0x00; 0x2000], "");
+        let mut copy = mbc5.clone_box();
+        assert_eq!(copy.get_rom().as_ptr(), mbc5.get_rom().as_ptr());
+        copy.get_rom_mut()[0x0000] = 0xFF;
+        assert_eq!(mbc5.get_rom()[0x0000], 0x00);
+        assert_eq!(copy.get_rom()[0x0000], 0xFF);
+    }
+
+    #[test]
+    fn read_ram_checks_save_size() {
+        let path = std::env::temp_dir().join(format!("gameboyr-{}.sav", std::process::id()));
+        std::fs::write(&path, [0xAB; 0x2000]).unwrap();
+        assert_eq!(read_ram_from_save(&path, 0x2000).unwrap(), [0xAB; 0x2000]);
+        for size in [0x1000, 0x8000] {
+            assert!(matches!(
+                read_ram_from_save(&path, size),
+                Err(GameboyError::InvalidSaveSize {
+                    expected,
+                    actual: 0x2000,
+                    ..
+                }) if expected == size
+            ));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Write a MBC3 save of 8KByte RAM followed by an RTC footer, and read it back.
+    fn read_mbc3_save(footer: usize) -> Result<Vec<u8>, GameboyError> {
+        let name = format!("gameboyr-{}-rtc{}.sav", std::process::id(), footer);
+        let path = std::env::temp_dir().join(name);
+        let mut save = vec![0xAB; 0x2000];
+        save.resize(0x2000 + footer, 0xCD);
+        std::fs::write(&path, save).unwrap();
+        let ram = read_mbc3_ram_from_save(&path, 0x2000);
+        std::fs::remove_file(&path).unwrap();
+        ram
+    }
+
+    #[test]
+    fn read_mbc3_ram_drops_32_bit_rtc_footer() {
+        assert_eq!(read_mbc3_save(44).unwrap(), [0xAB; 0x2000]);
+    }
+
+    #[test]
+    fn read_mbc3_ram_drops_64_bit_rtc_footer() {
+        assert_eq!(read_mbc3_save(48).unwrap(), [0xAB; 0x2000]);
+    }
+
+    #[test]
+    fn read_mbc3_ram_checks_save_size() {
+        assert!(matches!(
+            read_mbc3_save(40),
+            Err(GameboyError::InvalidSaveSize {
+                expected: 0x2000,
+                actual: 0x2028,
+                ..
+            })
+        ));
+    }
+}
@@ -4,16 +4,22 @@
 // Optionally up to 8KByte of RAM could be connected at A000-BFFF, even
 // though that could require a tiny MBC-like circuit, but no real MBC chip.
 
+use std::sync::Arc;
+
 use crate::cartridges::{Cartridge, Stable};
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
+#[derive(Clone)]
 pub struct RomOnly {
-    rom: Vec<u8>,
+    rom: Arc<[u8]>,
 }
 
 impl RomOnly {
     pub fn new(rom: Vec<u8>) -> RomOnly {
-        RomOnly { rom }
+        RomOnly {
+            rom: Arc::from(rom),
+        }
     }
 }
 
@@ -28,4 +34,25 @@ impl Memory for RomOnly {
 
 impl Stable for RomOnly {}
 
-impl Cartridge for RomOnly {}
+// A ROM only cartridge has no registers or RAM, so there is no state to save.
+impl Stateful for RomOnly {
+    fn save_state(&self, _: &mut StateWriter) {}
+
+    fn load_state(&mut self, _: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn get_rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn get_rom_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.rom)
+    }
+
+    fn clone_box(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+}
@@ -1,5 +1,7 @@
 // The Clock is used to help normalize the cycles in the Timer
 
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
+
 #[derive(Debug, Copy, Clone)]
 pub struct Clock {
     pub period: u32,
@@ -22,9 +24,27 @@ impl Clock {
     }
 }
 
+impl Stateful for Clock {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.period);
+        writer.write_u32(self.num_cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let period = reader.read_u32()?;
+        if period == 0 {
+            return Err(StateError::InvalidFormat);
+        }
+        self.period = period;
+        self.num_cycles = reader.read_u32()? % period;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::Clock;
+    use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
     #[test]
     fn clock_functionality() {
@@ -35,4 +55,31 @@ mod test {
         assert_eq!(clock.run_cycles(221), 1);
         assert_eq!(clock.num_cycles, 110);
     }
+
+    #[test]
+    fn load_state_rejects_zero_period() {
+        let mut writer = StateWriter::new();
+        Clock::new(0).save_state(&mut writer);
+        let data = writer.into_inner();
+        let mut clock = Clock::new(100);
+        assert_eq!(
+            clock.load_state(&mut StateReader::new(&data)),
+            Err(StateError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn load_state_wraps_cycles() {
+        let mut writer = StateWriter::new();
+        Clock {
+            period: 100,
+            num_cycles: u32::MAX,
+        }
+        .save_state(&mut writer);
+        let data = writer.into_inner();
+        let mut clock = Clock::new(100);
+        clock.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(clock.num_cycles, u32::MAX % 100);
+        assert_eq!(clock.run_cycles(100), 1);
+    }
 }
@@ -0,0 +1,520 @@
+// Disassembler
+// Decodes SM83 machine code into the mnemonics used throughout the CPU (see op_codes.rs and cb_codes.rs), the
+// operands are formatted as follows:
+//   d8, d16   Immediate values: $12, $1234
+//   a8        High RAM addresses (LDH): ($FF12)
+//   a16       Addresses: ($1234), or $1234 for jumps and calls
+//   r8        Relative jumps show the target address, ADD SP and LD HL,SP+ show the signed offset: -$02
+// Invalid op codes are shown as DB $D3. The cycles are those of the CPU tables, a taken branch takes longer.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::cpu::cb_codes::CB_CYCLES;
+use crate::cpu::op_codes::OP_CYCLES;
+use crate::memory::Memory;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const CB_OPERATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// The mnemonics of each op code, with placeholders for their operands. Invalid op codes are empty.
+const OP_MNEMONICS: [&str; 256] = [
+    // 0
+    "NOP",
+    "LD BC, d16",
+    "LD (BC), A",
+    "INC BC",
+    "INC B",
+    "DEC B",
+    "LD B, d8",
+    "RLCA",
+    "LD (a16), SP",
+    "ADD HL, BC",
+    "LD A, (BC)",
+    "DEC BC",
+    "INC C",
+    "DEC C",
+    "LD C, d8",
+    "RRCA",
+    // 1
+    "STOP",
+    "LD DE, d16",
+    "LD (DE), A",
+    "INC DE",
+    "INC D",
+    "DEC D",
+    "LD D, d8",
+    "RLA",
+    "JR r8",
+    "ADD HL, DE",
+    "LD A, (DE)",
+    "DEC DE",
+    "INC E",
+    "DEC E",
+    "LD E, d8",
+    "RRA",
+    // 2
+    "JR NZ, r8",
+    "LD HL, d16",
+    "LD (HL+), A",
+    "INC HL",
+    "INC H",
+    "DEC H",
+    "LD H, d8",
+    "DAA",
+    "JR Z, r8",
+    "ADD HL, HL",
+    "LD A, (HL+)",
+    "DEC HL",
+    "INC L",
+    "DEC L",
+    "LD L, d8",
+    "CPL",
+    // 3
+    "JR NC, r8",
+    "LD SP, d16",
+    "LD (HL-), A",
+    "INC SP",
+    "INC (HL)",
+    "DEC (HL)",
+    "LD (HL), d8",
+    "SCF",
+    "JR C, r8",
+    "ADD HL, SP",
+    "LD A, (HL-)",
+    "DEC SP",
+    "INC A",
+    "DEC A",
+    "LD A, d8",
+    "CCF",
+    // 4
+    "LD B, B",
+    "LD B, C",
+    "LD B, D",
+    "LD B, E",
+    "LD B, H",
+    "LD B, L",
+    "LD B, (HL)",
+    "LD B, A",
+    "LD C, B",
+    "LD C, C",
+    "LD C, D",
+    "LD C, E",
+    "LD C, H",
+    "LD C, L",
+    "LD C, (HL)",
+    "LD C, A",
+    // 5
+    "LD D, B",
+    "LD D, C",
+    "LD D, D",
+    "LD D, E",
+    "LD D, H",
+    "LD D, L",
+    "LD D, (HL)",
+    "LD D, A",
+    "LD E, B",
+    "LD E, C",
+    "LD E, D",
+    "LD E, E",
+    "LD E, H",
+    "LD E, L",
+    "LD E, (HL)",
+    "LD E, A",
+    // 6
+    "LD H, B",
+    "LD H, C",
+    "LD H, D",
+    "LD H, E",
+    "LD H, H",
+    "LD H, L",
+    "LD H, (HL)",
+    "LD H, A",
+    "LD L, B",
+    "LD L, C",
+    "LD L, D",
+    "LD L, E",
+    "LD L, H",
+    "LD L, L",
+    "LD L, (HL)",
+    "LD L, A",
+    // 7
+    "LD (HL), B",
+    "LD (HL), C",
+    "LD (HL), D",
+    "LD (HL), E",
+    "LD (HL), H",
+    "LD (HL), L",
+    "HALT",
+    "LD (HL), A",
+    "LD A, B",
+    "LD A, C",
+    "LD A, D",
+    "LD A, E",
+    "LD A, H",
+    "LD A, L",
+    "LD A, (HL)",
+    "LD A, A",
+    // 8
+    "ADD A, B",
+    "ADD A, C",
+    "ADD A, D",
+    "ADD A, E",
+    "ADD A, H",
+    "ADD A, L",
+    "ADD A, (HL)",
+    "ADD A, A",
+    "ADC A, B",
+    "ADC A, C",
+    "ADC A, D",
+    "ADC A, E",
+    "ADC A, H",
+    "ADC A, L",
+    "ADC A, (HL)",
+    "ADC A, A",
+    // 9
+    "SUB B",
+    "SUB C",
+    "SUB D",
+    "SUB E",
+    "SUB H",
+    "SUB L",
+    "SUB (HL)",
+    "SUB A",
+    "SBC A, B",
+    "SBC A, C",
+    "SBC A, D",
+    "SBC A, E",
+    "SBC A, H",
+    "SBC A, L",
+    "SBC A, (HL)",
+    "SBC A, A",
+    // a
+    "AND B",
+    "AND C",
+    "AND D",
+    "AND E",
+    "AND H",
+    "AND L",
+    "AND (HL)",
+    "AND A",
+    "XOR B",
+    "XOR C",
+    "XOR D",
+    "XOR E",
+    "XOR H",
+    "XOR L",
+    "XOR (HL)",
+    "XOR A",
+    // b
+    "OR B",
+    "OR C",
+    "OR D",
+    "OR E",
+    "OR H",
+    "OR L",
+    "OR (HL)",
+    "OR A",
+    "CP B",
+    "CP C",
+    "CP D",
+    "CP E",
+    "CP H",
+    "CP L",
+    "CP (HL)",
+    "CP A",
+    // c
+    "RET NZ",
+    "POP BC",
+    "JP NZ, a16",
+    "JP a16",
+    "CALL NZ, a16",
+    "PUSH BC",
+    "ADD A, d8",
+    "RST $00",
+    "RET Z",
+    "RET",
+    "JP Z, a16",
+    "PREFIX CB",
+    "CALL Z, a16",
+    "CALL a16",
+    "ADC A, d8",
+    "RST $08",
+    // d
+    "RET NC",
+    "POP DE",
+    "JP NC, a16",
+    "",
+    "CALL NC, a16",
+    "PUSH DE",
+    "SUB d8",
+    "RST $10",
+    "RET C",
+    "RETI",
+    "JP C, a16",
+    "",
+    "CALL C, a16",
+    "",
+    "SBC A, d8",
+    "RST $18",
+    // e
+    "LDH (a8), A",
+    "POP HL",
+    "LD ($FF00+C), A",
+    "",
+    "",
+    "PUSH HL",
+    "AND d8",
+    "RST $20",
+    "ADD SP, r8",
+    "JP (HL)",
+    "LD (a16), A",
+    "",
+    "",
+    "",
+    "XOR d8",
+    "RST $28",
+    // f
+    "LDH A, (a8)",
+    "POP AF",
+    "LD A, ($FF00+C)",
+    "DI",
+    "",
+    "PUSH AF",
+    "OR d8",
+    "RST $30",
+    "LD HL, SP+r8",
+    "LD SP, HL",
+    "LD A, (a16)",
+    "EI",
+    "",
+    "",
+    "CP d8",
+    "RST $38",
+];
+
+/// Instruction is a single decoded SM83 instruction.
+///
+/// Instructions are displayed with their address and bytes, for example `0150  FA 00 C0  LD A, ($C000)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// The address of the first byte.
+    pub addr: u16,
+    /// The bytes of the instruction (1 to 3).
+    pub bytes: Vec<u8>,
+    /// The mnemonic with its operands, for example `LD A, ($C000)`.
+    pub text: String,
+    /// The number of CPU cycles taken, not including the extra cycles of a taken branch.
+    pub cycles: u32,
+}
+
+impl Instruction {
+    /// Decode the instruction at `addr`, reading its bytes using `get_byte`.
+    pub fn decode(addr: u16, get_byte: impl Fn(u16) -> u8) -> Instruction {
+        let op_code = get_byte(addr);
+        if op_code == 0xCB {
+            let cb_code = get_byte(addr.wrapping_add(1));
+            let register = REGISTERS[usize::from(cb_code & 0x07)];
+            let bit = (cb_code >> 3) & 0x07;
+            let text = match cb_code >> 6 {
+                0 => format!("{} {}", CB_OPERATIONS[usize::from(bit)], register),
+                1 => format!("BIT {}, {}", bit, register),
+                2 => format!("RES {}, {}", bit, register),
+                _ => format!("SET {}, {}", bit, register),
+            };
+            return Instruction {
+                addr,
+                bytes: vec![op_code, cb_code],
+                text,
+                cycles: CB_CYCLES[usize::from(cb_code)] * 4,
+            };
+        }
+        let mnemonic = OP_MNEMONICS[usize::from(op_code)];
+        if mnemonic.is_empty() {
+            return Instruction {
+                addr,
+                bytes: vec![op_code],
+                text: format!("DB ${:02X}", op_code),
+                cycles: 0,
+            };
+        }
+        let len = if mnemonic.contains("16") {
+            3
+        } else if ["d8", "a8", "r8"]
+            .iter()
+            .any(|operand| mnemonic.contains(operand))
+        {
+            2
+        } else {
+            1
+        };
+        let bytes: Vec<u8> = (0..len).map(|i| get_byte(addr.wrapping_add(i))).collect();
+        let d8 = bytes.get(1).copied().unwrap_or(0);
+        let d16 = u16::from(d8) | (u16::from(bytes.get(2).copied().unwrap_or(0)) << 8);
+        let r8 = d8 as i8;
+        let signed = if r8 < 0 {
+            format!("-${:02X}", r8.unsigned_abs())
+        } else {
+            format!("${:02X}", r8)
+        };
+        let text = if mnemonic.starts_with("JR") {
+            let target = addr.wrapping_add(2).wrapping_add_signed(i16::from(r8));
+            mnemonic.replace("r8", &format!("${:04X}", target))
+        } else if mnemonic.contains("SP+r8") {
+            let sign = if r8 < 0 { "" } else { "+" };
+            mnemonic.replace("+r8", &format!("{}{}", sign, signed))
+        } else {
+            mnemonic
+                .replace("r8", &signed)
+                .replace("d16", &format!("${:04X}", d16))
+                .replace("a16", &format!("${:04X}", d16))
+                .replace("d8", &format!("${:02X}", d8))
+                .replace("a8", &format!("$FF{:02X}", d8))
+        };
+        Instruction {
+            addr,
+            bytes,
+            text,
+            cycles: OP_CYCLES[usize::from(op_code)] * 4,
+        }
+    }
+
+    /// Get the address of the next instruction.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X}  {:<8}  {}",
+            self.addr,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+/// Disassemble the instructions starting in `range`, reading from `memory`. The last
+/// instruction may end after the range.
+pub fn disassemble<M: Memory + ?Sized>(memory: &M, range: RangeInclusive<u16>) -> Vec<Instruction> {
+    disassemble_with(range, |addr| memory.get_byte(addr))
+}
+
+/// Disassemble a 16KB bank of a ROM, at the addresses it is mapped to: bank 0 at
+/// 0000-3FFF and the other banks at 4000-7FFF. Returns nothing when the ROM does not have
+/// the bank.
+pub fn disassemble_rom_bank(rom: &[u8], bank: usize) -> Vec<Instruction> {
+    let Some(start) = bank
+        .checked_mul(ROM_BANK_SIZE)
+        .filter(|&start| start < rom.len())
+    else {
+        return Vec::new();
+    };
+    let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let data = &rom[start..rom.len().min(start + ROM_BANK_SIZE)];
+    let end = base + (data.len() - 1) as u16;
+    disassemble_with(base..=end, |addr| {
+        data.get(usize::from(addr.wrapping_sub(base)))
+            .copied()
+            .unwrap_or(0x00)
+    })
+}
+
+// Disassemble the instructions starting in `range`, reading using `get_byte`.
+pub(crate) fn disassemble_with(
+    range: RangeInclusive<u16>,
+    get_byte: impl Fn(u16) -> u8,
+) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut addr = u32::from(*range.start());
+    while addr <= u32::from(*range.end()) {
+        let instruction = Instruction::decode(addr as u16, &get_byte);
+        addr += instruction.bytes.len() as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Instruction, disassemble_rom_bank, disassemble_with};
+
+    fn decode(bytes: &[u8]) -> Instruction {
+        Instruction::decode(0x0150, |addr| {
+            bytes
+                .get(usize::from(addr - 0x0150))
+                .copied()
+                .unwrap_or(0x00)
+        })
+    }
+
+    #[test]
+    fn decode_operands() {
+        for (bytes, text) in [
+            (&[0x00][..], "NOP"),
+            (&[0x3E, 0x12], "LD A, $12"),
+            (&[0xFA, 0x00, 0xC0], "LD A, ($C000)"),
+            (&[0xCD, 0x34, 0x12], "CALL $1234"),
+            (&[0xE0, 0x44], "LDH ($FF44), A"),
+            (&[0x18, 0xFE], "JR $0150"),
+            (&[0x20, 0x05], "JR NZ, $0157"),
+            (&[0xE8, 0xFE], "ADD SP, -$02"),
+            (&[0xF8, 0x05], "LD HL, SP+$05"),
+            (&[0xF8, 0x80], "LD HL, SP-$80"),
+            (&[0xFF], "RST $38"),
+            (&[0xCF], "RST $08"),
+            (&[0xD3], "DB $D3"),
+            (&[0xCB, 0x37], "SWAP A"),
+            (&[0xCB, 0x7E], "BIT 7, (HL)"),
+            (&[0xCB, 0x80], "RES 0, B"),
+        ] {
+            let instruction = decode(bytes);
+            assert_eq!(instruction.text, text);
+            assert_eq!(instruction.bytes, bytes);
+        }
+    }
+
+    #[test]
+    fn cycles() {
+        assert_eq!(decode(&[0x00]).cycles, 4);
+        assert_eq!(decode(&[0xCD, 0x34, 0x12]).cycles, 24);
+        assert_eq!(decode(&[0x20, 0x05]).cycles, 8);
+        assert_eq!(decode(&[0xCB, 0x46]).cycles, 12);
+        assert_eq!(decode(&[0xCB, 0x06]).cycles, 16);
+    }
+
+    #[test]
+    fn range() {
+        // 0100: INC A; JR -3
+        let code = [0x3C, 0x18, 0xFD];
+        let instructions = disassemble_with(0x0100..=0x0101, |addr| {
+            code.get(usize::from(addr - 0x0100))
+                .copied()
+                .unwrap_or(0x00)
+        });
+        let text: Vec<&str> = instructions.iter().map(|i| i.text.as_str()).collect();
+        assert_eq!(text, ["INC A", "JR $0100"]);
+        // The last instruction may end past the end of the memory map.
+        let instructions = disassemble_with(0xFFFF..=0xFFFF, |_| 0xCD);
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].bytes, [0xCD, 0xCD, 0xCD]);
+    }
+
+    #[test]
+    fn rom_bank() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x4000..0x4004].copy_from_slice(&[0x3C, 0xC3, 0x00, 0x40]);
+        let instructions = disassemble_rom_bank(&rom, 1);
+        assert_eq!(instructions[0].to_string(), "4000  3C        INC A");
+        assert_eq!(instructions[1].to_string(), "4001  C3 00 40  JP $4000");
+        assert_eq!(instructions[2].addr, 0x4004);
+        assert_eq!(instructions.last().unwrap().next_addr(), 0x8000);
+        assert!(disassemble_rom_bank(&rom, 2).is_empty());
+        assert!(disassemble_rom_bank(&rom, usize::MAX).is_empty());
+    }
+}
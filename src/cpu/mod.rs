@@ -12,17 +12,59 @@
 //  - https://izik1.github.io/gbops/
 //  - https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
 
-mod registers;
+pub mod registers;
 
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::thread;
-use std::time;
 
 use crate::cartridges::CartridgeMode;
 use crate::cpu::registers::Registers;
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
+use crate::{HardwareModel, Register};
 
+/// CpuState is a snapshot of the CPU registers and interrupt state, see `Gameboy::get_cpu_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    registers: Registers,
+    ime: bool,
+    halted: bool,
+}
+
+impl CpuState {
+    /// Get the value of a register, 8 bit registers are returned in the low byte.
+    pub fn get_register(&self, register: Register) -> u16 {
+        register.get(&self.registers)
+    }
+
+    /// Set the value of a register, 8 bit registers use the low byte. The low 4 bits of F
+    /// are always 0.
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        register.set(&mut self.registers, value);
+    }
+
+    /// Check if interrupts are enabled (the IME flag, set by EI and cleared by DI).
+    pub fn get_ime(&self) -> bool {
+        self.ime
+    }
+
+    /// Enable or disable interrupts (the IME flag).
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+    }
+
+    /// Check if the CPU is halted, waiting for an interrupt.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Halt the CPU or wake it up.
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+}
+
+#[derive(Clone)]
 pub struct Cpu {
     pub registers: Registers,
     pub memory: Rc<RefCell<dyn Memory>>,
@@ -32,9 +74,9 @@ pub struct Cpu {
 }
 
 impl Cpu {
-    pub fn new(mode: CartridgeMode, memory: Rc<RefCell<dyn Memory>>) -> Cpu {
+    pub fn new(model: HardwareModel, mode: CartridgeMode, memory: Rc<RefCell<dyn Memory>>) -> Cpu {
         Cpu {
-            registers: Registers::new(mode),
+            registers: Registers::new(model, mode),
             memory,
             halted: false,
             stopped: false,
@@ -42,6 +84,31 @@ impl Cpu {
         }
     }
 
+    // Create a CPU in its power on state, ready to execute a boot ROM.
+    pub fn power_on(memory: Rc<RefCell<dyn Memory>>) -> Cpu {
+        Cpu {
+            registers: Registers::power_on(),
+            memory,
+            halted: false,
+            stopped: false,
+            ei: false,
+        }
+    }
+
+    pub fn get_state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            ime: self.ei,
+            halted: self.halted,
+        }
+    }
+
+    pub fn set_state(&mut self, state: CpuState) {
+        self.registers = state.registers;
+        self.ei = state.ime;
+        self.halted = state.halted;
+    }
+
     // Get the next byte (based on program counter) and increment pc
     pub fn get_byte_at_pc(&mut self) -> u8 {
         let value = self.memory.borrow().get_byte(self.registers.pc);
@@ -120,78 +187,81 @@ impl Cpu {
         4
     }
 
-    pub fn run(&mut self) -> u32 {
-        let cycles = {
-            match self.handle_interrupts() {
-                0 => {}
-                n => return n,
-            }
-            if self.halted {
-                // Emulate a noop instruction
-                1
-            } else {
-                let op_code = self.get_byte_at_pc();
-                self.execute(op_code)
-            }
+    // Run the instruction at PC, returning the number of CPU cycles. Interrupts are handled separately using
+    // `handle_interrupts`.
+    pub fn run_instruction(&mut self) -> u32 {
+        let cycles = if self.halted {
+            // Emulate a noop instruction
+            1
+        } else {
+            let op_code = self.get_byte_at_pc();
+            self.execute(op_code)
         };
         cycles * 4
     }
 }
 
+impl Stateful for Cpu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.registers.save_state(writer);
+        writer.write_bool(self.halted);
+        writer.write_bool(self.stopped);
+        writer.write_bool(self.ei);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.registers.load_state(reader)?;
+        self.halted = reader.read_bool()?;
+        self.stopped = reader.read_bool()?;
+        self.ei = reader.read_bool()?;
+        Ok(())
+    }
+}
+
 mod cb_codes;
+pub mod disassembler;
 mod instructions;
 mod op_codes;
 
 pub const CLOCK_FREQUENCY: u32 = 4_194_304;
-pub const STEP_TIME: u32 = 16;
-pub const STEP_CYCLES: u32 = (STEP_TIME as f64 / (1000_f64 / CLOCK_FREQUENCY as f64)) as u32;
-
-pub struct RealTimeCpu {
-    pub cpu: Cpu,
-    step_cycles: u32,
-    step_zero: time::Instant,
-    step_flip: bool,
-}
 
-impl RealTimeCpu {
-    pub fn new(mode: CartridgeMode, memory: Rc<RefCell<dyn Memory>>) -> RealTimeCpu {
-        RealTimeCpu {
-            cpu: Cpu::new(mode, memory),
-            step_cycles: 0,
-            step_zero: time::Instant::now(),
-            step_flip: false,
-        }
-    }
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
-    // Simulate real hardware execution speed by limiting function call of cpu.run()
-    pub fn run(&mut self) -> u32 {
-        if self.step_cycles > STEP_CYCLES {
-            self.step_flip = true;
-            self.step_cycles -= STEP_CYCLES;
-            let now = time::Instant::now();
-            let duration = now.duration_since(self.step_zero);
-            let s = u64::from(STEP_TIME.saturating_sub(duration.as_millis() as u32));
-            thread::sleep(time::Duration::from_millis(s));
-            self.step_zero = self
-                .step_zero
-                .checked_add(time::Duration::from_millis(u64::from(STEP_TIME)))
-                .unwrap();
-
-            // If now is after the just updated target frame time, reset to avoid drifting
-            if now.checked_duration_since(self.step_zero).is_some() {
-                self.step_zero = now;
-            }
-        }
-        let cycles = self.cpu.run();
-        self.step_cycles += cycles;
-        cycles
-    }
+    use super::Cpu;
+    use crate::cartridges::{self, CartridgeMode};
+    use crate::mmu::Mmu;
+    use crate::{HardwareModel, LoadOptions, Register};
 
-    pub fn flip(&mut self) -> bool {
-        let step_flip = self.step_flip;
-        if step_flip {
-            self.step_flip = false;
-        }
-        step_flip
+    #[test]
+    fn cpu_state() {
+        // 0100: INC A
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100] = 0x3C;
+        let cartridge = cartridges::new(rom, "", &LoadOptions::skip_checks()).unwrap();
+        let mmu = Mmu::new(cartridge, HardwareModel::Dmg, None);
+        let mut cpu = Cpu::new(
+            HardwareModel::Dmg,
+            CartridgeMode::Gb,
+            Rc::new(RefCell::new(mmu)),
+        );
+        let mut state = cpu.get_state();
+        assert_eq!(state.get_register(Register::PC), 0x0100);
+        assert!(!state.get_ime());
+        state.set_register(Register::A, 0x41);
+        state.set_register(Register::F, 0xFF);
+        state.set_register(Register::HL, 0x1234);
+        cpu.set_state(state);
+        assert_eq!(cpu.get_state(), state);
+        assert_eq!(state.get_register(Register::F), 0xF0);
+        cpu.run_instruction();
+        let state = cpu.get_state();
+        // INC A keeps the carry flag.
+        assert_eq!(state.get_register(Register::AF), 0x4210);
+        assert_eq!(state.get_register(Register::H), 0x12);
+        assert_eq!(state.get_register(Register::PC), 0x0101);
+        assert!(!state.is_halted());
     }
 }
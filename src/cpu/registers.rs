@@ -9,9 +9,11 @@
 // As shown above, most registers can be accessed either as one 16bit register,
 // or as two separate 8bit registers.
 
+use crate::HardwareModel;
 use crate::cartridges::CartridgeMode;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Registers {
     // Program counter / pointer
     pub pc: u16,
@@ -66,8 +68,27 @@ pub enum CpuFlag {
 //    DE=$00D8
 //    HL=$014D
 //    Stack Pointer=$FFFE
+// The values left by the boot ROM differ between hardware models (and whether a CGB is running a
+// DMG game), which allows games to detect the hardware they are running on:
+//    Model        AF     BC     DE     HL
+//    DMG          01B0   0013   00D8   014D
+//    MGB          FFB0   0013   00D8   014D
+//    SGB          0100   0014   0000   C060
+//    CGB          1180   0000   FF56   000D
+//    CGB (DMG)    1180   0000   0008   007C
+//    AGB          1100   0100   FF56   000D
+//    AGB (DMG)    1100   0100   0008   007C
 impl Registers {
-    pub fn new(mode: CartridgeMode) -> Registers {
+    pub fn new(model: HardwareModel, mode: CartridgeMode) -> Registers {
+        let (af, bc, de, hl) = match (model, mode) {
+            (HardwareModel::Dmg, _) => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            (HardwareModel::Mgb, _) => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            (HardwareModel::Sgb, _) => (0x0100, 0x0014, 0x0000, 0xC060),
+            (HardwareModel::Cgb, CartridgeMode::Gbc) => (0x1180, 0x0000, 0xFF56, 0x000D),
+            (HardwareModel::Cgb, CartridgeMode::Gb) => (0x1180, 0x0000, 0x0008, 0x007C),
+            (HardwareModel::Agb, CartridgeMode::Gbc) => (0x1100, 0x0100, 0xFF56, 0x000D),
+            (HardwareModel::Agb, CartridgeMode::Gb) => (0x1100, 0x0100, 0x0008, 0x007C),
+        };
         let mut registers = Registers {
             // On power up, the GameBoy Program Counter is initialized to 0x0100 and the instruction found at
             // this location in ROM is executed. The Program Counter from this point on is controlled, indirectly,
@@ -76,22 +97,39 @@ impl Registers {
             // The GameBoy stack pointer is initialized to 0xfffe on power up but a programmer should not rely on
             // this setting and rather should explicitly set its value.
             sp: 0xFFFE,
-            a: 0x01,
-            f: 0xB0,
+            a: 0x00,
+            f: 0x00,
             b: 0x00,
-            c: 0x13,
+            c: 0x00,
             d: 0x00,
-            e: 0xD8,
-            h: 0x01,
-            l: 0x4D,
-        };
-        match mode {
-            CartridgeMode::Gb => registers.a = 0x01,
-            CartridgeMode::Gbc => registers.a = 0x11,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
         };
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
         registers
     }
 
+    // When a boot ROM is used the registers start cleared and execution begins at 0000h, the boot ROM
+    // itself is then responsible for leaving the registers with the values listed above.
+    pub fn power_on() -> Registers {
+        Registers {
+            pc: 0x0000,
+            sp: 0x0000,
+            a: 0x00,
+            f: 0x00,
+            b: 0x00,
+            c: 0x00,
+            d: 0x00,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
+        }
+    }
+
     pub fn af(&self) -> u16 {
         // f gets bitwise AND with 0xF0 because the last 4 bits are always 0
         ((self.a as u16) << 8) | ((self.f & 0xF0) as u16)
@@ -163,15 +201,36 @@ impl Registers {
     }
 }
 
+impl Stateful for Registers {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.pc);
+        writer.write_u16(self.sp);
+        writer.write_u16(self.af());
+        writer.write_u16(self.bc());
+        writer.write_u16(self.de());
+        writer.write_u16(self.hl());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u16()?;
+        self.set_af(reader.read_u16()?);
+        self.set_bc(reader.read_u16()?);
+        self.set_de(reader.read_u16()?);
+        self.set_hl(reader.read_u16()?);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::CartridgeMode;
     use super::CpuFlag;
     use super::Registers;
+    use super::{CartridgeMode, HardwareModel};
 
     #[test]
     fn wide_registers() {
-        let mut reg = Registers::new(CartridgeMode::Gb);
+        let mut reg = Registers::new(HardwareModel::Dmg, CartridgeMode::Gb);
         reg.a = 0x12;
         reg.set_f(0x23);
         reg.b = 0x34;
@@ -199,7 +258,7 @@ mod test {
 
     #[test]
     fn flags() {
-        let mut reg = Registers::new(CartridgeMode::Gb);
+        let mut reg = Registers::new(HardwareModel::Dmg, CartridgeMode::Gb);
         let flags = [CpuFlag::C, CpuFlag::H, CpuFlag::N, CpuFlag::Z];
 
         assert_eq!(reg.f & 0x0F, 0);
@@ -224,7 +283,7 @@ mod test {
 
     #[test]
     fn hl_special_functions() {
-        let mut reg = Registers::new(CartridgeMode::Gb);
+        let mut reg = Registers::new(HardwareModel::Dmg, CartridgeMode::Gb);
         reg.set_hl(0x1234);
         assert_eq!(reg.hl(), 0x1234);
         assert_eq!(reg.hl_then_dec(), 0x1234);
@@ -236,7 +295,15 @@ mod test {
 
     #[test]
     fn initial_values_on_gbc() {
-        let reg = Registers::new(CartridgeMode::Gbc);
+        let reg = Registers::new(HardwareModel::Cgb, CartridgeMode::Gbc);
+        assert_eq!(reg.a, 0x11);
+    }
+
+    #[test]
+    fn initial_values_on_agb() {
+        let reg = Registers::new(HardwareModel::Agb, CartridgeMode::Gb);
         assert_eq!(reg.a, 0x11);
+        assert_eq!(reg.b & 0x01, 0x01);
+        assert_eq!(reg.de(), 0x0008);
     }
 }
@@ -0,0 +1,491 @@
+// Debugger
+// The debugger halts the emulator between instructions when a breakpoint or watchpoint is hit:
+//   - A breakpoint stops before the instruction at its address is run. It can be limited to a ROM bank, as the
+//     same address maps different code depending on the selected bank, and to a condition on a register.
+//   - A read or write watchpoint stops after an instruction accessed the address (including IO registers). Only
+//     the accesses made by the CPU are watched, not those of DMA transfers or the interrupt check.
+//   - A change watchpoint stops after the value at the address changed, by any means (the CPU, DMA transfers or
+//     the hardware itself, such as LY).
+// Stepping runs until the next instruction (step in), until a CALL or RST returns (step over), until the current
+// routine returns (step out) or until the end of the frame, stopping early when a breakpoint or watchpoint is hit.
+// Stepping over or out stops at the end of the frame at the latest, as the call may never return.
+
+use std::cell::RefCell;
+
+use crate::cpu::Cpu;
+use crate::cpu::registers::Registers;
+
+/// A CPU register, used in the `Condition` of a breakpoint and by `CpuState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub(crate) fn get(self, registers: &Registers) -> u16 {
+        match self {
+            Register::A => u16::from(registers.a),
+            Register::F => registers.af() & 0x00FF,
+            Register::B => u16::from(registers.b),
+            Register::C => u16::from(registers.c),
+            Register::D => u16::from(registers.d),
+            Register::E => u16::from(registers.e),
+            Register::H => u16::from(registers.h),
+            Register::L => u16::from(registers.l),
+            Register::AF => registers.af(),
+            Register::BC => registers.bc(),
+            Register::DE => registers.de(),
+            Register::HL => registers.hl(),
+            Register::SP => registers.sp,
+            Register::PC => registers.pc,
+        }
+    }
+
+    pub(crate) fn set(self, registers: &mut Registers, value: u16) {
+        let low = value as u8;
+        match self {
+            Register::A => registers.a = low,
+            Register::F => registers.set_af(u16::from(registers.a) << 8 | u16::from(low)),
+            Register::B => registers.b = low,
+            Register::C => registers.c = low,
+            Register::D => registers.d = low,
+            Register::E => registers.e = low,
+            Register::H => registers.h = low,
+            Register::L => registers.l = low,
+            Register::AF => registers.set_af(value),
+            Register::BC => registers.set_bc(value),
+            Register::DE => registers.set_de(value),
+            Register::HL => registers.set_hl(value),
+            Register::SP => registers.sp = value,
+            Register::PC => registers.pc = value,
+        }
+    }
+}
+
+/// How a register is compared with the value of a `Condition`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Condition limits a breakpoint to stop only when a register compares to a value.
+///
+/// ```
+/// use gameboyr::{Comparison, Condition, Register};
+///
+/// // Stop when A is zero.
+/// let condition = Condition {
+///     register: Register::A,
+///     comparison: Comparison::Equal,
+///     value: 0x00,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    fn matches(&self, cpu: &Cpu) -> bool {
+        let register = self.register.get(&cpu.registers);
+        match self.comparison {
+            Comparison::Equal => register == self.value,
+            Comparison::NotEqual => register != self.value,
+            Comparison::Less => register < self.value,
+            Comparison::LessOrEqual => register <= self.value,
+            Comparison::Greater => register > self.value,
+            Comparison::GreaterOrEqual => register >= self.value,
+        }
+    }
+}
+
+/// Breakpoint stops the Gameboy before the instruction at `addr` is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// Only stop when this ROM bank is mapped at `addr`. Ignored for addresses outside of ROM.
+    pub bank: Option<usize>,
+    /// Only stop when the condition is met.
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    /// Create a breakpoint at `addr`, in any ROM bank and without a condition.
+    pub fn new(addr: u16) -> Breakpoint {
+        Breakpoint {
+            addr,
+            bank: None,
+            condition: None,
+        }
+    }
+
+    fn matches(&self, cpu: &Cpu, rom_bank: usize) -> bool {
+        let pc = cpu.registers.pc;
+        let bank = match pc {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => Some(rom_bank),
+            _ => None,
+        };
+        self.addr == pc
+            && self
+                .bank
+                .is_none_or(|expected| bank.is_none_or(|bank| bank == expected))
+            && self
+                .condition
+                .is_none_or(|condition| condition.matches(cpu))
+    }
+}
+
+/// The kind of access watched by a `Watchpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// The CPU read the address.
+    Read,
+    /// The CPU wrote the address, even when the write is ignored.
+    Write,
+    /// The value at the address changed.
+    Change,
+}
+
+/// Watchpoint stops the Gameboy after the instruction which accessed (or changed) `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub kind: WatchKind,
+}
+
+/// StopReason describes why the Gameboy stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The step completed.
+    Step,
+    /// The frame completed, see `Gameboy::run_frame`.
+    Frame,
+    /// A breakpoint at this address was hit, the instruction has not been run yet.
+    Breakpoint(u16),
+    /// A watchpoint was hit, with the value read, written or changed to.
+    Watchpoint(Watchpoint, u8),
+}
+
+// Breakpoints and change watchpoints, checked by the Gameboy after each step.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    // The watched addresses with the value last seen.
+    changes: Vec<(u16, u8)>,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    // Remove the breakpoints at `addr` in `bank`, returning whether any were removed.
+    pub fn remove_breakpoint(&mut self, addr: u16, bank: Option<usize>) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.addr != addr || breakpoint.bank != bank);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn get_breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn has_breakpoint(&self, cpu: &Cpu, rom_bank: usize) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.matches(cpu, rom_bank))
+    }
+
+    pub fn add_change(&mut self, addr: u16, value: u8) {
+        if !self.changes.iter().any(|&(watched, _)| watched == addr) {
+            self.changes.push((addr, value));
+        }
+    }
+
+    pub fn remove_change(&mut self, addr: u16) -> bool {
+        let len = self.changes.len();
+        self.changes.retain(|&(watched, _)| watched != addr);
+        self.changes.len() != len
+    }
+
+    pub fn clear_changes(&mut self) {
+        self.changes.clear();
+    }
+
+    pub fn get_changes(&self) -> impl Iterator<Item = u16> + '_ {
+        self.changes.iter().map(|&(addr, _)| addr)
+    }
+
+    // Compare the watched addresses with their current value (read using `get_byte`), returning the first address
+    // which changed. All values are updated, so each change is only reported once.
+    pub fn check_changes(&mut self, get_byte: impl Fn(u16) -> u8) -> Option<(u16, u8)> {
+        let mut changed = None;
+        for (addr, last) in self.changes.iter_mut() {
+            let value = get_byte(*addr);
+            if value != *last {
+                *last = value;
+                changed = changed.or(Some((*addr, value)));
+            }
+        }
+        changed
+    }
+}
+
+// Read and write watchpoints, the MMU reports every access made by the CPU. Accesses happen through a shared
+// reference (reads), so the hits are kept in a RefCell.
+#[derive(Default)]
+pub struct Watch {
+    reads: Vec<u16>,
+    writes: Vec<u16>,
+    hits: RefCell<Vec<(Watchpoint, u8)>>,
+}
+
+impl Watch {
+    pub fn add(&mut self, addr: u16, kind: WatchKind) {
+        let addrs = match kind {
+            WatchKind::Read => &mut self.reads,
+            WatchKind::Write => &mut self.writes,
+            WatchKind::Change => panic!("debugger: change watchpoints are not watched by the MMU"),
+        };
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    pub fn remove(&mut self, addr: u16, kind: WatchKind) -> bool {
+        let addrs = match kind {
+            WatchKind::Read => &mut self.reads,
+            WatchKind::Write => &mut self.writes,
+            WatchKind::Change => return false,
+        };
+        let len = addrs.len();
+        addrs.retain(|&watched| watched != addr);
+        addrs.len() != len
+    }
+
+    pub fn get(&self) -> impl Iterator<Item = Watchpoint> + '_ {
+        let reads = self.reads.iter().map(|&addr| Watchpoint {
+            addr,
+            kind: WatchKind::Read,
+        });
+        let writes = self.writes.iter().map(|&addr| Watchpoint {
+            addr,
+            kind: WatchKind::Write,
+        });
+        reads.chain(writes)
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.clear();
+        self.writes.clear();
+        self.hits.borrow_mut().clear();
+    }
+
+    pub fn read(&self, addr: u16, value: u8) {
+        if self.reads.contains(&addr) {
+            self.hit(addr, WatchKind::Read, value);
+        }
+    }
+
+    pub fn write(&self, addr: u16, value: u8) {
+        if self.writes.contains(&addr) {
+            self.hit(addr, WatchKind::Write, value);
+        }
+    }
+
+    // Forget the watchpoints hit so far.
+    pub fn clear_hits(&self) {
+        let mut hits = self.hits.borrow_mut();
+        if !hits.is_empty() {
+            hits.clear();
+        }
+    }
+
+    fn hit(&self, addr: u16, kind: WatchKind, value: u8) {
+        self.hits
+            .borrow_mut()
+            .push((Watchpoint { addr, kind }, value));
+    }
+
+    // Forget the reads hit so far, used to ignore the reads of IF and IE by the interrupt check.
+    pub fn discard_reads(&self) {
+        let mut hits = self.hits.borrow_mut();
+        if !hits.is_empty() {
+            hits.retain(|(watchpoint, _)| watchpoint.kind != WatchKind::Read);
+        }
+    }
+
+    // Take the first watchpoint hit since the last call, forgetting the others.
+    pub fn take(&self) -> Option<(Watchpoint, u8)> {
+        let mut hits = self.hits.borrow_mut();
+        let hit = hits.first().copied();
+        hits.clear();
+        hit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{
+        Breakpoint, Comparison, Condition, Debugger, Register, Watch, WatchKind, Watchpoint,
+    };
+    use crate::cartridges::{self, CartridgeMode};
+    use crate::cpu::Cpu;
+    use crate::mmu::Mmu;
+    use crate::{HardwareModel, LoadOptions};
+
+    fn cpu(pc: u16, a: u8) -> Cpu {
+        let cartridge =
+            cartridges::new(vec![0x00; 0x8000], "", &LoadOptions::skip_checks()).unwrap();
+        let mmu = Mmu::new(cartridge, HardwareModel::Dmg, None);
+        let mut cpu = Cpu::new(
+            HardwareModel::Dmg,
+            CartridgeMode::Gb,
+            Rc::new(RefCell::new(mmu)),
+        );
+        cpu.registers.pc = pc;
+        cpu.registers.a = a;
+        cpu
+    }
+
+    #[test]
+    fn breakpoint_bank() {
+        let in_bank = |addr, bank| Breakpoint {
+            bank: Some(bank),
+            ..Breakpoint::new(addr)
+        };
+        // Bank 0 is always mapped at 0100.
+        assert!(!in_bank(0x0100, 1).matches(&cpu(0x0100, 0), 1));
+        assert!(in_bank(0x0100, 0).matches(&cpu(0x0100, 0), 1));
+        assert!(in_bank(0x4000, 1).matches(&cpu(0x4000, 0), 1));
+        assert!(!in_bank(0x4000, 1).matches(&cpu(0x4000, 0), 2));
+        assert!(Breakpoint::new(0x4000).matches(&cpu(0x4000, 0), 2));
+        // The bank is ignored outside of ROM.
+        assert!(in_bank(0xC000, 1).matches(&cpu(0xC000, 0), 2));
+        assert!(!Breakpoint::new(0x4000).matches(&cpu(0x4001, 0), 1));
+    }
+
+    #[test]
+    fn breakpoint_condition() {
+        let breakpoint = |comparison| Breakpoint {
+            condition: Some(Condition {
+                register: Register::A,
+                comparison,
+                value: 0x80,
+            }),
+            ..Breakpoint::new(0x0100)
+        };
+        for (comparison, a, expected) in [
+            (Comparison::Equal, 0x80, true),
+            (Comparison::Equal, 0x81, false),
+            (Comparison::NotEqual, 0x81, true),
+            (Comparison::Less, 0x80, false),
+            (Comparison::LessOrEqual, 0x80, true),
+            (Comparison::Greater, 0x81, true),
+            (Comparison::GreaterOrEqual, 0x7F, false),
+        ] {
+            assert_eq!(
+                breakpoint(comparison).matches(&cpu(0x0100, a), 1),
+                expected,
+                "{:?} {:02X}",
+                comparison,
+                a
+            );
+        }
+    }
+
+    #[test]
+    fn remove_breakpoint() {
+        let mut debugger = Debugger::default();
+        let in_bank = Breakpoint {
+            bank: Some(1),
+            ..Breakpoint::new(0x4000)
+        };
+        debugger.add_breakpoint(Breakpoint::new(0x4000));
+        debugger.add_breakpoint(in_bank);
+        debugger.add_breakpoint(in_bank);
+        assert_eq!(debugger.get_breakpoints().len(), 2);
+        assert!(debugger.remove_breakpoint(0x4000, Some(1)));
+        assert!(!debugger.remove_breakpoint(0x4000, Some(1)));
+        assert_eq!(debugger.get_breakpoints(), [Breakpoint::new(0x4000)]);
+        assert!(debugger.has_breakpoint(&cpu(0x4000, 0), 2));
+        debugger.clear_breakpoints();
+        assert!(!debugger.has_breakpoint(&cpu(0x4000, 0), 2));
+    }
+
+    #[test]
+    fn change_watchpoints() {
+        let mut debugger = Debugger::default();
+        debugger.add_change(0xFF44, 0x00);
+        debugger.add_change(0xC000, 0x00);
+        assert_eq!(debugger.check_changes(|_| 0x00), None);
+        // The first change is reported, each change only once.
+        assert_eq!(debugger.check_changes(|_| 0x01), Some((0xFF44, 0x01)));
+        assert_eq!(debugger.check_changes(|_| 0x01), None);
+        assert_eq!(
+            debugger.check_changes(|addr| if addr == 0xC000 { 0x02 } else { 0x01 }),
+            Some((0xC000, 0x02))
+        );
+        assert!(debugger.remove_change(0xFF44));
+        assert_eq!(debugger.get_changes().collect::<Vec<_>>(), [0xC000]);
+    }
+
+    #[test]
+    fn read_and_write_watchpoints() {
+        let watch_point = |addr, kind| Watchpoint { addr, kind };
+        let mut watch = Watch::default();
+        watch.add(0xC000, WatchKind::Read);
+        watch.add(0xC001, WatchKind::Write);
+        watch.read(0xC001, 0x01);
+        watch.write(0xC000, 0x01);
+        assert_eq!(watch.take(), None);
+        // The first hit is taken, the others are forgotten.
+        watch.write(0xC001, 0x02);
+        watch.read(0xC000, 0x03);
+        assert_eq!(
+            watch.take(),
+            Some((watch_point(0xC001, WatchKind::Write), 0x02))
+        );
+        assert_eq!(watch.take(), None);
+        watch.read(0xC000, 0x03);
+        watch.discard_reads();
+        assert_eq!(watch.take(), None);
+        assert!(watch.remove(0xC000, WatchKind::Read));
+        assert!(!watch.remove(0xC000, WatchKind::Change));
+        assert_eq!(
+            watch.get().collect::<Vec<_>>(),
+            [watch_point(0xC001, WatchKind::Write)]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// GameboyError represents the errors that can occur while loading a ROM,
+/// saving cartridge data or recording.
+#[derive(Debug)]
+pub enum GameboyError {
+    /// The cartridge type (header byte 0147) is not supported.
+    UnsupportedCartridgeType(u8),
+    /// The ROM size code (header byte 0148) is not valid.
+    UnsupportedRomSize(u8),
+    /// The RAM size code (header byte 0149) is not valid.
+    UnsupportedRamSize(u8),
+    /// The ROM is smaller than the header (or size code) requires.
+    TruncatedRom { expected: usize, actual: usize },
+    /// The ROM is larger than the size specified in the header.
+    RomTooLarge { expected: usize, actual: usize },
+    /// The Nintendo logo in the header (0104-0133) is incorrect.
+    InvalidNintendoLogo,
+    /// The header checksum (014D) does not match the header.
+    InvalidHeaderChecksum,
+    /// The boot ROM size does not match the hardware model, DMG boot ROMs are 256 bytes and
+    /// CGB boot ROMs are 2304 bytes.
+    InvalidBootRomSize { expected: usize, actual: usize },
+    /// Reading or writing a save file failed.
+    SaveIo { path: PathBuf, error: io::Error },
+    /// The save file does not match the RAM size of the cartridge.
+    InvalidSaveSize {
+        path: PathBuf,
+        expected: usize,
+        actual: usize,
+    },
+    /// Writing a recording failed.
+    RecordingIo { path: PathBuf, error: io::Error },
+}
+
+impl fmt::Display for GameboyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameboyError::UnsupportedCartridgeType(byte) => {
+                write!(f, "unsupported cartridge type {:#04X?}", byte)
+            }
+            GameboyError::UnsupportedRomSize(byte) => {
+                write!(f, "unsupported rom size {:#04X?}", byte)
+            }
+            GameboyError::UnsupportedRamSize(byte) => {
+                write!(f, "unsupported ram size {:#04X?}", byte)
+            }
+            GameboyError::TruncatedRom { expected, actual } => write!(
+                f,
+                "rom is truncated (expected: {} bytes, actual: {} bytes)",
+                expected, actual
+            ),
+            GameboyError::RomTooLarge { expected, actual } => write!(
+                f,
+                "rom size more than max (max: {} bytes, actual: {} bytes)",
+                expected, actual
+            ),
+            GameboyError::InvalidNintendoLogo => write!(f, "could not validate nintendo logo"),
+            GameboyError::InvalidHeaderChecksum => write!(f, "could not validate header checksum"),
+            GameboyError::InvalidBootRomSize { expected, actual } => write!(
+                f,
+                "invalid boot rom size (expected: {} bytes, actual: {} bytes)",
+                expected, actual
+            ),
+            GameboyError::SaveIo { path, error } => {
+                write!(
+                    f,
+                    "failed to access save file '{}': {}",
+                    path.display(),
+                    error
+                )
+            }
+            GameboyError::InvalidSaveSize {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "invalid save file size '{}' (expected: {} bytes, actual: {} bytes)",
+                path.display(),
+                expected,
+                actual
+            ),
+            GameboyError::RecordingIo { path, error } => {
+                write!(
+                    f,
+                    "failed to write recording '{}': {}",
+                    path.display(),
+                    error
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GameboyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GameboyError::SaveIo { error, .. } | GameboyError::RecordingIo { error, .. } => {
+                Some(error)
+            }
+            _ => None,
+        }
+    }
+}
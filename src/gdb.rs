@@ -0,0 +1,610 @@
+// GDB Remote Serial Protocol
+// A GDB stub lets a client using the GDB remote protocol (GDB, LLDB or an IDE) debug the Gameboy over TCP. Packets
+// are sent as $<data>#<checksum>, where the checksum is the sum of the data bytes modulo 256 in hex. Each packet is
+// acknowledged with + (or - to request it again) until the client switches to no acknowledgment mode. A single
+// 0x03 byte (Ctrl+C) interrupts the Gameboy while it is running.
+// The registers are sent as 16 bit little endian values in the order AF, BC, DE, HL, SP, PC (register numbers 0-5).
+// The supported packets are:
+//   ?                   Why the Gameboy stopped
+//   g, G                Read or write all registers
+//   p n, P n=v          Read or write a single register
+//   m addr,len          Read memory
+//   M addr,len:data     Write memory
+//   Z0/z0 addr,kind     Insert or remove a breakpoint (Z1 is treated the same)
+//   Z2-4/z2-4 addr,len  Insert or remove a write, read or access watchpoint
+//   s [addr], c [addr]  Single step or continue, optionally from addr
+//   D, k                Detach or kill, which both disconnect the client and let the Gameboy run freely
+// Memory is accessed as currently mapped, without the restrictions of the PPU modes or OAM DMA.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::{Breakpoint, Gameboy, Register, StopReason, WatchKind, Watchpoint};
+
+const INTERRUPT: u8 = 0x03;
+// The largest packet accepted, sent to the client in the qSupported reply.
+const PACKET_SIZE: usize = 0x1000;
+// Signals used in stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+// The registers in the order of their register numbers.
+const REGISTERS: [Register; 6] = [
+    Register::AF,
+    Register::BC,
+    Register::DE,
+    Register::HL,
+    Register::SP,
+    Register::PC,
+];
+
+/// GdbStub lets a GDB remote protocol client debug a Gameboy over a localhost TCP port.
+///
+/// The stub is driven by calling `run_frame` in place of `Gameboy::run_frame`, which
+/// handles the packets of the client and runs a frame when the client lets the Gameboy run.
+/// Without a client the Gameboy runs freely, it is stopped when a client connects.
+///
+/// ```no_run
+/// # let mut gameboy = gameboyr::Gameboy::new(vec![], "", Default::default()).unwrap();
+/// let mut stub = gameboyr::GdbStub::bind(2345).unwrap();
+/// loop {
+///     stub.run_frame(&mut gameboy).unwrap();
+/// }
+/// ```
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    // Bytes received which do not form a complete packet yet.
+    buffer: Vec<u8>,
+    running: bool,
+    no_ack: bool,
+    // The breakpoints and watchpoints inserted by the client, removed when it disconnects. Those the host had
+    // already set are not recorded, so they are kept.
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl GdbStub {
+    /// Listen for a client on localhost at `port`, 0 picks any free port.
+    pub fn bind(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+            buffer: Vec::new(),
+            running: true,
+            no_ack: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        })
+    }
+
+    /// Get the port the stub is listening on.
+    pub fn get_port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    /// Check if a client is connected.
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Handle the packets received from the client, then run a frame (see
+    /// `Gameboy::run_to_frame`) unless the client stopped the Gameboy. A client which
+    /// disconnects (or fails) removes its breakpoints and watchpoints and lets the Gameboy
+    /// run freely. Errors are only returned when accepting a client fails.
+    pub fn run_frame(&mut self, gameboy: &mut Gameboy) -> io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some(stream);
+                    self.buffer.clear();
+                    self.running = false;
+                    self.no_ack = false;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if self.client.is_some() && self.handle_client(gameboy).is_err() {
+            self.disconnect(gameboy);
+        }
+        if !self.running {
+            return Ok(());
+        }
+        if self.client.is_none() {
+            gameboy.run_frame();
+            return Ok(());
+        }
+        let reason = gameboy.run_to_frame();
+        if reason != StopReason::Frame {
+            self.running = false;
+            if self.send_packet(&stop_reply(reason)).is_err() {
+                self.disconnect(gameboy);
+            }
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self, gameboy: &mut Gameboy) {
+        self.client = None;
+        self.running = true;
+        for addr in std::mem::take(&mut self.breakpoints) {
+            remove_breakpoint(gameboy, addr);
+        }
+        for watchpoint in std::mem::take(&mut self.watchpoints) {
+            gameboy.remove_watchpoint(watchpoint);
+        }
+    }
+
+    // Receive the bytes available and handle the complete packets.
+    fn handle_client(&mut self, gameboy: &mut Gameboy) -> io::Result<()> {
+        let Some(stream) = self.client.as_mut() else {
+            return Ok(());
+        };
+        let mut data = [0; 1024];
+        loop {
+            match stream.read(&mut data) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buffer.extend_from_slice(&data[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        while self.client.is_some()
+            && let Some(&byte) = self.buffer.first()
+        {
+            match byte {
+                INTERRUPT => {
+                    self.buffer.remove(0);
+                    if self.running {
+                        self.running = false;
+                        self.send_packet(&format!("S{:02x}", SIGINT))?;
+                    }
+                }
+                b'$' => {
+                    let Some(end) = self.buffer.iter().position(|&b| b == b'#') else {
+                        break;
+                    };
+                    if self.buffer.len() < end + 3 {
+                        break;
+                    }
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                    if checksum != Some(checksum_of(data)) {
+                        self.send_raw(b"-")?;
+                        continue;
+                    }
+                    if !self.no_ack {
+                        self.send_raw(b"+")?;
+                    }
+                    // Packets are plain ASCII, anything else is replied to with an error
+                    let reply = match std::str::from_utf8(data) {
+                        Ok(data) if data.is_ascii() => self.handle_packet(gameboy, data),
+                        _ => Some(error()),
+                    };
+                    if let Some(reply) = reply {
+                        self.send_packet(&reply)?;
+                    }
+                }
+                // Acknowledgments (and anything else outside of a packet) are ignored
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+        if self.buffer.len() > PACKET_SIZE * 2 {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        Ok(())
+    }
+
+    // Handle a packet, returning the reply. Continuing does not reply until the Gameboy stops.
+    fn handle_packet(&mut self, gameboy: &mut Gameboy, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => REGISTERS
+                .iter()
+                .map(|register| hex_u16(register.get(&gameboy.cpu.registers)))
+                .collect(),
+            "G" => match parse_registers(args) {
+                Some(values) => {
+                    for (register, value) in REGISTERS.iter().zip(values) {
+                        register.set(&mut gameboy.cpu.registers, value);
+                    }
+                    String::from("OK")
+                }
+                None => error(),
+            },
+            "p" => match parse_register(args) {
+                Some(register) => hex_u16(register.get(&gameboy.cpu.registers)),
+                None => error(),
+            },
+            "P" => {
+                let register = args.split_once('=').and_then(|(register, value)| {
+                    Some((parse_register(register)?, parse_u16_le(value)?))
+                });
+                match register {
+                    Some((register, value)) => {
+                        register.set(&mut gameboy.cpu.registers, value);
+                        String::from("OK")
+                    }
+                    None => error(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    let mmu = gameboy.mmu.borrow();
+                    (0..len)
+                        .map(|i| format!("{:02x}", mmu.get_bus_byte(addr.wrapping_add(i))))
+                        .collect()
+                }
+                None => error(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let bytes = parse_hex(data)?;
+                    (bytes.len() == usize::from(len)).then_some((addr, bytes))
+                });
+                match write {
+                    Some((addr, bytes)) => {
+                        let mut mmu = gameboy.mmu.borrow_mut();
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            mmu.set_bus_byte(addr.wrapping_add(i as u16), byte);
+                        }
+                        String::from("OK")
+                    }
+                    None => error(),
+                }
+            }
+            "Z" | "z" => match parse_point(args) {
+                Some((kind, addr, len)) => {
+                    if self.set_point(gameboy, command == "Z", kind, addr, len) {
+                        String::from("OK")
+                    } else {
+                        String::new()
+                    }
+                }
+                None => error(),
+            },
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match parse_u16(args) {
+                        Some(addr) => gameboy.cpu.registers.pc = addr,
+                        None => return Some(error()),
+                    }
+                }
+                if command == "c" {
+                    self.running = true;
+                    return None;
+                }
+                stop_reply(gameboy.step())
+            }
+            "D" | "k" => {
+                // The reply is sent before disconnecting, a kill is not replied to
+                if command == "D" {
+                    let _ = self.send_packet("OK");
+                }
+                self.disconnect(gameboy);
+                return None;
+            }
+            "H" => String::from("OK"),
+            "q" if args.starts_with("Supported") => {
+                format!("PacketSize={:x};QStartNoAckMode+", PACKET_SIZE)
+            }
+            "q" if args == "Attached" => String::from("1"),
+            "q" if args == "C" => String::from("QC1"),
+            "q" if args == "fThreadInfo" => String::from("m1"),
+            "q" if args == "sThreadInfo" => String::from("l"),
+            "Q" if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                String::from("OK")
+            }
+            // An empty reply tells the client the packet is not supported
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // Insert or remove a breakpoint (types 0 and 1) or watchpoint (types 2-4), returning false for other types.
+    // Only the points inserted by the client are removed.
+    fn set_point(
+        &mut self,
+        gameboy: &mut Gameboy,
+        insert: bool,
+        kind: u8,
+        addr: u16,
+        len: u16,
+    ) -> bool {
+        if kind <= 1 {
+            let inserted = self.breakpoints.iter().position(|&other| other == addr);
+            match inserted {
+                None if insert && !gameboy.get_breakpoints().contains(&Breakpoint::new(addr)) => {
+                    gameboy.add_breakpoint(Breakpoint::new(addr));
+                    self.breakpoints.push(addr);
+                }
+                Some(i) if !insert => {
+                    remove_breakpoint(gameboy, addr);
+                    self.breakpoints.remove(i);
+                }
+                _ => {}
+            }
+            return true;
+        }
+        let kinds: &[WatchKind] = match kind {
+            2 => &[WatchKind::Write],
+            3 => &[WatchKind::Read],
+            4 => &[WatchKind::Read, WatchKind::Write],
+            _ => return false,
+        };
+        let watchpoints = gameboy.get_watchpoints();
+        for i in 0..len.max(1) {
+            for &kind in kinds {
+                let watchpoint = Watchpoint {
+                    addr: addr.wrapping_add(i),
+                    kind,
+                };
+                let inserted = self
+                    .watchpoints
+                    .iter()
+                    .position(|&other| other == watchpoint);
+                match inserted {
+                    None if insert && !watchpoints.contains(&watchpoint) => {
+                        gameboy.add_watchpoint(watchpoint);
+                        self.watchpoints.push(watchpoint);
+                    }
+                    Some(i) if !insert => {
+                        gameboy.remove_watchpoint(watchpoint);
+                        self.watchpoints.remove(i);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.send_raw(packet.as_bytes())
+    }
+
+    // The stream is non-blocking for reading, writes wait until everything has been sent.
+    fn send_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        let Some(stream) = self.client.as_mut() else {
+            return Ok(());
+        };
+        stream.set_nonblocking(false)?;
+        let result = stream.write_all(data);
+        stream.set_nonblocking(true)?;
+        result
+    }
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint(watchpoint, _) if watchpoint.kind != WatchKind::Change => {
+            let kind = match watchpoint.kind {
+                WatchKind::Read => "rwatch",
+                _ => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, watchpoint.addr)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex_u16(value: u16) -> String {
+    let [low, high] = value.to_le_bytes();
+    format!("{:02x}{:02x}", low, high)
+}
+
+fn parse_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) || !data.is_ascii() {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Parse a little endian 16 bit register value.
+fn parse_u16_le(data: &str) -> Option<u16> {
+    match parse_hex(data)?.as_slice() {
+        &[low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+fn parse_u16(data: &str) -> Option<u16> {
+    u16::from_str_radix(data, 16).ok()
+}
+
+fn parse_registers(data: &str) -> Option<Vec<u16>> {
+    if data.len() != REGISTERS.len() * 4 || !data.is_ascii() {
+        return None;
+    }
+    (0..REGISTERS.len())
+        .map(|i| parse_u16_le(&data[i * 4..i * 4 + 4]))
+        .collect()
+}
+
+// Parse a register number.
+fn parse_register(data: &str) -> Option<Register> {
+    let register = usize::from_str_radix(data, 16).ok()?;
+    REGISTERS.get(register).copied()
+}
+
+// Parse "addr,len".
+fn parse_range(data: &str) -> Option<(u16, u16)> {
+    let (addr, len) = data.split_once(',')?;
+    Some((parse_u16(addr)?, parse_u16(len)?))
+}
+
+// Parse "type,addr,kind" of a Z or z packet.
+fn parse_point(data: &str) -> Option<(u8, u16, u16)> {
+    let (kind, range) = data.split_once(',')?;
+    let (addr, len) = parse_range(range)?;
+    Some((kind.parse().ok()?, addr, len))
+}
+
+// Remove the breakpoint without a bank or condition at `addr`, keeping the others at `addr`.
+fn remove_breakpoint(gameboy: &mut Gameboy, addr: u16) {
+    let others: Vec<Breakpoint> = gameboy
+        .get_breakpoints()
+        .iter()
+        .filter(|&&breakpoint| {
+            breakpoint.addr == addr
+                && breakpoint.bank.is_none()
+                && breakpoint != Breakpoint::new(addr)
+        })
+        .copied()
+        .collect();
+    gameboy.remove_breakpoint(addr, None);
+    for breakpoint in others {
+        gameboy.add_breakpoint(breakpoint);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    use super::{GdbStub, checksum_of};
+    use crate::{Breakpoint, Gameboy, LoadOptions, WatchKind, Watchpoint};
+
+    // Send a packet and wait for its acknowledgment.
+    fn send(stream: &mut TcpStream, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        stream.write_all(packet.as_bytes()).unwrap();
+        let mut ack = [0];
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+    }
+
+    // Receive a packet, acknowledging it.
+    fn receive(stream: &mut TcpStream) -> String {
+        let mut packet = Vec::new();
+        let mut byte = [0];
+        while !packet.ends_with(b"#") {
+            stream.read_exact(&mut byte).unwrap();
+            if !packet.is_empty() || byte[0] == b'$' {
+                packet.push(byte[0]);
+            }
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        let data = &packet[1..packet.len() - 1];
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", checksum_of(data))
+        );
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(data.to_vec()).unwrap()
+    }
+
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        send(stream, data);
+        receive(stream)
+    }
+
+    #[test]
+    fn session() {
+        let mut rom = vec![0x00; 0x8000];
+        // 0100: INC A; JR -3
+        rom[0x0100..0x0103].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+        let mut gameboy = Gameboy::new(rom, "", LoadOptions::skip_checks()).unwrap();
+        let mut stub = GdbStub::bind(0).unwrap();
+        let port = stub.get_port().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            assert_eq!(request(&mut stream, "?"), "S05");
+            assert!(request(&mut stream, "qSupported:swbreak+").starts_with("PacketSize="));
+            assert!(request(&mut stream, "g").ends_with("feff0001"));
+            assert_eq!(request(&mut stream, "m100,3"), "3c18fd");
+            assert_eq!(request(&mut stream, "Mc000,2:abcd"), "OK");
+            assert_eq!(request(&mut stream, "mc000,2"), "abcd");
+            assert_eq!(request(&mut stream, "P0=0012"), "OK");
+            assert_eq!(request(&mut stream, "p0"), "0012");
+            // Run into a breakpoint at the JR
+            assert_eq!(request(&mut stream, "Z0,101,1"), "OK");
+            assert_eq!(request(&mut stream, "c"), "S05");
+            assert_eq!(request(&mut stream, "p5"), "0101");
+            assert_eq!(request(&mut stream, "p0"), "0013");
+            assert_eq!(request(&mut stream, "s"), "S05");
+            assert_eq!(request(&mut stream, "p5"), "0001");
+            assert_eq!(request(&mut stream, "z0,101,1"), "OK");
+            // Write watchpoint
+            assert_eq!(request(&mut stream, "Z2,ff80,1"), "OK");
+            assert_eq!(request(&mut stream, "Mfff0,2:e080"), "OK");
+            assert_eq!(request(&mut stream, "cfff0"), "T05watch:ff80;");
+            assert_eq!(request(&mut stream, "z2,ff80,1"), "OK");
+            // Continue forever, until interrupted
+            send(&mut stream, "c100");
+            stream.write_all(&[0x03]).unwrap();
+            assert_eq!(receive(&mut stream), "S02");
+            assert_eq!(request(&mut stream, "vMustReplyEmpty"), "");
+            // Non-ASCII packets are rejected
+            assert_eq!(request(&mut stream, "\u{e9}"), "E01");
+            assert_eq!(
+                request(&mut stream, "G\u{20ac}000000000000000000000"),
+                "E01"
+            );
+            assert_eq!(request(&mut stream, "D"), "OK");
+        });
+        while !client.is_finished() {
+            stub.run_frame(&mut gameboy).unwrap();
+        }
+        client.join().unwrap();
+        assert!(gameboy.get_breakpoints().is_empty());
+    }
+
+    #[test]
+    fn detach_keeps_host_points() {
+        let mut gameboy = Gameboy::new(vec![0x00; 0x8000], "", LoadOptions::skip_checks()).unwrap();
+        let host_breakpoint = Breakpoint::new(0x0150);
+        let host_watchpoint = Watchpoint {
+            addr: 0xC000,
+            kind: WatchKind::Write,
+        };
+        gameboy.add_breakpoint(host_breakpoint);
+        gameboy.add_watchpoint(host_watchpoint);
+        let mut stub = GdbStub::bind(0).unwrap();
+        let port = stub.get_port().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            assert_eq!(request(&mut stream, "?"), "S05");
+            // The points are left inserted when detaching, including those the host also set.
+            assert_eq!(request(&mut stream, "Z0,150,1"), "OK");
+            assert_eq!(request(&mut stream, "Z0,200,1"), "OK");
+            assert_eq!(request(&mut stream, "Z4,c000,2"), "OK");
+            // Removing a point the client did not insert keeps the host's.
+            assert_eq!(request(&mut stream, "z0,150,1"), "OK");
+            assert_eq!(request(&mut stream, "D"), "OK");
+        });
+        while !client.is_finished() {
+            stub.run_frame(&mut gameboy).unwrap();
+        }
+        client.join().unwrap();
+        assert_eq!(gameboy.get_breakpoints(), &[host_breakpoint]);
+        assert_eq!(gameboy.get_watchpoints(), vec![host_watchpoint]);
+    }
+}
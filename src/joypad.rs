@@ -30,6 +30,7 @@
 
 use crate::memory::Memory;
 use crate::mmu::InterruptFlag;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 #[derive(Debug, Copy, Clone)]
 pub enum JoypadKey {
@@ -103,6 +104,21 @@ impl Memory for Joypad {
     }
 }
 
+impl Stateful for Joypad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.matrix);
+        writer.write_u8(self.select);
+        writer.write_u8(self.interrupt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.matrix = reader.read_u8()?;
+        self.select = reader.read_u8()?;
+        self.interrupt = reader.read_u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::InterruptFlag;
@@ -3,21 +3,47 @@
 //! Gameboy R provides a simple to use implmentation of a Gameboy / Gameboy Color.
 
 mod apu;
+mod avi;
 mod cartridges;
 mod clock;
 mod cpu;
+mod debugger;
+mod error;
+mod gdb;
 mod joypad;
 mod memory;
 mod mmu;
+mod pacer;
+mod png;
 mod ppu;
+mod rewind;
 mod serial;
+mod state;
 mod timer;
 
 use std::cell::RefCell;
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::memory::Memory;
+use crate::state::{StateReader, StateWriter, Stateful};
+
+pub use crate::apu::{AudioChannel, AudioSink, BufferSink, CpalSink};
+pub use crate::cpu::CpuState;
+pub use crate::cpu::disassembler::{Instruction, disassemble, disassemble_rom_bank};
+pub use crate::debugger::{
+    Breakpoint, Comparison, Condition, Register, StopReason, WatchKind, Watchpoint,
+};
+pub use crate::error::GameboyError;
+pub use crate::gdb::GdbStub;
+pub use crate::memory::Memory;
+pub use crate::mmu::MemoryRegion;
+pub use crate::pacer::Pacer;
+pub use crate::ppu::{
+    ColorCorrection, DmgPalette, FrameBlending, Image, OamEntry, Pixel, Renderer,
+};
+pub use crate::rewind::RewindOptions;
+pub use crate::state::StateError;
 
 /// Dimensions represent length and width of a screen.
 pub struct Dimensions {
@@ -25,6 +51,74 @@ pub struct Dimensions {
     pub height: usize,
 }
 
+/// HardwareModel represents each Gameboy model that can be emulated.
+///
+/// The model determines the initial register values and whether the CGB only hardware
+/// (color palettes, VRAM/WRAM banking, double speed, HDMA) is available. A CGB or AGB runs
+/// DMG games in compatibility mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareModel {
+    /// Original Gameboy
+    Dmg,
+    /// Gameboy Pocket
+    Mgb,
+    /// Super Gameboy
+    Sgb,
+    /// Gameboy Color
+    Cgb,
+    /// Gameboy Advance
+    Agb,
+}
+
+impl HardwareModel {
+    // Whether the model has the CGB only hardware.
+    fn is_cgb(self) -> bool {
+        matches!(self, HardwareModel::Cgb | HardwareModel::Agb)
+    }
+
+    // The size of the boot ROM used by the model.
+    fn boot_rom_size(self) -> usize {
+        if self.is_cgb() {
+            mmu::CGB_BOOT_ROM_SIZE
+        } else {
+            mmu::DMG_BOOT_ROM_SIZE
+        }
+    }
+}
+
+/// LoadOptions control how a ROM is loaded by `Gameboy::new`.
+///
+/// ```
+/// let options = gameboyr::LoadOptions {
+///     skip_checksum_check: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Skip verifying the Nintendo logo in the cartridge header.
+    pub skip_logo_check: bool,
+    /// Skip verifying the cartridge header checksum.
+    pub skip_checksum_check: bool,
+    /// A DMG (256 bytes) or CGB (2304 bytes) boot ROM to run before the cartridge. When not
+    /// provided the Gameboy starts in the state the boot ROM would have left it in.
+    pub boot_rom: Option<Vec<u8>>,
+    /// The hardware model to emulate. When not provided the model is chosen based on the
+    /// boot ROM, or the CGB flag in the cartridge header.
+    pub model: Option<HardwareModel>,
+}
+
+impl LoadOptions {
+    /// Create LoadOptions which skip all cartridge header checks.
+    pub fn skip_checks() -> LoadOptions {
+        LoadOptions {
+            skip_logo_check: true,
+            skip_checksum_check: true,
+            ..Default::default()
+        }
+    }
+}
+
 /// GameboyButton represents each possibly button available.
 ///
 /// This enum is used to provide users with a way to easily map any
@@ -61,47 +155,544 @@ impl From<GameboyButton> for joypad::JoypadKey {
 /// The Gameboy functionality is provided to the user through a set of
 /// consise and useful helper functions. The user can not directly interact
 /// with the Gameboy itself.
+///
+/// The Gameboy runs as fast as possible, use a `Pacer` to run it at the speed
+/// of real hardware.
 pub struct Gameboy {
     mmu: Rc<RefCell<mmu::Mmu>>,
-    cpu: cpu::RealTimeCpu,
+    cpu: cpu::Cpu,
+    debugger: debugger::Debugger,
+    rewind: Option<rewind::Rewind>,
+    video: Option<VideoRecording>,
+    // The frame during which `can_take_input` last returned true.
+    input_frame: u64,
+}
+
+struct VideoRecording {
+    path: PathBuf,
+    writer: avi::AviWriter,
+    // Dots run since the last recorded frame.
+    dots: u32,
 }
 
 impl Gameboy {
-    /// Create a new Gameboy by providing ROM data, a save path, and whether to skip checks.
-    /// When the save path contains an existing save, that data will be loaded.
-    pub fn new(rom: Vec<u8>, save_path: impl AsRef<Path>, skip_checks: bool) -> Gameboy {
-        let cartridge = cartridges::new(rom, save_path, skip_checks);
-        let cartridge_mode = cartridge.get_mode();
-        let mmu = Rc::new(RefCell::new(mmu::Mmu::new(cartridge)));
-        let cpu = cpu::RealTimeCpu::new(cartridge_mode, mmu.clone());
-        Gameboy { mmu, cpu }
+    /// Create a new Gameboy by providing ROM data, a save path, and the options to load with.
+    /// When the save path contains an existing save, that data will be loaded. An error is
+    /// returned when the ROM is malformed or unsupported, or the save could not be read.
+    pub fn new(
+        rom: Vec<u8>,
+        save_path: impl AsRef<Path>,
+        options: LoadOptions,
+    ) -> Result<Gameboy, GameboyError> {
+        let cartridge = cartridges::new(rom, save_path, &options)?;
+        let model = match (options.model, &options.boot_rom) {
+            (Some(model), _) => model,
+            (None, Some(boot_rom)) if boot_rom.len() == mmu::CGB_BOOT_ROM_SIZE => {
+                HardwareModel::Cgb
+            }
+            (None, Some(_)) => HardwareModel::Dmg,
+            (None, None) => match cartridge.get_mode() {
+                cartridges::CartridgeMode::Gb => HardwareModel::Dmg,
+                cartridges::CartridgeMode::Gbc => HardwareModel::Cgb,
+            },
+        };
+        if let Some(boot_rom) = &options.boot_rom
+            && boot_rom.len() != model.boot_rom_size()
+        {
+            return Err(GameboyError::InvalidBootRomSize {
+                expected: model.boot_rom_size(),
+                actual: boot_rom.len(),
+            });
+        }
+        let has_boot_rom = options.boot_rom.is_some();
+        let mmu = mmu::Mmu::new(cartridge, model, options.boot_rom);
+        let mode = mmu.ppu.get_mode();
+        let mmu = Rc::new(RefCell::new(mmu));
+        let cpu = if has_boot_rom {
+            cpu::Cpu::power_on(mmu.clone())
+        } else {
+            cpu::Cpu::new(model, mode, mmu.clone())
+        };
+        Ok(Gameboy {
+            mmu,
+            cpu,
+            debugger: debugger::Debugger::default(),
+            rewind: None,
+            video: None,
+            input_frame: 0,
+        })
     }
 
     // Shutdown the Gameboy.
-    pub fn shutdown(&mut self) {
-        self.save();
+    pub fn shutdown(&mut self) -> Result<(), GameboyError> {
+        let recording = self.stop_wav_recording();
+        let video = self.stop_video_recording();
+        self.save()?;
+        recording.and(video)
     }
 
-    // Attempt to enable audio on the Gameboy. Returning true if successful.
+    // Attempt to enable audio on the Gameboy using the default output device. Returning true if successful.
     pub fn try_enable_audio(&mut self) -> bool {
-        let apu = apu::Apu::new();
-        self.mmu.borrow_mut().apu = apu;
-        self.mmu.borrow().apu.is_some()
+        match CpalSink::new() {
+            Some(sink) => {
+                self.set_audio_sink(sink);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Send the audio produced by the Gameboy to `sink`, replacing any previous sink.
+    /// Audio is always emulated, a sink only decides what happens to the output.
+    pub fn set_audio_sink(&mut self, sink: impl AudioSink + 'static) {
+        self.mmu.borrow_mut().apu.set_sink(Some(Box::new(sink)));
+    }
+
+    /// Stop sending audio to the current sink, if any.
+    pub fn remove_audio_sink(&mut self) {
+        self.mmu.borrow_mut().apu.set_sink(None);
+    }
+
+    /// Mute or unmute a sound channel. This only affects the audio output, the
+    /// emulated hardware (including the NR52 status bits) is unchanged.
+    pub fn set_channel_enabled(&mut self, channel: AudioChannel, enabled: bool) {
+        self.mmu
+            .borrow_mut()
+            .apu
+            .set_channel_enabled(channel, enabled);
+    }
+
+    /// Check if a sound channel is enabled (not muted).
+    pub fn is_channel_enabled(&self, channel: AudioChannel) -> bool {
+        self.mmu.borrow().apu.is_channel_enabled(channel)
+    }
+
+    /// Only output the given sound channel, muting the others. Passing None enables all channels.
+    pub fn solo_channel(&mut self, channel: Option<AudioChannel>) {
+        let apu = &mut self.mmu.borrow_mut().apu;
+        for c in [
+            AudioChannel::Square1,
+            AudioChannel::Square2,
+            AudioChannel::Wave,
+            AudioChannel::Noise,
+        ] {
+            apu.set_channel_enabled(c, channel.is_none_or(|channel| channel == c));
+        }
+    }
+
+    /// Set the master volume of the audio output, from 0.0 (silent) to 1.0 (full volume).
+    pub fn set_volume(&mut self, volume: f32) {
+        self.mmu.borrow_mut().apu.set_volume(volume);
+    }
+
+    /// Get the master volume of the audio output.
+    pub fn get_volume(&self) -> f32 {
+        self.mmu.borrow().apu.get_volume()
+    }
+
+    /// Start recording audio to a WAV file at `path`, stopping any previous recording.
+    /// When `stems` is set each channel (square1, square2, wave and noise) is also
+    /// recorded before mixing, next to `path` (for example `music.wav` gives
+    /// `music.square1.wav`). The recording uses the sample rate of the current audio
+    /// sink (44100 without a sink), so the sink should not be changed while recording.
+    pub fn start_wav_recording<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        stems: bool,
+    ) -> Result<(), GameboyError> {
+        self.mmu
+            .borrow_mut()
+            .apu
+            .start_recording(path.as_ref(), stems)
+    }
+
+    /// Stop recording audio, finishing the WAV files.
+    pub fn stop_wav_recording(&mut self) -> Result<(), GameboyError> {
+        self.mmu.borrow_mut().apu.stop_recording()
+    }
+
+    /// Check if audio is being recorded.
+    pub fn is_wav_recording(&self) -> bool {
+        self.mmu.borrow().apu.is_recording()
+    }
+
+    /// Set the speed the Gameboy is run at relative to real hardware (see `Pacer::set_speed`),
+    /// 0.0 means unlimited. Audio is resampled to match, so it does not fall behind or run out.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.mmu.borrow_mut().apu.set_speed(speed);
+    }
+
+    /// Perform one step (including CPU, MMU, PPU), running a single instruction or handling an
+    /// interrupt. Returns `StopReason::Step`, unless the step hit a watchpoint or stopped at a
+    /// breakpoint.
+    pub fn step(&mut self) -> StopReason {
+        self.run_step();
+        self.check_debugger().unwrap_or(StopReason::Step)
+    }
+
+    /// Step over the instruction at PC: a CALL or RST runs until it returns, any other
+    /// instruction is a single `step`. Stops early when a breakpoint or watchpoint is hit,
+    /// or returns `StopReason::Frame` when the call has not returned by the end of the frame.
+    pub fn step_over(&mut self) -> StopReason {
+        let pc = self.cpu.registers.pc;
+        let op_code = self.mmu.borrow().get_bus_byte(pc);
+        let len = match op_code {
+            // CALL a16, CALL cc,a16
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+            // RST n
+            _ if op_code & 0xC7 == 0xC7 => 1,
+            _ => return self.step(),
+        };
+        if self.cpu.halted {
+            return self.step();
+        }
+        // A recursive call returns to the same address with less on the stack.
+        let sp = self.cpu.registers.sp;
+        let frame = self.mmu.borrow().ppu.frames;
+        let mut dots = 0;
+        loop {
+            if let Some(reason) = self.debug_step(&mut dots) {
+                return reason;
+            }
+            let registers = &self.cpu.registers;
+            if registers.pc == pc.wrapping_add(len) && registers.sp >= sp {
+                return StopReason::Step;
+            }
+            if self.has_frame_ended(frame, dots) {
+                return StopReason::Frame;
+            }
+        }
+    }
+
+    /// Run until the current routine returns (a RET or RETI popping the return address
+    /// of the routine from the stack). Stops early when a breakpoint or watchpoint is hit,
+    /// or returns `StopReason::Frame` when the routine has not returned by the end of the frame.
+    pub fn step_out(&mut self) -> StopReason {
+        let sp = self.cpu.registers.sp;
+        let frame = self.mmu.borrow().ppu.frames;
+        let mut dots = 0;
+        loop {
+            let op_code = self.mmu.borrow().get_bus_byte(self.cpu.registers.pc);
+            // RET cc, RET, RETI
+            let returning =
+                !self.cpu.halted && matches!(op_code, 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9);
+            if let Some(reason) = self.debug_step(&mut dots) {
+                return reason;
+            }
+            if returning && self.cpu.registers.sp > sp {
+                return StopReason::Step;
+            }
+            if self.has_frame_ended(frame, dots) {
+                return StopReason::Frame;
+            }
+        }
+    }
+
+    /// Run until the PPU enters V-Blank (see `run_frame`), returning `StopReason::Frame`.
+    /// Stops early when a breakpoint or watchpoint is hit.
+    pub fn run_to_frame(&mut self) -> StopReason {
+        self.run_frame_steps(true).1
+    }
+
+    /// Run until the PPU enters V-Blank, returning the number of CPU cycles run. When the
+    /// LCD is disabled this returns after the amount of time a frame would have taken.
+    /// Breakpoints and watchpoints are ignored, use `run_to_frame` to stop at them.
+    pub fn run_frame(&mut self) -> u32 {
+        self.run_frame_steps(false).0
+    }
+
+    // Run a frame, returning the number of CPU cycles run and why the frame stopped.
+    fn run_frame_steps(&mut self, debug: bool) -> (u32, StopReason) {
+        let frame = self.mmu.borrow().ppu.frames;
+        let mut cycles = 0;
+        let mut dots = 0;
+        while !self.has_frame_ended(frame, dots) {
+            let (cpu_cycles, ppu_cycles) = self.run_step();
+            cycles += cpu_cycles;
+            dots += ppu_cycles;
+            if debug && let Some(reason) = self.check_debugger() {
+                return (cycles, reason);
+            }
+        }
+        if self.rewind.as_mut().is_some_and(rewind::Rewind::tick) {
+            let snapshot = self.save_state();
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.push(snapshot);
+            }
+        }
+        (cycles, StopReason::Frame)
+    }
+
+    /// Read a byte from the memory map as currently mapped, without the restrictions the
+    /// PPU modes and OAM DMA place on the CPU. Reading does not hit read watchpoints.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.mmu.borrow().get_bus_byte(addr)
+    }
+
+    /// Write a byte to the memory map as currently mapped, without the restrictions the PPU
+    /// modes and OAM DMA place on the CPU. The write has the same effect as one made by the
+    /// CPU, so writing to ROM controls the MBC (use `poke_bank` to patch the ROM), but it
+    /// does not hit write watchpoints.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.mmu.borrow_mut().set_bus_byte(addr, value);
+    }
+
+    /// Get the number of banks of a region of memory.
+    pub fn get_bank_count(&self, region: MemoryRegion) -> usize {
+        self.mmu.borrow().get_bank_count(region)
+    }
+
+    /// Read the byte at `offset` in `bank` of a region, regardless of the selected bank.
+    /// Returns None when the bank does not exist or the offset is outside of it.
+    ///
+    /// ```no_run
+    /// # let gameboy = gameboyr::Gameboy::new(vec![], "", Default::default()).unwrap();
+    /// use gameboyr::MemoryRegion;
+    ///
+    /// // The byte read from 4010 when ROM bank 3 is selected.
+    /// let byte = gameboy.peek_bank(MemoryRegion::Rom, 3, 0x0010);
+    /// ```
+    pub fn peek_bank(&self, region: MemoryRegion, bank: usize, offset: u16) -> Option<u8> {
+        self.mmu.borrow().get_bank_byte(region, bank, offset)
+    }
+
+    /// Write the byte at `offset` in `bank` of a region, regardless of the selected bank.
+    /// Returns false when the bank does not exist or the offset is outside of it.
+    pub fn poke_bank(&mut self, region: MemoryRegion, bank: usize, offset: u16, value: u8) -> bool {
+        self.mmu
+            .borrow_mut()
+            .set_bank_byte(region, bank, offset, value)
+    }
+
+    /// Get a snapshot of the CPU registers and interrupt state.
+    pub fn get_cpu_state(&self) -> CpuState {
+        self.cpu.get_state()
+    }
+
+    /// Replace the CPU registers and interrupt state, for example with a modified snapshot
+    /// from `get_cpu_state`.
+    pub fn set_cpu_state(&mut self, state: CpuState) {
+        self.cpu.set_state(state);
     }
 
-    /// Perform one step (including CPU, MMU, PPU), returning the number of CPU cycles run.
-    pub fn step(&mut self) -> u32 {
-        if self.mmu.borrow().get_byte(self.cpu.cpu.registers.pc) == 0x10 {
+    /// Disassemble the instructions starting in `range` of the memory map, as currently
+    /// mapped. Reading the instructions does not hit read watchpoints.
+    pub fn disassemble(&self, range: RangeInclusive<u16>) -> Vec<Instruction> {
+        let mmu = self.mmu.borrow();
+        cpu::disassembler::disassemble_with(range, |addr| mmu.get_bus_byte(addr))
+    }
+
+    /// Add a breakpoint, stopping `step`, `step_over`, `step_out` and `run_to_frame`
+    /// before the instruction at its address is run.
+    ///
+    /// ```no_run
+    /// # let mut gameboy = gameboyr::Gameboy::new(vec![], "", Default::default()).unwrap();
+    /// use gameboyr::{Breakpoint, StopReason};
+    ///
+    /// // Stop at 4000 when ROM bank 3 is mapped.
+    /// gameboy.add_breakpoint(Breakpoint {
+    ///     bank: Some(3),
+    ///     ..Breakpoint::new(0x4000)
+    /// });
+    /// assert_eq!(gameboy.run_to_frame(), StopReason::Breakpoint(0x4000));
+    /// ```
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.debugger.add_breakpoint(breakpoint);
+    }
+
+    /// Remove the breakpoints at `addr` qualified by `bank`, returning whether any were removed.
+    pub fn remove_breakpoint(&mut self, addr: u16, bank: Option<usize>) -> bool {
+        self.debugger.remove_breakpoint(addr, bank)
+    }
+
+    /// Remove all breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+    }
+
+    /// Get the breakpoints, in the order they were added.
+    pub fn get_breakpoints(&self) -> &[Breakpoint] {
+        self.debugger.get_breakpoints()
+    }
+
+    /// Add a watchpoint, stopping `step`, `step_over`, `step_out` and `run_to_frame` after
+    /// the instruction which accessed its address (or changed the value at its address).
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        let mut mmu = self.mmu.borrow_mut();
+        match watchpoint.kind {
+            WatchKind::Change => {
+                let value = mmu.get_bus_byte(watchpoint.addr);
+                self.debugger.add_change(watchpoint.addr, value);
+            }
+            kind => mmu.watch.add(watchpoint.addr, kind),
+        }
+    }
+
+    /// Remove a watchpoint, returning whether it was removed.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        match watchpoint.kind {
+            WatchKind::Change => self.debugger.remove_change(watchpoint.addr),
+            kind => self.mmu.borrow_mut().watch.remove(watchpoint.addr, kind),
+        }
+    }
+
+    /// Remove all watchpoints.
+    pub fn clear_watchpoints(&mut self) {
+        self.debugger.clear_changes();
+        self.mmu.borrow_mut().watch.clear();
+    }
+
+    /// Get the watchpoints: the read watchpoints, then the write and change watchpoints,
+    /// each in the order they were added.
+    pub fn get_watchpoints(&self) -> Vec<Watchpoint> {
+        let mut watchpoints: Vec<Watchpoint> = self.mmu.borrow().watch.get().collect();
+        watchpoints.extend(self.debugger.get_changes().map(|addr| Watchpoint {
+            addr,
+            kind: WatchKind::Change,
+        }));
+        watchpoints
+    }
+
+    /// Enable rewinding, capturing snapshots while running frames using `run_frame`.
+    /// Enabling rewind again replaces the options and discards existing snapshots.
+    pub fn enable_rewind(&mut self, options: RewindOptions) {
+        self.rewind = Some(rewind::Rewind::new(options));
+    }
+
+    /// Disable rewinding, discarding all snapshots.
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Rewind to the most recent snapshot, each call moves further back in time. Returns
+    /// false when rewind is disabled or there are no snapshots left.
+    pub fn rewind_step(&mut self) -> bool {
+        match self.rewind.as_mut().and_then(rewind::Rewind::pop) {
+            Some(snapshot) => self.load_state(&snapshot).is_ok(),
+            None => false,
+        }
+    }
+
+    // Perform one step, returning the number of CPU cycles and PPU cycles (dots) run.
+    fn run_step(&mut self) -> (u32, u32) {
+        if self.mmu.borrow().get_bus_byte(self.cpu.registers.pc) == 0x10 {
             self.mmu.borrow_mut().perform_speed_switch();
         }
-        let cycles = self.cpu.run();
-        self.mmu.borrow_mut().run_cycles(cycles);
-        cycles
+        let frame = self.mmu.borrow().ppu.frames;
+        self.mmu.borrow().watch.clear_hits();
+        let interrupt = self.cpu.handle_interrupts();
+        // Reading IF and IE to check for interrupts is not an access watched by the debugger.
+        self.mmu.borrow().watch.discard_reads();
+        let cycles = match interrupt {
+            0 => self.cpu.run_instruction(),
+            n => n,
+        };
+        let dots = self.mmu.borrow_mut().run_cycles(cycles);
+        if self.video.is_some() {
+            self.record_video(frame, dots);
+        }
+        (cycles, dots)
+    }
+
+    // Perform one step, adding the dots run to `dots` and returning why the debugger stopped it (if it did).
+    fn debug_step(&mut self, dots: &mut u32) -> Option<StopReason> {
+        let (_, ppu_cycles) = self.run_step();
+        *dots += ppu_cycles;
+        self.check_debugger()
+    }
+
+    // Check if the frame running when stepping started has ended, see `run_frame`.
+    fn has_frame_ended(&self, frame: u64, dots: u32) -> bool {
+        self.mmu.borrow().ppu.frames != frame || dots >= ppu::FRAME_DOTS
+    }
+
+    // Check the watchpoints hit by the last step and the breakpoints at the next instruction.
+    fn check_debugger(&mut self) -> Option<StopReason> {
+        let mmu = self.mmu.borrow();
+        let hit = mmu.watch.take();
+        let change = self.debugger.check_changes(|addr| mmu.get_bus_byte(addr));
+        if let Some((watchpoint, value)) = hit {
+            return Some(StopReason::Watchpoint(watchpoint, value));
+        }
+        if let Some((addr, value)) = change {
+            let watchpoint = Watchpoint {
+                addr,
+                kind: WatchKind::Change,
+            };
+            return Some(StopReason::Watchpoint(watchpoint, value));
+        }
+        if self
+            .debugger
+            .has_breakpoint(&self.cpu, mmu.cartridge.get_rom_bank())
+        {
+            return Some(StopReason::Breakpoint(self.cpu.registers.pc));
+        }
+        None
+    }
+
+    // Frames are recorded when V-Blank is entered, or every FRAME_DOTS while the LCD is disabled, together
+    // with the audio produced since the previous frame. This keeps the recording in sync with emulated time.
+    fn record_video(&mut self, frame: u64, dots: u32) {
+        let Some(video) = self.video.as_mut() else {
+            return;
+        };
+        let mut mmu = self.mmu.borrow_mut();
+        video.dots += dots;
+        if mmu.ppu.frames != frame {
+            video.dots = 0;
+        } else if video.dots >= ppu::FRAME_DOTS {
+            video.dots -= ppu::FRAME_DOTS;
+        } else {
+            return;
+        }
+        let rgb: Vec<u8> = mmu
+            .ppu
+            .data
+            .iter()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b])
+            .collect();
+        video.writer.write_frame(&rgb);
+        video.writer.write_audio(&mmu.apu.take_capture());
     }
 
     /// Save the current state of the Gameboy.
-    pub fn save(&mut self) {
-        self.mmu.borrow_mut().cartridge.save();
+    pub fn save(&mut self) -> Result<(), GameboyError> {
+        self.mmu.borrow_mut().cartridge.save()
+    }
+
+    /// Save the complete state of the Gameboy (CPU, memory, video, audio, timers and cartridge)
+    /// so that it can be restored later using `load_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_header(&self.get_rom_header());
+        self.cpu.save_state(&mut writer);
+        self.mmu.borrow().save_state(&mut writer);
+        writer.into_inner()
+    }
+
+    /// Restore a state previously created using `save_state`. States created with a different
+    /// ROM or an incompatible version of the emulator are rejected. When a state fails to load
+    /// the Gameboy is left unchanged.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data);
+        reader.read_header(&self.get_rom_header())?;
+        // The state is read into copies of the CPU and MMU state, which replace the current state only once the
+        // complete state has been read.
+        let mut cpu = self.cpu.clone();
+        cpu.load_state(&mut reader)?;
+        let mmu = self.mmu.borrow().read_state(&mut reader)?;
+        reader.finish()?;
+        self.cpu = cpu;
+        self.mmu.borrow_mut().restore_state(mmu);
+        Ok(())
+    }
+
+    // The cartridge header is used to identify which ROM a save state belongs to.
+    fn get_rom_header(&self) -> Vec<u8> {
+        let mmu = self.mmu.borrow();
+        (state::HEADER_START..=state::HEADER_END)
+            .map(|addr| mmu.cartridge.get_byte(addr))
+            .collect()
     }
 
     /// Get the title of the currently loaded ROM.
@@ -143,15 +734,162 @@ impl Gameboy {
     /// }
     /// ```
     ///
-    /// NOTE: when using a Gameboy without color support, all fields of the Pixel will be
-    ///       the same.
-    pub fn get_screen_data(&self) -> [ppu::Pixel; ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT] {
+    /// NOTE: when using a Gameboy without color support, the colors are those of the
+    ///       DmgPalette (see `set_dmg_palette`).
+    pub fn get_screen_data(&self) -> [Pixel; ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT] {
         self.mmu.borrow().ppu.data
     }
 
-    /// Check whether the Gameboy is able to take input.
+    /// Draw the 384 tiles of a VRAM bank (8000-97FF), 16 tiles per row, as a 128x192 image.
+    /// The tiles use background palette 0 (BGP without color support). Returns None when
+    /// the bank does not exist, a Gameboy Color has 2 VRAM banks.
+    pub fn get_tile_sheet(&self, bank: usize) -> Option<Image> {
+        (bank < self.get_bank_count(MemoryRegion::Vram))
+            .then(|| self.mmu.borrow().ppu.get_tile_sheet(bank))
+    }
+
+    /// Draw tile map 0 (9800-9BFF) or 1 (9C00-9FFF) as a 256x256 image, with the tile data
+    /// selected by LCDC and, with color support, the palette, bank and flips of each tile's
+    /// attribute. When `viewport` is set and the map is used for the background, the area
+    /// shown on the screen is outlined. Returns None when the map does not exist.
+    pub fn get_tile_map(&self, map: usize, viewport: bool) -> Option<Image> {
+        (map <= 1).then(|| self.mmu.borrow().ppu.get_tile_map(map, viewport))
+    }
+
+    /// Get the 40 objects of the OAM with their attributes decoded.
+    pub fn get_oam_entries(&self) -> Vec<OamEntry> {
+        self.mmu.borrow().ppu.get_oam_entries()
+    }
+
+    /// Draw the 40 objects of the OAM, 8 per row, as an 80x90 image. Each object is drawn
+    /// with its palette and flips in a 10x18 cell, transparent pixels are grey.
+    pub fn get_object_sheet(&self) -> Image {
+        self.mmu.borrow().ppu.get_object_sheet()
+    }
+
+    /// Draw the 8 background palettes followed by the 8 object palettes of the Gameboy
+    /// Color as a 32x128 image, with one palette per row and each color an 8x8 square.
+    pub fn get_palette_sheet(&self) -> Image {
+        self.mmu.borrow().ppu.get_palette_sheet()
+    }
+
+    /// Start recording video and audio to an uncompressed AVI file at `path`, stopping
+    /// any previous video recording. Every frame is recorded at the frame rate of real
+    /// hardware (~59.73 frames per second) regardless of the speed the Gameboy is run
+    /// at. The audio uses the sample rate of the current audio sink (44100 without a
+    /// sink), so the sink should not be changed while recording.
+    pub fn start_video_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<(), GameboyError> {
+        self.stop_video_recording()?;
+        let path = path.as_ref().to_path_buf();
+        let sample_rate = self.mmu.borrow().apu.get_sample_rate();
+        let writer =
+            avi::AviWriter::create(&path, ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT, sample_rate)
+                .map_err(|error| GameboyError::RecordingIo {
+                    path: path.clone(),
+                    error,
+                })?;
+        self.mmu.borrow_mut().apu.set_capture(true);
+        self.video = Some(VideoRecording {
+            path,
+            writer,
+            dots: 0,
+        });
+        Ok(())
+    }
+
+    /// Stop recording video, finishing the AVI file.
+    pub fn stop_video_recording(&mut self) -> Result<(), GameboyError> {
+        let Some(video) = self.video.take() else {
+            return Ok(());
+        };
+        self.mmu.borrow_mut().apu.set_capture(false);
+        video
+            .writer
+            .finish()
+            .map_err(|error| GameboyError::RecordingIo {
+                path: video.path,
+                error,
+            })
+    }
+
+    /// Check if video is being recorded.
+    pub fn is_video_recording(&self) -> bool {
+        self.video.is_some()
+    }
+
+    /// Set the colors used for the four shades of a DMG, see `DmgPalette` for the
+    /// built-in presets. This has no effect on CGB games (or DMG games running on a CGB).
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.mmu.borrow_mut().ppu.set_dmg_palette(palette);
+    }
+
+    /// Get the colors used for the four shades of a DMG.
+    pub fn get_dmg_palette(&self) -> DmgPalette {
+        self.mmu.borrow().ppu.get_dmg_palette()
+    }
+
+    /// Set how the colors of CGB games are converted to the colors of the screen data.
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.mmu
+            .borrow_mut()
+            .ppu
+            .set_color_correction(color_correction);
+    }
+
+    /// Get how the colors of CGB games are converted to the colors of the screen data.
+    pub fn get_color_correction(&self) -> ColorCorrection {
+        self.mmu.borrow().ppu.get_color_correction()
+    }
+
+    /// Set how each frame is blended with the previous frames, which emulates the
+    /// persistence of the LCD that games use to hide sprite flickering.
+    pub fn set_frame_blending(&mut self, frame_blending: FrameBlending) {
+        self.mmu.borrow_mut().ppu.set_frame_blending(frame_blending);
+    }
+
+    /// Get how each frame is blended with the previous frames.
+    pub fn get_frame_blending(&self) -> FrameBlending {
+        self.mmu.borrow().ppu.get_frame_blending()
+    }
+
+    /// Set how the screen is drawn. The pixel FIFO (the default) shows the raster
+    /// effects of games changing registers during a scanline, the scanline renderer is
+    /// faster.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.mmu.borrow_mut().ppu.set_renderer(renderer);
+    }
+
+    /// Get how the screen is drawn.
+    pub fn get_renderer(&self) -> Renderer {
+        self.mmu.borrow().ppu.get_renderer()
+    }
+
+    /// Set whether the CPU is blocked from accessing VRAM, OAM and the CGB palettes
+    /// while the PPU is using them (enabled by default, as on real hardware). Disabling
+    /// it lets games that rely on the blocking misbehave, but can help debugging.
+    pub fn set_ppu_access_blocking(&mut self, enabled: bool) {
+        self.mmu.borrow_mut().ppu.set_access_blocking(enabled);
+    }
+
+    /// Check if the CPU is blocked from accessing VRAM, OAM and the CGB palettes while
+    /// the PPU is using them.
+    pub fn get_ppu_access_blocking(&self) -> bool {
+        self.mmu.borrow().ppu.get_access_blocking()
+    }
+
+    /// Encode the current screen as a PNG image. Each pixel is scaled up to a
+    /// `scale` x `scale` block, a scale of 1 (or 0) gives the native 160x144 image.
+    pub fn screenshot_png(&self, scale: usize) -> Vec<u8> {
+        png::encode_pixels(ppu::SCREEN_WIDTH, &self.get_screen_data(), scale)
+    }
+
+    /// Check whether the Gameboy is able to take input, which is once per frame.
+    #[deprecated(note = "use `run_frame` and handle input between frames instead")]
     pub fn can_take_input(&mut self) -> bool {
-        self.cpu.flip()
+        let frame = self.mmu.borrow().ppu.frames;
+        let result = frame != self.input_frame;
+        self.input_frame = frame;
+        result
     }
 
     /// Handle keydown on a GameboyButton.
@@ -164,3 +902,385 @@ impl Gameboy {
         self.mmu.borrow_mut().joypad.keyup(button.into());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AudioChannel, Breakpoint, Comparison, Condition, Gameboy, GameboyError, HardwareModel,
+        LoadOptions, Register, RewindOptions, StopReason, WatchKind, Watchpoint,
+    };
+    use crate::memory::Memory;
+
+    // Build a 32KB ROM only cartridge that loops forever incrementing A.
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        // 0100: INC A; JR -3
+        rom[0x0100..0x0103].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        // The LCD is enabled after boot, so a frame ends when V-Blank is entered.
+        let frame = gameboy.mmu.borrow().ppu.frames;
+        let cycles = gameboy.run_frame();
+        assert!(cycles > 0 && cycles <= super::ppu::FRAME_DOTS);
+        assert_eq!(gameboy.mmu.borrow().ppu.frames, frame + 1);
+        assert!(gameboy.has_screen_updated());
+        let cycles = gameboy.run_frame();
+        assert!(cycles >= super::ppu::FRAME_DOTS - 4);
+        assert_eq!(gameboy.mmu.borrow().ppu.frames, frame + 2);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn can_take_input_once_per_frame() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        assert!(!gameboy.can_take_input());
+        gameboy.run_frame();
+        assert!(gameboy.can_take_input());
+        assert!(!gameboy.can_take_input());
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        for _ in 0..1000 {
+            gameboy.step();
+        }
+        let state = gameboy.save_state();
+        for _ in 0..1000 {
+            gameboy.step();
+        }
+        assert_ne!(gameboy.save_state(), state);
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.save_state(), state);
+    }
+
+    #[test]
+    fn load_state_is_atomic() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        let state = gameboy.save_state();
+        for _ in 0..1000 {
+            gameboy.step();
+        }
+        let current = gameboy.save_state();
+        // Truncated in the CPU state right after the header (magic, version and cartridge header), and
+        // in the MMU state after the CPU state was read.
+        for len in [36 + 4, state.len() / 2] {
+            assert_eq!(
+                gameboy.load_state(&state[..len]),
+                Err(super::StateError::UnexpectedEnd)
+            );
+            assert_eq!(gameboy.save_state(), current);
+        }
+        // Trailing data is only detected after everything was read.
+        let mut state = current.clone();
+        gameboy.step();
+        let stepped = gameboy.save_state();
+        state.push(0x00);
+        assert!(gameboy.load_state(&state).is_err());
+        assert_eq!(gameboy.save_state(), stepped);
+    }
+
+    #[test]
+    fn load_state_rejects_other_rom() {
+        let gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        let state = gameboy.save_state();
+        let mut rom = test_rom();
+        rom[0x0134..0x0138].copy_from_slice(b"ELSE");
+        let mut other = Gameboy::new(rom, "", LoadOptions::skip_checks()).unwrap();
+        assert_eq!(
+            other.load_state(&state),
+            Err(super::StateError::RomMismatch)
+        );
+        assert_eq!(
+            other.load_state(&state[..10]),
+            Err(super::StateError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn new_rejects_malformed_rom() {
+        let result = Gameboy::new(vec![0x00; 0x100], "", LoadOptions::skip_checks());
+        assert!(matches!(
+            result,
+            Err(GameboyError::TruncatedRom {
+                expected: 0x150,
+                actual: 0x100
+            })
+        ));
+        let mut rom = test_rom();
+        rom[0x0147] = 0xFC;
+        let result = Gameboy::new(rom, "", LoadOptions::skip_checks());
+        assert!(matches!(
+            result,
+            Err(GameboyError::UnsupportedCartridgeType(0xFC))
+        ));
+        let mut rom = test_rom();
+        rom[0x0148] = 0x42;
+        let result = Gameboy::new(rom, "", LoadOptions::skip_checks());
+        assert!(matches!(
+            result,
+            Err(GameboyError::UnsupportedRomSize(0x42))
+        ));
+        let result = Gameboy::new(test_rom(), "", LoadOptions::default());
+        assert!(matches!(result, Err(GameboyError::InvalidNintendoLogo)));
+        let options = LoadOptions {
+            skip_logo_check: true,
+            ..Default::default()
+        };
+        let result = Gameboy::new(test_rom(), "", options);
+        assert!(matches!(result, Err(GameboyError::InvalidHeaderChecksum)));
+    }
+
+    #[test]
+    fn run_boot_rom() {
+        // 0000: LD A,$01; JP $00FE
+        // 00FE: LDH ($50),A
+        let mut boot_rom = vec![0x00; 0x100];
+        boot_rom[0x00..0x05].copy_from_slice(&[0x3E, 0x01, 0xC3, 0xFE, 0x00]);
+        boot_rom[0xFE..0x100].copy_from_slice(&[0xE0, 0x50]);
+        let options = LoadOptions {
+            boot_rom: Some(boot_rom),
+            ..LoadOptions::skip_checks()
+        };
+        let mut gameboy = Gameboy::new(test_rom(), "", options).unwrap();
+        assert_eq!(gameboy.cpu.registers.pc, 0x0000);
+        assert_eq!(gameboy.mmu.borrow().get_byte(0x0000), 0x3E);
+        for _ in 0..3 {
+            gameboy.step();
+        }
+        assert_eq!(gameboy.cpu.registers.pc, 0x0100);
+        assert_eq!(gameboy.mmu.borrow().get_byte(0x0000), 0x00);
+        assert_eq!(gameboy.mmu.borrow().get_byte(0x0100), 0x3C);
+    }
+
+    #[test]
+    fn new_rejects_invalid_boot_rom() {
+        let options = LoadOptions {
+            boot_rom: Some(vec![0x00; 0x200]),
+            ..LoadOptions::skip_checks()
+        };
+        let result = Gameboy::new(test_rom(), "", options);
+        assert!(matches!(
+            result,
+            Err(GameboyError::InvalidBootRomSize {
+                expected: 0x100,
+                actual: 0x200
+            })
+        ));
+        let options = LoadOptions {
+            boot_rom: Some(vec![0x00; 0x100]),
+            model: Some(HardwareModel::Cgb),
+            ..LoadOptions::skip_checks()
+        };
+        let result = Gameboy::new(test_rom(), "", options);
+        assert!(matches!(
+            result,
+            Err(GameboyError::InvalidBootRomSize {
+                expected: 0x900,
+                actual: 0x100
+            })
+        ));
+    }
+
+    #[test]
+    fn select_hardware_model() {
+        // A DMG game runs on a CGB in compatibility mode, without the CGB only registers.
+        let options = LoadOptions {
+            model: Some(HardwareModel::Cgb),
+            ..LoadOptions::skip_checks()
+        };
+        let gameboy = Gameboy::new(test_rom(), "", options).unwrap();
+        assert_eq!(gameboy.cpu.registers.a, 0x11);
+        assert_eq!(gameboy.cpu.registers.de(), 0x0008);
+        assert_eq!(gameboy.mmu.borrow().get_byte(0xFF70), 0x00);
+
+        // A CGB game can be forced to run on a DMG.
+        let mut rom = test_rom();
+        rom[0x0143] = 0x80;
+        let options = LoadOptions {
+            model: Some(HardwareModel::Dmg),
+            ..LoadOptions::skip_checks()
+        };
+        let gameboy = Gameboy::new(rom.clone(), "", options).unwrap();
+        assert_eq!(gameboy.cpu.registers.a, 0x01);
+        assert_eq!(gameboy.mmu.borrow().get_byte(0xFF70), 0x00);
+
+        let gameboy = Gameboy::new(rom, "", LoadOptions::skip_checks()).unwrap();
+        assert_eq!(gameboy.cpu.registers.a, 0x11);
+        assert_eq!(gameboy.mmu.borrow().get_byte(0xFF70), 0x01);
+    }
+
+    #[test]
+    fn rewind_step() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        assert!(!gameboy.rewind_step());
+        gameboy.enable_rewind(RewindOptions {
+            interval: 2,
+            ..Default::default()
+        });
+        let mut states = Vec::new();
+        for _ in 0..3 {
+            gameboy.run_frame();
+            gameboy.run_frame();
+            states.push(gameboy.save_state());
+        }
+        gameboy.run_frame();
+        for state in states.iter().rev() {
+            assert!(gameboy.rewind_step());
+            assert_eq!(&gameboy.save_state(), state);
+        }
+        assert!(!gameboy.rewind_step());
+    }
+
+    #[test]
+    fn solo_channel() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        gameboy.solo_channel(Some(AudioChannel::Square1));
+        assert!(gameboy.is_channel_enabled(AudioChannel::Square1));
+        assert!(!gameboy.is_channel_enabled(AudioChannel::Square2));
+        assert!(!gameboy.is_channel_enabled(AudioChannel::Wave));
+        assert!(!gameboy.is_channel_enabled(AudioChannel::Noise));
+        gameboy.solo_channel(None);
+        assert!(gameboy.is_channel_enabled(AudioChannel::Square2));
+    }
+
+    #[test]
+    fn video_recording() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        let path = std::env::temp_dir().join(format!("gameboyr-{}.avi", std::process::id()));
+        gameboy.start_video_recording(&path).unwrap();
+        assert!(gameboy.is_video_recording());
+        gameboy.set_speed(0.0);
+        for _ in 0..10 {
+            gameboy.run_frame();
+        }
+        // Frames are still recorded while the LCD is disabled.
+        gameboy.mmu.borrow_mut().set_byte(0xFF40, 0x00);
+        for _ in 0..5 {
+            gameboy.run_frame();
+        }
+        gameboy.stop_video_recording().unwrap();
+        assert!(!gameboy.is_video_recording());
+
+        let avi = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let read_u32 =
+            |position: usize| u32::from_le_bytes(avi[position..position + 4].try_into().unwrap());
+        assert_eq!(&avi[0..4], b"RIFF");
+        assert_eq!(read_u32(4) as usize, avi.len() - 8);
+        // Total frames and video stream length
+        assert_eq!(read_u32(48), 15);
+        assert_eq!(read_u32(140), 15);
+        // 15 frames at 44100Hz is ~11100 samples.
+        let samples = read_u32(264);
+        assert!((10500..11500).contains(&samples), "samples: {}", samples);
+    }
+
+    // Build a test ROM running `code` from 0100.
+    fn code_rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = test_rom();
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        gameboy.add_breakpoint(Breakpoint::new(0x0101));
+        assert_eq!(gameboy.step(), StopReason::Breakpoint(0x0101));
+        assert_eq!(gameboy.step(), StopReason::Step);
+        assert!(gameboy.remove_breakpoint(0x0101, None));
+        gameboy.add_breakpoint(Breakpoint {
+            condition: Some(Condition {
+                register: Register::A,
+                comparison: Comparison::Equal,
+                value: 0x80,
+            }),
+            ..Breakpoint::new(0x0101)
+        });
+        assert_eq!(gameboy.run_to_frame(), StopReason::Breakpoint(0x0101));
+        assert_eq!(gameboy.cpu.registers.a, 0x80);
+        assert_eq!(gameboy.get_breakpoints().len(), 1);
+    }
+
+    #[test]
+    fn watchpoints() {
+        // 0100: EI
+        // 0101: INC A; LD ($C000),A; LD A,($C000); JR -9
+        let code = [0xFB, 0x3C, 0xEA, 0x00, 0xC0, 0xFA, 0x00, 0xC0, 0x18, 0xF7];
+        let mut gameboy = Gameboy::new(code_rom(&code), "", LoadOptions::skip_checks()).unwrap();
+        let watch = |addr, kind| Watchpoint { addr, kind };
+        gameboy.add_watchpoint(watch(0xC000, WatchKind::Write));
+        assert_eq!(
+            gameboy.run_to_frame(),
+            StopReason::Watchpoint(watch(0xC000, WatchKind::Write), 0x02)
+        );
+        assert_eq!(gameboy.cpu.registers.pc, 0x0105);
+        gameboy.clear_watchpoints();
+        gameboy.add_watchpoint(watch(0xC000, WatchKind::Read));
+        assert_eq!(
+            gameboy.run_to_frame(),
+            StopReason::Watchpoint(watch(0xC000, WatchKind::Read), 0x02)
+        );
+        assert!(gameboy.remove_watchpoint(watch(0xC000, WatchKind::Read)));
+        // The interrupt check reads IF on every step, which is not reported.
+        gameboy.add_watchpoint(watch(0xFF0F, WatchKind::Read));
+        assert_eq!(gameboy.run_to_frame(), StopReason::Frame);
+        gameboy.clear_watchpoints();
+        let ly = gameboy.mmu.borrow().get_byte(0xFF44);
+        gameboy.add_watchpoint(watch(0xFF44, WatchKind::Change));
+        assert_eq!(
+            gameboy.run_to_frame(),
+            StopReason::Watchpoint(watch(0xFF44, WatchKind::Change), ly + 1)
+        );
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        // 0100: CALL $0110; JR -5
+        // 0110: INC A; INC A; RET
+        let mut rom = code_rom(&[0xCD, 0x10, 0x01, 0x18, 0xFB]);
+        rom[0x0110..0x0113].copy_from_slice(&[0x3C, 0x3C, 0xC9]);
+        let mut gameboy = Gameboy::new(rom, "", LoadOptions::skip_checks()).unwrap();
+        let a = gameboy.cpu.registers.a;
+        assert_eq!(gameboy.step_over(), StopReason::Step);
+        assert_eq!(gameboy.cpu.registers.pc, 0x0103);
+        assert_eq!(gameboy.cpu.registers.a, a.wrapping_add(2));
+        gameboy.step();
+        assert_eq!(gameboy.step(), StopReason::Step);
+        assert_eq!(gameboy.cpu.registers.pc, 0x0110);
+        assert_eq!(gameboy.step_out(), StopReason::Step);
+        assert_eq!(gameboy.cpu.registers.pc, 0x0103);
+        gameboy.step();
+        gameboy.add_breakpoint(Breakpoint::new(0x0111));
+        assert_eq!(gameboy.step_over(), StopReason::Breakpoint(0x0111));
+    }
+
+    #[test]
+    fn step_over_and_out_stop_at_frame() {
+        // 0100: CALL $0110
+        // 0110: JR -2
+        let mut rom = code_rom(&[0xCD, 0x10, 0x01]);
+        rom[0x0110..0x0112].copy_from_slice(&[0x18, 0xFE]);
+        let mut gameboy = Gameboy::new(rom, "", LoadOptions::skip_checks()).unwrap();
+        assert_eq!(gameboy.step_over(), StopReason::Frame);
+        assert_eq!(gameboy.cpu.registers.pc, 0x0110);
+        assert_eq!(gameboy.step_out(), StopReason::Frame);
+        assert_eq!(gameboy.cpu.registers.pc, 0x0110);
+    }
+
+    #[test]
+    fn vram_viewers_check_bank_and_map() {
+        let gameboy = Gameboy::new(test_rom(), "", LoadOptions::skip_checks()).unwrap();
+        assert!(gameboy.get_tile_sheet(0).is_some());
+        // A DMG has a single VRAM bank.
+        assert_eq!(gameboy.get_tile_sheet(1), None);
+        assert!(gameboy.get_tile_map(1, true).is_some());
+        assert_eq!(gameboy.get_tile_map(2, true), None);
+    }
+}
@@ -1,6 +1,13 @@
-use argparse::{ArgumentParser, Print, Store, StoreFalse, StoreTrue};
-use gameboyr::{Dimensions, Gameboy, GameboyButton};
-use minifb::{Key, Scale, Window, WindowOptions};
+use argparse::{ArgumentParser, Print, Store, StoreFalse, StoreOption, StoreTrue};
+use gameboyr::{
+    AudioChannel, ColorCorrection, Dimensions, DmgPalette, FrameBlending, Gameboy, GameboyButton,
+    GdbStub, HardwareModel, Image, LoadOptions, Pacer, Pixel, Renderer, RewindOptions,
+    disassemble_rom_bank,
+};
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // Map minifb keys to their respective Gameboy buttons
 const KEY_MAPPINGS: [(Key, GameboyButton); 8] = [
@@ -14,12 +21,148 @@ const KEY_MAPPINGS: [(Key, GameboyButton); 8] = [
     (Key::Enter, GameboyButton::Start),
 ];
 
+// Frontend hotkeys
+const SAVE_STATE_KEY: Key = Key::F1;
+const LOAD_STATE_KEY: Key = Key::F2;
+const REWIND_KEY: Key = Key::Backspace;
+const FAST_FORWARD_KEY: Key = Key::Tab;
+const RECORD_AUDIO_KEY: Key = Key::F3;
+const RECORD_VIDEO_KEY: Key = Key::F4;
+const SCREENSHOT_KEY: Key = Key::F12;
+const FRAME_BLENDING_KEY: Key = Key::F5;
+// Toggle (mute/unmute) the sound channels
+const CHANNEL_KEYS: [(Key, AudioChannel); 4] = [
+    (Key::Key1, AudioChannel::Square1),
+    (Key::Key2, AudioChannel::Square2),
+    (Key::Key3, AudioChannel::Wave),
+    (Key::Key4, AudioChannel::Noise),
+];
+// Open/close the VRAM viewer windows
+const VIEWER_KEYS: [(Key, Viewer); 4] = [
+    (Key::F6, Viewer::Tiles),
+    (Key::F7, Viewer::Maps),
+    (Key::F8, Viewer::Objects),
+    (Key::F9, Viewer::Palettes),
+];
+// The space between the images of a viewer window
+const VIEWER_GAP: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Viewer {
+    Tiles,
+    Maps,
+    Objects,
+    Palettes,
+}
+
+impl Viewer {
+    fn get_title(self) -> &'static str {
+        match self {
+            Viewer::Tiles => "Tiles",
+            Viewer::Maps => "Tile Maps",
+            Viewer::Objects => "Objects",
+            Viewer::Palettes => "Palettes",
+        }
+    }
+
+    // Draw the images shown by the viewer, every VRAM bank or tile map is shown side by side.
+    fn draw(self, gameboy: &Gameboy) -> Vec<Image> {
+        match self {
+            Viewer::Tiles => (0..)
+                .map_while(|bank| gameboy.get_tile_sheet(bank))
+                .collect(),
+            Viewer::Maps => (0..)
+                .map_while(|map| gameboy.get_tile_map(map, true))
+                .collect(),
+            Viewer::Objects => vec![gameboy.get_object_sheet()],
+            Viewer::Palettes => vec![gameboy.get_palette_sheet()],
+        }
+    }
+}
+
+// A secondary window showing a viewer, redrawn after every frame.
+struct ViewerWindow {
+    viewer: Viewer,
+    window: Window,
+    buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+impl ViewerWindow {
+    fn open(viewer: Viewer, gameboy: &Gameboy) -> Result<ViewerWindow, minifb::Error> {
+        let images = viewer.draw(gameboy);
+        let width = images
+            .iter()
+            .map(|image| image.width + VIEWER_GAP)
+            .sum::<usize>()
+            - VIEWER_GAP;
+        let height = images.iter().map(|image| image.height).max().unwrap_or(0);
+        let window_options = WindowOptions {
+            resize: true,
+            scale: Scale::X2,
+            ..Default::default()
+        };
+        let title = format!("GameboyR - {}", viewer.get_title());
+        let mut viewer_window = ViewerWindow {
+            viewer,
+            window: Window::new(&title, width, height, window_options)?,
+            buffer: vec![0xFF00_0000; width * height],
+            width,
+            height,
+        };
+        viewer_window.window.set_target_fps(0);
+        viewer_window.show(&images);
+        Ok(viewer_window)
+    }
+
+    fn update(&mut self, gameboy: &Gameboy) {
+        let images = self.viewer.draw(gameboy);
+        self.show(&images);
+    }
+
+    fn show(&mut self, images: &[Image]) {
+        let mut left = 0;
+        for image in images {
+            for (y, row) in image.data.chunks(image.width).enumerate() {
+                let start = y * self.width + left;
+                for (pixel, argb) in row.iter().zip(&mut self.buffer[start..start + image.width]) {
+                    *argb = to_argb(pixel);
+                }
+            }
+            left += image.width + VIEWER_GAP;
+        }
+        if let Err(err) = self
+            .window
+            .update_with_buffer(&self.buffer, self.width, self.height)
+        {
+            eprintln!("gameboyr: failed to update viewer: {}", err);
+        }
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "disasm") {
+        disasm(args);
+        return;
+    }
+
     let mut rom_path = String::from("");
     let mut save_path = String::from("");
     let mut window_scale = 1;
     let mut use_audio = true;
     let mut skip_checks = false;
+    let mut boot_rom_path = String::from("");
+    let mut model = String::from("");
+    let mut speed: f64 = 1.0;
+    let mut stems = false;
+    let mut volume: f32 = 1.0;
+    let mut palette = String::from("grey");
+    let mut color_correction = String::from("lcd");
+    let mut renderer = String::from("fifo");
+    let mut access_blocking = true;
+    let mut gdb_port: Option<u16> = None;
     {
         let mut arg_parser = ArgumentParser::new();
         arg_parser.set_description("Gameboy R");
@@ -48,6 +191,56 @@ fn main() {
             StoreTrue,
             "Skip header checksum and nintendo logo checks for ROM",
         );
+        arg_parser.refer(&mut boot_rom_path).add_option(
+            &["--boot-rom"],
+            Store,
+            "Path to a DMG or CGB boot ROM to run before the ROM",
+        );
+        arg_parser.refer(&mut model).add_option(
+            &["-m", "--model"],
+            Store,
+            "Hardware model to emulate: dmg, mgb, sgb, cgb, agb (Default: based on ROM)",
+        );
+        arg_parser.refer(&mut speed).add_option(
+            &["--speed"],
+            Store,
+            "Speed multiplier relative to real hardware, 0 is unlimited (Default: 1)",
+        );
+        arg_parser.refer(&mut palette).add_option(
+            &["--palette"],
+            Store,
+            "Colors used for DMG games: grey, green, pocket, light (Default: grey)",
+        );
+        arg_parser.refer(&mut color_correction).add_option(
+            &["--color-correction"],
+            Store,
+            "Color correction used for CGB games: raw, lcd, modern, gba-sp (Default: lcd)",
+        );
+        arg_parser.refer(&mut renderer).add_option(
+            &["--renderer"],
+            Store,
+            "Renderer: fifo (accurate), scanline (fast) (Default: fifo)",
+        );
+        arg_parser.refer(&mut access_blocking).add_option(
+            &["--no-access-blocking"],
+            StoreFalse,
+            "Let the CPU access VRAM, OAM and palettes while the PPU uses them (for debugging)",
+        );
+        arg_parser.refer(&mut gdb_port).add_option(
+            &["--gdb"],
+            StoreOption,
+            "Listen for a GDB remote protocol client on this localhost port",
+        );
+        arg_parser.refer(&mut volume).add_option(
+            &["--volume"],
+            Store,
+            "Master volume from 0 (silent) to 1 (Default: 1)",
+        );
+        arg_parser.refer(&mut stems).add_option(
+            &["--stems"],
+            StoreTrue,
+            "Also record each sound channel to its own file when recording audio",
+        );
         arg_parser
             .refer(&mut rom_path)
             .add_argument("rom", Store, "Path to the ROM you want to use")
@@ -55,6 +248,41 @@ fn main() {
         arg_parser.parse_args_or_exit();
     }
 
+    if speed.is_nan() || speed < 0.0 || (speed > 0.0 && speed < Pacer::MIN_SPEED) {
+        panic!(
+            "gameboyr: unsupported speed (valid options: 0, or {} or greater)",
+            Pacer::MIN_SPEED
+        );
+    }
+
+    if !(0.0..=1.0).contains(&volume) {
+        panic!("gameboyr: unsupported volume (valid options: 0 to 1)");
+    }
+
+    let dmg_palette = match palette.as_str() {
+        "grey" => DmgPalette::GREY,
+        "green" => DmgPalette::CLASSIC_GREEN,
+        "pocket" => DmgPalette::POCKET,
+        "light" => DmgPalette::LIGHT,
+        _ => panic!("gameboyr: unsupported palette (valid options: grey, green, pocket, light)"),
+    };
+
+    let color_correction = match color_correction.as_str() {
+        "raw" => ColorCorrection::Raw,
+        "lcd" => ColorCorrection::Lcd,
+        "modern" => ColorCorrection::Modern,
+        "gba-sp" => ColorCorrection::GbaSp,
+        _ => panic!(
+            "gameboyr: unsupported color correction (valid options: raw, lcd, modern, gba-sp)"
+        ),
+    };
+
+    let renderer = match renderer.as_str() {
+        "fifo" => Renderer::Fifo,
+        "scanline" => Renderer::Scanline,
+        _ => panic!("gameboyr: unsupported renderer (valid options: fifo, scanline)"),
+    };
+
     // Default to ROM path if no save path specified
     if save_path.clone().is_empty() {
         save_path.clone_from(&rom_path);
@@ -74,8 +302,40 @@ fn main() {
     let rom = std::fs::read(&rom_path)
         .unwrap_or_else(|err| panic!("gameboyr: failed to read ROM '{}': {}", rom_path, err));
 
-    let mut gameboy = Gameboy::new(rom, save_path, skip_checks);
+    let state_path = Path::new(&save_path).with_extension("state");
 
+    let mut options = if skip_checks {
+        LoadOptions::skip_checks()
+    } else {
+        LoadOptions::default()
+    };
+    options.model = match model.as_str() {
+        "" => None,
+        "dmg" => Some(HardwareModel::Dmg),
+        "mgb" => Some(HardwareModel::Mgb),
+        "sgb" => Some(HardwareModel::Sgb),
+        "cgb" => Some(HardwareModel::Cgb),
+        "agb" => Some(HardwareModel::Agb),
+        _ => panic!("gameboyr: unsupported model (valid options: dmg, mgb, sgb, cgb, agb)"),
+    };
+    if !boot_rom_path.is_empty() {
+        let boot_rom = std::fs::read(&boot_rom_path).unwrap_or_else(|err| {
+            panic!(
+                "gameboyr: failed to read boot ROM '{}': {}",
+                boot_rom_path, err
+            )
+        });
+        options.boot_rom = Some(boot_rom);
+    }
+
+    let mut gameboy = Gameboy::new(rom, &save_path, options)
+        .unwrap_or_else(|err| panic!("gameboyr: failed to load ROM '{}': {}", rom_path, err));
+
+    gameboy.set_dmg_palette(dmg_palette);
+    gameboy.set_color_correction(color_correction);
+    gameboy.set_renderer(renderer);
+    gameboy.set_ppu_access_blocking(access_blocking);
+    gameboy.set_volume(volume);
     if use_audio {
         let success = gameboy.try_enable_audio();
         if !success {
@@ -97,29 +357,219 @@ fn main() {
         .update_with_buffer(window_buffer.as_slice(), width, height)
         .unwrap();
 
+    // Frames are paced by the Pacer, so minifb should not limit the update rate.
+    window.set_target_fps(0);
+
+    let mut gdb = gdb_port.map(|port| {
+        let stub = GdbStub::bind(port)
+            .unwrap_or_else(|err| panic!("gameboyr: failed to listen for GDB: {}", err));
+        println!("gameboyr: listening for GDB on port {}", port);
+        stub
+    });
+
+    let mut viewers: Vec<ViewerWindow> = Vec::new();
+
+    let mut pacer = Pacer::new();
+    gameboy.enable_rewind(RewindOptions::default());
+
     while window.is_open() {
-        gameboy.step();
+        let rewinding = window.is_key_down(REWIND_KEY);
+        // Rewinding steps back one snapshot per frame at the speed of real hardware, even while fast-forwarding.
+        let target_speed = if rewinding {
+            1.0
+        } else if window.is_key_down(FAST_FORWARD_KEY) {
+            0.0
+        } else {
+            speed
+        };
+        if target_speed != pacer.get_speed() {
+            pacer.set_speed(target_speed);
+            gameboy.set_speed(target_speed);
+        }
+        if rewinding {
+            gameboy.rewind_step();
+        } else if let Some(stub) = gdb.as_mut() {
+            if let Err(err) = stub.run_frame(&mut gameboy) {
+                eprintln!("gameboyr: gdb: {}", err);
+            }
+        } else {
+            gameboy.run_frame();
+        }
         if gameboy.has_screen_updated() {
             for (i, pixel) in gameboy.get_screen_data().iter().enumerate() {
-                let r = u32::from(pixel.r) << 16;
-                let g = u32::from(pixel.g) << 8;
-                let b = u32::from(pixel.b);
-                let a = 0xFF00_0000;
-                window_buffer[i] = a | r | g | b;
+                window_buffer[i] = to_argb(pixel);
             }
             window
                 .update_with_buffer(window_buffer.as_slice(), width, height)
                 .unwrap();
+        } else {
+            // Keep processing window events (input, close) while the LCD is disabled
+            window.update();
+        }
+        viewers.retain(|viewer| viewer.window.is_open());
+        for viewer in viewers.iter_mut() {
+            viewer.update(&gameboy);
+        }
+        if window.is_key_pressed(SAVE_STATE_KEY, KeyRepeat::No)
+            && let Err(err) = std::fs::write(&state_path, gameboy.save_state())
+        {
+            eprintln!(
+                "gameboyr: failed to write state '{}': {}",
+                state_path.display(),
+                err
+            );
+        }
+        if window.is_key_pressed(LOAD_STATE_KEY, KeyRepeat::No) {
+            match std::fs::read(&state_path) {
+                Ok(state) => {
+                    if let Err(err) = gameboy.load_state(&state) {
+                        eprintln!("gameboyr: failed to load state: {}", err);
+                    }
+                }
+                Err(err) => eprintln!(
+                    "gameboyr: failed to read state '{}': {}",
+                    state_path.display(),
+                    err
+                ),
+            }
+        }
+        if window.is_key_pressed(RECORD_AUDIO_KEY, KeyRepeat::No) {
+            if gameboy.is_wav_recording() {
+                match gameboy.stop_wav_recording() {
+                    Ok(()) => println!("gameboyr: stopped recording audio"),
+                    Err(err) => eprintln!("gameboyr: {}", err),
+                }
+            } else {
+                let wav_path = timestamped_path(&save_path, "wav");
+                match gameboy.start_wav_recording(&wav_path, stems) {
+                    Ok(()) => println!("gameboyr: recording audio to '{}'", wav_path.display()),
+                    Err(err) => eprintln!("gameboyr: {}", err),
+                }
+            }
+        }
+        if window.is_key_pressed(RECORD_VIDEO_KEY, KeyRepeat::No) {
+            if gameboy.is_video_recording() {
+                match gameboy.stop_video_recording() {
+                    Ok(()) => println!("gameboyr: stopped recording video"),
+                    Err(err) => eprintln!("gameboyr: {}", err),
+                }
+            } else {
+                let avi_path = timestamped_path(&save_path, "avi");
+                match gameboy.start_video_recording(&avi_path) {
+                    Ok(()) => println!("gameboyr: recording video to '{}'", avi_path.display()),
+                    Err(err) => eprintln!("gameboyr: {}", err),
+                }
+            }
+        }
+        if window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+            // Screenshots are scaled to match the window
+            let png_path = timestamped_path(&rom_path, "png");
+            if let Err(err) = std::fs::write(&png_path, gameboy.screenshot_png(window_scale * 2)) {
+                eprintln!(
+                    "gameboyr: failed to write screenshot '{}': {}",
+                    png_path.display(),
+                    err
+                );
+            }
+        }
+        if window.is_key_pressed(FRAME_BLENDING_KEY, KeyRepeat::No) {
+            let frame_blending = match gameboy.get_frame_blending() {
+                FrameBlending::None => FrameBlending::Mix,
+                FrameBlending::Mix => FrameBlending::LcdResponse,
+                FrameBlending::LcdResponse => FrameBlending::None,
+            };
+            gameboy.set_frame_blending(frame_blending);
+            println!("gameboyr: frame blending {:?}", frame_blending);
         }
-        if gameboy.can_take_input() {
-            for (physical_key, gameboy_button) in &KEY_MAPPINGS {
-                if window.is_key_down(*physical_key) {
-                    gameboy.handle_keydown(*gameboy_button);
+        for (key, viewer) in &VIEWER_KEYS {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                if let Some(i) = viewers.iter().position(|open| open.viewer == *viewer) {
+                    viewers.remove(i);
                 } else {
-                    gameboy.handle_keyup(*gameboy_button);
+                    match ViewerWindow::open(*viewer, &gameboy) {
+                        Ok(viewer) => viewers.push(viewer),
+                        Err(err) => eprintln!("gameboyr: failed to open viewer: {}", err),
+                    }
                 }
             }
         }
+        for (key, channel) in &CHANNEL_KEYS {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                let enabled = !gameboy.is_channel_enabled(*channel);
+                gameboy.set_channel_enabled(*channel, enabled);
+            }
+        }
+        for (physical_key, gameboy_button) in &KEY_MAPPINGS {
+            if window.is_key_down(*physical_key) {
+                gameboy.handle_keydown(*gameboy_button);
+            } else {
+                gameboy.handle_keyup(*gameboy_button);
+            }
+        }
+        pacer.wait();
+    }
+    if let Err(err) = gameboy.shutdown() {
+        eprintln!("gameboyr: {}", err);
+    }
+}
+
+// Print the disassembly of a ROM bank: gameboyr disasm rom.gb --bank 3
+fn disasm(args: Vec<String>) {
+    let mut rom_path = String::from("");
+    let mut bank: usize = 0;
+    {
+        let mut arg_parser = ArgumentParser::new();
+        arg_parser.set_description("Gameboy R disassembler");
+        arg_parser.refer(&mut bank).add_option(
+            &["-b", "--bank"],
+            Store,
+            "ROM bank to disassemble (Default: 0)",
+        );
+        arg_parser
+            .refer(&mut rom_path)
+            .add_argument("rom", Store, "Path to the ROM you want to disassemble")
+            .required();
+        // Parse the arguments after the program name, so the subcommand is shown as the program.
+        if let Err(code) = arg_parser.parse(
+            args[1..].to_vec(),
+            &mut std::io::stdout(),
+            &mut std::io::stderr(),
+        ) {
+            std::process::exit(code);
+        }
+    }
+
+    let rom = std::fs::read(&rom_path)
+        .unwrap_or_else(|err| panic!("gameboyr: failed to read ROM '{}': {}", rom_path, err));
+    let instructions = disassemble_rom_bank(&rom, bank);
+    if instructions.is_empty() {
+        panic!(
+            "gameboyr: unsupported bank (valid options: 0 to {})",
+            rom.len().div_ceil(0x4000).saturating_sub(1)
+        );
+    }
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    for instruction in instructions {
+        // Stop when the output is closed, for example when piped to head
+        if writeln!(stdout, "{:02X}:{}", bank, instruction).is_err() {
+            return;
+        }
     }
-    gameboy.shutdown();
+}
+
+// Convert a pixel to the ARGB format of minifb
+fn to_argb(pixel: &Pixel) -> u32 {
+    let r = u32::from(pixel.r) << 16;
+    let g = u32::from(pixel.g) << 8;
+    let b = u32::from(pixel.b);
+    let a = 0xFF00_0000;
+    a | r | g | b
+}
+
+// Create a path next to the given file which includes the current time, so that files are not overwritten.
+fn timestamped_path(path: &str, extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis());
+    Path::new(path).with_extension(format!("{}.{}", timestamp, extension))
 }
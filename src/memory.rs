@@ -1,3 +1,5 @@
+/// Memory is an address space which is accessed a byte at a time, such as the memory map of
+/// the CPU. Words are little endian.
 pub trait Memory {
     fn get_byte(&self, addr: u16) -> u8;
 
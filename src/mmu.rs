@@ -1,12 +1,16 @@
 // https://mgba-emu.github.io/gbdoc/#memory-map
 
-use crate::apu::Apu;
-use crate::cartridges::Cartridge;
+use crate::HardwareModel;
+use crate::apu::{Apu, ApuState};
+use crate::cartridges::{Cartridge, CartridgeMode};
+use crate::debugger::Watch;
 use crate::joypad::Joypad;
 use crate::memory::Memory;
 use crate::ppu::Ppu;
 use crate::ppu::hdma::{Hdma, HdmaMode};
+use crate::ppu::oam_dma::OamDma;
 use crate::serial::Serial;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 use crate::timer::Timer;
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -32,16 +36,51 @@ pub enum InterruptFlag {
     None = 0b0000_0000,
 }
 
+/// MemoryRegion is a banked region of memory, see `Gameboy::peek_bank`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// Cartridge ROM, 16KB banks (bank 0 is mapped at 0000-3FFF, the others at 4000-7FFF).
+    Rom,
+    /// Cartridge RAM, 8KB banks mapped at A000-BFFF.
+    Sram,
+    /// Work RAM, 4KB banks (bank 0 is mapped at C000-CFFF, the others at D000-DFFF). A DMG
+    /// has 2 banks, a CGB 8.
+    Wram,
+    /// Video RAM, 8KB banks mapped at 8000-9FFF. A DMG has 1 bank, a CGB 2.
+    Vram,
+}
+
+impl MemoryRegion {
+    fn get_bank_size(self) -> usize {
+        match self {
+            MemoryRegion::Rom => 0x4000,
+            MemoryRegion::Sram | MemoryRegion::Vram => 0x2000,
+            MemoryRegion::Wram => WRAM_BANK_SIZE,
+        }
+    }
+}
+
 // This value (0x7F) is based on the address space available for HRAM (0xFFFE - 0xFF80)
 const HRAM_SIZE: usize = 0x7F;
 // C000-CFFF   4KB Work RAM Bank 0 (WRAM)
 // D000-DFFF   4KB Work RAM Bank 1 (WRAM)  (switchable bank 1-7 in CGB Mode)
 const WRAM_SIZE: usize = 0x8000;
 const WRAM_BANK_SIZE: usize = 0x1000;
+// The DMG boot ROM is mapped to 0000-00FF. The CGB boot ROM is also mapped to 0200-08FF, leaving the
+// cartridge header (0100-01FF) visible so that it can be read by the boot ROM.
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+// Without a boot ROM to colorize DMG games on a CGB, the compatibility palettes are greyscale (RGB555).
+const COMPATIBILITY_PALETTE: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
 
 pub struct Mmu {
     pub cartridge: Box<dyn Cartridge>,
-    pub apu: Option<Apu>,
+    model: HardwareModel,
+    boot_rom: Option<Vec<u8>>,
+    // FF50 - BANK - Boot ROM Disable
+    // Writing a non-zero value unmaps the boot ROM, it can not be mapped again until the next power cycle.
+    boot_rom_mapped: bool,
+    pub apu: Apu,
     pub ppu: Ppu,
     pub joypad: Joypad,
     serial: Serial,
@@ -49,6 +88,9 @@ pub struct Mmu {
     speed: Speed,
     prepare_speed_switch: bool,
     hdma: Hdma,
+    oam_dma: OamDma,
+    // Read and write watchpoints of the debugger.
+    pub watch: Watch,
     hram: [u8; HRAM_SIZE],
     wram: [u8; WRAM_SIZE],
     wram_bank: usize,
@@ -63,46 +105,67 @@ pub struct Mmu {
 }
 
 impl Mmu {
-    pub fn new(cartridge: Box<dyn Cartridge>) -> Mmu {
-        let cartridge_mode = cartridge.get_mode();
+    pub fn new(
+        cartridge: Box<dyn Cartridge>,
+        model: HardwareModel,
+        boot_rom: Option<Vec<u8>>,
+    ) -> Mmu {
+        // A CGB always starts in CGB mode, DMG games are then switched to DMG compatibility mode.
+        let mode = if model.is_cgb() {
+            CartridgeMode::Gbc
+        } else {
+            CartridgeMode::Gb
+        };
+        let boot_rom_mapped = boot_rom.is_some();
         let mut mmu = Mmu {
             cartridge,
-            apu: None,
-            ppu: Ppu::new(cartridge_mode),
+            model,
+            boot_rom,
+            boot_rom_mapped,
+            apu: Apu::new(),
+            ppu: Ppu::new(mode),
             joypad: Joypad::new(),
             serial: Serial::new(),
             timer: Timer::new(),
             speed: Speed::Normal,
             prepare_speed_switch: false,
             hdma: Hdma::new(),
+            oam_dma: OamDma::new(),
+            watch: Watch::default(),
             hram: [0x00; HRAM_SIZE],
             wram: [0x00; WRAM_SIZE],
             wram_bank: 0x01,
             interrupts_asserted: InterruptFlag::None as u8,
             interrupts_enabled: 0x00,
         };
-        // Set initial values
+        // The boot ROM is responsible for initializing the hardware.
+        if mmu.boot_rom_mapped {
+            return mmu;
+        }
+        // Set initial values (as left by the boot ROM)
         mmu.set_byte(0xFF05, 0x00);
         mmu.set_byte(0xFF06, 0x00);
         mmu.set_byte(0xFF07, 0x00);
+        // The APU ignores writes to its registers while powered off. The channels are not triggered, the
+        // boot sound has already faded out by the time the boot ROM finishes.
+        mmu.set_byte(0xFF26, 0xF1);
         mmu.set_byte(0xFF10, 0x80);
         mmu.set_byte(0xFF11, 0xBF);
         mmu.set_byte(0xFF12, 0xF3);
-        mmu.set_byte(0xFF14, 0xBF);
+        mmu.set_byte(0xFF14, 0x3F);
         mmu.set_byte(0xFF16, 0x3F);
         mmu.set_byte(0xFF17, 0x00);
-        mmu.set_byte(0xFF19, 0xBF);
+        mmu.set_byte(0xFF19, 0x3F);
         mmu.set_byte(0xFF1A, 0x7F);
         mmu.set_byte(0xFF1B, 0xFF);
         mmu.set_byte(0xFF1C, 0x9F);
-        mmu.set_byte(0xFF1E, 0xFF);
+        mmu.set_byte(0xFF1E, 0x3F);
         mmu.set_byte(0xFF20, 0xFF);
         mmu.set_byte(0xFF21, 0x00);
         mmu.set_byte(0xFF22, 0x00);
-        mmu.set_byte(0xFF23, 0xBF);
+        mmu.set_byte(0xFF23, 0x3F);
         mmu.set_byte(0xFF24, 0x77);
         mmu.set_byte(0xFF25, 0xF3);
-        mmu.set_byte(0xFF26, 0xF1);
         mmu.set_byte(0xFF40, 0x91);
         mmu.set_byte(0xFF42, 0x00);
         mmu.set_byte(0xFF43, 0x00);
@@ -112,9 +175,98 @@ impl Mmu {
         mmu.set_byte(0xFF49, 0xFF);
         mmu.set_byte(0xFF4A, 0x00);
         mmu.set_byte(0xFF4B, 0x00);
+        if mode == CartridgeMode::Gbc && mmu.cartridge.get_mode() == CartridgeMode::Gb {
+            // BG palette 0 and OBJ palettes 0-1 are used in DMG compatibility mode.
+            mmu.set_byte(0xFF68, 0x80);
+            mmu.set_byte(0xFF6A, 0x80);
+            for color in COMPATIBILITY_PALETTE {
+                mmu.set_byte(0xFF69, color as u8);
+                mmu.set_byte(0xFF69, (color >> 8) as u8);
+            }
+            for color in [COMPATIBILITY_PALETTE; 2].as_flattened() {
+                mmu.set_byte(0xFF6B, *color as u8);
+                mmu.set_byte(0xFF6B, (color >> 8) as u8);
+            }
+            mmu.ppu.enable_dmg_compatibility();
+        }
         mmu
     }
 
+    // CGB only registers are available in CGB mode, and to the CGB boot ROM while it is setting up DMG
+    // compatibility mode.
+    fn has_cgb_registers(&self) -> bool {
+        self.ppu.get_mode() == CartridgeMode::Gbc || (self.boot_rom_mapped && self.model.is_cgb())
+    }
+
+    // The number of banks in a region, a DMG has less WRAM and VRAM than a CGB.
+    pub fn get_bank_count(&self, region: MemoryRegion) -> usize {
+        let size = region.get_bank_size();
+        match region {
+            MemoryRegion::Rom => self.cartridge.get_rom().len().div_ceil(size),
+            MemoryRegion::Sram => self.cartridge.get_ram().len().div_ceil(size),
+            MemoryRegion::Wram if self.model.is_cgb() => 8,
+            MemoryRegion::Wram => 2,
+            MemoryRegion::Vram if self.model.is_cgb() => 2,
+            MemoryRegion::Vram => 1,
+        }
+    }
+
+    // Get the index of `offset` in `bank` of a region, None when the bank does not exist.
+    fn get_bank_index(&self, region: MemoryRegion, bank: usize, offset: u16) -> Option<usize> {
+        let size = region.get_bank_size();
+        (bank < self.get_bank_count(region) && usize::from(offset) < size)
+            .then_some(bank * size + usize::from(offset))
+    }
+
+    // Read a byte from a bank, regardless of the selected bank.
+    pub fn get_bank_byte(&self, region: MemoryRegion, bank: usize, offset: u16) -> Option<u8> {
+        let index = self.get_bank_index(region, bank, offset)?;
+        match region {
+            MemoryRegion::Rom => self.cartridge.get_rom().get(index).copied(),
+            MemoryRegion::Sram => self.cartridge.get_ram().get(index).copied(),
+            MemoryRegion::Wram => self.wram.get(index).copied(),
+            MemoryRegion::Vram => self.ppu.get_vram_data().get(index).copied(),
+        }
+    }
+
+    // Write a byte to a bank, regardless of the selected bank. Returns false when the bank does not exist.
+    pub fn set_bank_byte(
+        &mut self,
+        region: MemoryRegion,
+        bank: usize,
+        offset: u16,
+        value: u8,
+    ) -> bool {
+        let Some(index) = self.get_bank_index(region, bank, offset) else {
+            return false;
+        };
+        let byte = match region {
+            MemoryRegion::Rom => self.cartridge.get_rom_mut().get_mut(index),
+            MemoryRegion::Sram => self.cartridge.get_ram_mut().get_mut(index),
+            MemoryRegion::Wram => self.wram.get_mut(index),
+            MemoryRegion::Vram => self.ppu.get_vram_data_mut().get_mut(index),
+        };
+        match byte {
+            Some(byte) => {
+                *byte = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get_boot_rom_byte(&self, addr: u16) -> Option<u8> {
+        if !self.boot_rom_mapped {
+            return None;
+        }
+        match addr {
+            0x0000..=0x00FF | 0x0200..=0x08FF => {
+                self.boot_rom.as_ref()?.get(addr as usize).copied()
+            }
+            _ => None,
+        }
+    }
+
     pub fn perform_speed_switch(&mut self) {
         if self.prepare_speed_switch {
             self.speed = if self.speed == Speed::Double {
@@ -132,6 +284,8 @@ impl Mmu {
         let ppu_cycles = cycles / cpu_divider + vram_cycles;
         let cpu_cycles = cycles + vram_cycles * cpu_divider;
 
+        self.run_oam_dma(cpu_cycles);
+
         self.timer.run_cycles(cpu_cycles);
         self.interrupts_asserted |= self.timer.interrupt;
         self.timer.interrupt = InterruptFlag::None as u8;
@@ -143,9 +297,7 @@ impl Mmu {
         self.interrupts_asserted |= self.ppu.interrupt;
         self.ppu.interrupt = InterruptFlag::None as u8;
 
-        if let Some(apu) = self.apu.as_mut() {
-            apu.run_cycles(ppu_cycles);
-        }
+        self.apu.run_cycles(ppu_cycles);
 
         self.interrupts_asserted |= self.serial.interrupt;
         self.serial.interrupt = InterruptFlag::None as u8;
@@ -179,10 +331,18 @@ impl Mmu {
         }
     }
 
+    fn run_oam_dma(&mut self, cycles: u32) {
+        for _ in 0..self.oam_dma.run_cycles(cycles) {
+            let (source, destination) = self.oam_dma.next();
+            let value = self.get_bus_byte(source);
+            self.ppu.set_byte(destination, value);
+        }
+    }
+
     pub fn run_dma_hram(&mut self) {
         let source = self.hdma.source;
         for i in 0..0x10 {
-            let b: u8 = self.get_byte(source + i);
+            let b: u8 = self.get_bus_byte(source + i);
             self.ppu.set_byte(self.hdma.destination + i, b);
         }
         self.hdma.source += 0x10;
@@ -193,13 +353,15 @@ impl Mmu {
             self.hdma.remain -= 1;
         }
     }
-}
 
-impl Memory for Mmu {
-    fn get_byte(&self, addr: u16) -> u8 {
+    // Read a byte without the restrictions of OAM DMA, as done by the DMA itself. This is also used by the debugger,
+    // as it does not report to read watchpoints.
+    pub fn get_bus_byte(&self, addr: u16) -> u8 {
         match addr {
-            // External bus (ROM region)
-            0x0000..=0x7FFF => self.cartridge.get_byte(addr),
+            // External bus (ROM region), with the boot ROM mapped over it during boot
+            0x0000..=0x7FFF => self
+                .get_boot_rom_byte(addr)
+                .unwrap_or_else(|| self.cartridge.get_byte(addr)),
             // VRAM
             0x8000..=0x9FFF => self.ppu.get_byte(addr),
             // External bus (RAM region)
@@ -237,14 +399,13 @@ impl Memory for Mmu {
                     // IF - Interrupt Flag (R/W)
                     0xFF0F => self.interrupts_asserted,
                     // Sound Controller (APU)
-                    0xFF10..=0xFF3F => match &self.apu {
-                        Some(apu) => apu.get_byte(addr),
-                        None => 0x00,
-                    },
+                    0xFF10..=0xFF3F => self.apu.get_byte(addr),
                     // LCD Control Register, LCD Status Register, LCD Position and Scrolling, LCD Monochrome Palettes
                     0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.get_byte(addr),
+                    // FF46 - DMA - DMA Transfer and Start Address (R/W)
+                    0xFF46 => self.oam_dma.register,
                     // KEY1 - CGB Mode Only - Prepare Speed Switch
-                    0xFF4D => {
+                    0xFF4D if self.has_cgb_registers() => {
                         // Bit 7: Current Speed (0=Normal, 1=Double) (Read Only)
                         // Bit 0: Prepare Speed Switch (0=No, 1=Prepare) (Read/Write)
                         let current_speed_bit: u8 = match self.speed {
@@ -258,13 +419,13 @@ impl Memory for Mmu {
                         current_speed_bit | prepare_switch_bit
                     }
                     // LCD VRAM Bank (CGB only)
-                    0xFF4F => self.ppu.get_byte(addr),
+                    0xFF4F if self.has_cgb_registers() => self.ppu.get_byte(addr),
                     // LCD VRAM DMA Transfers (CGB only)
-                    0xFF51..=0xFF55 => self.hdma.get_byte(addr),
+                    0xFF51..=0xFF55 if self.has_cgb_registers() => self.hdma.get_byte(addr),
                     // LCD Color Palettes (CGB only)
-                    0xFF68..=0xFF6B => self.ppu.get_byte(addr),
+                    0xFF68..=0xFF6B if self.has_cgb_registers() => self.ppu.get_byte(addr),
                     // SVBK - CGB Mode Only - WRAM Bank
-                    0xFF70 => self.wram_bank as u8,
+                    0xFF70 if self.has_cgb_registers() => self.wram_bank as u8,
                     _ => 0x00,
                 }
             }
@@ -275,7 +436,9 @@ impl Memory for Mmu {
         }
     }
 
-    fn set_byte(&mut self, addr: u16, value: u8) {
+    // Write a byte without the restrictions of OAM DMA, as done by the DMA itself. This is also used by the
+    // debugger, as it does not report to write watchpoints.
+    pub fn set_bus_byte(&mut self, addr: u16, value: u8) {
         match addr {
             // External bus (ROM region)
             0x0000..=0x7FFF => self.cartridge.set_byte(addr, value),
@@ -316,50 +479,42 @@ impl Memory for Mmu {
                     // IF - Interrupt Flag (R/W)
                     0xFF0F => self.interrupts_asserted = value,
                     // Sound Controller (APU)
-                    0xFF10..=0xFF3F => self
-                        .apu
-                        .as_mut()
-                        .map_or((), |apu| apu.set_byte(addr, value)),
+                    0xFF10..=0xFF3F => self.apu.set_byte(addr, value),
                     // LCD Control Register, LCD Status Register,  LCD Position and Scrolling
                     0xFF40..=0xFF45 => self.ppu.set_byte(addr, value),
-                    // FF46 - DMA - DMA Transfer and Start Address (W)
-                    0xFF46 => {
-                        // Source: XX00-XX9F ;XX in range from 00-F1h
-                        assert!(
-                            value <= 0xF1,
-                            "mmu: invalid DMA transfer start address {:?}",
-                            value
-                        );
-                        let base = u16::from(value) << 8;
-                        for i in 0..0xA0 {
-                            let value = self.get_byte(base + i);
-                            // Destination: FE00-FE9F
-                            self.set_byte(0xFE00 + i, value);
-                        }
-                    }
+                    // FF46 - DMA - DMA Transfer and Start Address (R/W)
+                    0xFF46 => self.oam_dma.start(value),
                     // LCD Monochrome Palettes, LCD Color Palettes (CGB only)
                     0xFF47..=0xFF4B => self.ppu.set_byte(addr, value),
                     // KEY1 - CGB Mode Only - Prepare Speed Switch
-                    0xFF4D => {
+                    0xFF4D if self.has_cgb_registers() => {
                         // This register is used to prepare the gameboy to switch between CGB Double Speed Mode and Normal Speed Mode.
                         // The actual speed switch is performed by executing a STOP command after Bit 0 has been set. After that Bit 0
                         // will be cleared automatically, and the gameboy will operate at the 'other' speed.
                         self.prepare_speed_switch = (value & 0b0000_0001) == 0b0000_0001;
                     }
+                    // KEY0 - CGB boot ROM only - Compatibility mode select
+                    0xFF4C
+                        if self.boot_rom_mapped && self.model.is_cgb() && value & 0x04 != 0x00 =>
+                    {
+                        self.ppu.enable_dmg_compatibility();
+                    }
                     // LCD VRAM Bank (CGB only)
-                    0xFF4F => self.ppu.set_byte(addr, value),
+                    0xFF4F if self.has_cgb_registers() => self.ppu.set_byte(addr, value),
+                    // BANK - Boot ROM Disable
+                    0xFF50 if value != 0x00 => self.boot_rom_mapped = false,
                     // LCD VRAM DMA Transfers (CGB only)
-                    0xFF51..=0xFF55 => self.hdma.set_byte(addr, value),
+                    0xFF51..=0xFF55 if self.has_cgb_registers() => self.hdma.set_byte(addr, value),
                     // LCD Color Palettes (CGB only)
-                    0xFF68..=0xFF6B => self.ppu.set_byte(addr, value),
+                    0xFF68..=0xFF6B if self.has_cgb_registers() => self.ppu.set_byte(addr, value),
                     // SVBK - CGB Mode Only - WRAM Bank
-                    0xFF70 => {
+                    0xFF70 if self.has_cgb_registers() => {
                         // Writing a value of 01h-07h will select Bank 1-7, writing a value of 00h
                         // will select Bank 1 either.
                         // Bit 0-2  Select WRAM Bank (Read/Write)
                         self.wram_bank = match value & 0x07 {
                             0x00 => 1,
-                            _ => value as usize,
+                            n => usize::from(n),
                         };
                     }
                     _ => {}
@@ -372,3 +527,256 @@ impl Memory for Mmu {
         }
     }
 }
+
+impl Memory for Mmu {
+    fn get_byte(&self, addr: u16) -> u8 {
+        // The CPU can only access HRAM (and the I/O registers) while OAM DMA is running.
+        let value = if (self.oam_dma.is_active() && addr < 0xFF00) || !self.ppu.is_accessible(addr)
+        {
+            0xFF
+        } else {
+            self.get_bus_byte(addr)
+        };
+        self.watch.read(addr, value);
+        value
+    }
+
+    fn set_byte(&mut self, addr: u16, value: u8) {
+        self.watch.write(addr, value);
+        if (self.oam_dma.is_active() && addr < 0xFF00) || !self.ppu.is_accessible(addr) {
+            return;
+        }
+        self.set_bus_byte(addr, value);
+    }
+}
+
+impl Stateful for Mmu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.wram);
+        writer.write_bytes(&self.hram);
+        writer.write_u8(self.wram_bank as u8);
+        writer.write_u8(self.speed as u8);
+        writer.write_bool(self.prepare_speed_switch);
+        writer.write_u8(self.interrupts_asserted);
+        writer.write_u8(self.interrupts_enabled);
+        writer.write_bool(self.boot_rom_mapped);
+        self.cartridge.save_state(writer);
+        self.ppu.save_state(writer);
+        self.joypad.save_state(writer);
+        self.serial.save_state(writer);
+        self.timer.save_state(writer);
+        self.hdma.save_state(writer);
+        self.oam_dma.save_state(writer);
+        self.apu.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let state = self.read_state(reader)?;
+        self.restore_state(state);
+        Ok(())
+    }
+}
+
+// The state of the memory and the hardware it connects, read from a save state and restored once the complete
+// state has been read. Only the state is copied, the ROM is shared with the cartridge.
+pub struct MmuState {
+    wram: [u8; WRAM_SIZE],
+    hram: [u8; HRAM_SIZE],
+    wram_bank: usize,
+    speed: Speed,
+    prepare_speed_switch: bool,
+    interrupts_asserted: u8,
+    interrupts_enabled: u8,
+    boot_rom_mapped: bool,
+    cartridge: Box<dyn Cartridge>,
+    ppu: Ppu,
+    joypad: Joypad,
+    serial: Serial,
+    timer: Timer,
+    hdma: Hdma,
+    oam_dma: OamDma,
+    apu: ApuState,
+}
+
+impl Mmu {
+    // Read the state into copies of the components, the Mmu is left unchanged so that a state that fails to
+    // load does not leave some of them restored.
+    pub fn read_state(&self, reader: &mut StateReader) -> Result<MmuState, StateError> {
+        let mut wram = self.wram;
+        reader.read_bytes_into(&mut wram)?;
+        let mut hram = self.hram;
+        reader.read_bytes_into(&mut hram)?;
+        let wram_bank = match reader.read_u8()? & 0x07 {
+            0x00 => 1,
+            n => usize::from(n),
+        };
+        let speed = match reader.read_u8()? {
+            0x01 => Speed::Normal,
+            0x02 => Speed::Double,
+            _ => return Err(StateError::InvalidFormat),
+        };
+        let prepare_speed_switch = reader.read_bool()?;
+        let interrupts_asserted = reader.read_u8()?;
+        let interrupts_enabled = reader.read_u8()?;
+        let boot_rom_mapped = reader.read_bool()?;
+        if boot_rom_mapped && self.boot_rom.is_none() {
+            return Err(StateError::BootRomRequired);
+        }
+        let mut cartridge = self.cartridge.clone_box();
+        cartridge.load_state(reader)?;
+        let mut ppu = self.ppu.clone();
+        ppu.load_state(reader)?;
+        let mut joypad = self.joypad;
+        joypad.load_state(reader)?;
+        let mut serial = self.serial;
+        serial.load_state(reader)?;
+        let mut timer = self.timer;
+        timer.load_state(reader)?;
+        let mut hdma = self.hdma.clone();
+        hdma.load_state(reader)?;
+        let mut oam_dma = self.oam_dma.clone();
+        oam_dma.load_state(reader)?;
+        let apu = self.apu.read_state(reader)?;
+        Ok(MmuState {
+            wram,
+            hram,
+            wram_bank,
+            speed,
+            prepare_speed_switch,
+            interrupts_asserted,
+            interrupts_enabled,
+            boot_rom_mapped,
+            cartridge,
+            ppu,
+            joypad,
+            serial,
+            timer,
+            hdma,
+            oam_dma,
+            apu,
+        })
+    }
+
+    pub fn restore_state(&mut self, state: MmuState) {
+        self.wram = state.wram;
+        self.hram = state.hram;
+        self.wram_bank = state.wram_bank;
+        self.speed = state.speed;
+        self.prepare_speed_switch = state.prepare_speed_switch;
+        self.interrupts_asserted = state.interrupts_asserted;
+        self.interrupts_enabled = state.interrupts_enabled;
+        self.boot_rom_mapped = state.boot_rom_mapped;
+        self.cartridge = state.cartridge;
+        self.ppu = state.ppu;
+        self.joypad = state.joypad;
+        self.serial = state.serial;
+        self.timer = state.timer;
+        self.hdma = state.hdma;
+        self.oam_dma = state.oam_dma;
+        self.apu.restore_state(state.apu);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryRegion, Mmu};
+    use crate::memory::Memory;
+    use crate::{HardwareModel, LoadOptions, cartridges};
+
+    fn mmu() -> Mmu {
+        let cartridge =
+            cartridges::new(vec![0x00; 0x8000], "", &LoadOptions::skip_checks()).unwrap();
+        Mmu::new(cartridge, HardwareModel::Dmg, None)
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut mmu = mmu();
+        mmu.set_byte(0xFF40, 0x00);
+        for i in 0..0xA0 {
+            mmu.set_byte(0xC000 + i, i as u8);
+        }
+        // E0 is mirrored to C0
+        mmu.set_byte(0xFF46, 0xE0);
+        assert_eq!(mmu.get_byte(0xFF46), 0xE0);
+        mmu.run_cycles(4);
+        // Only HRAM and the I/O registers are accessible during the transfer
+        assert_eq!(mmu.get_byte(0xC001), 0xFF);
+        assert_eq!(mmu.get_byte(0xFE00), 0xFF);
+        mmu.set_byte(0xFF80, 0x12);
+        assert_eq!(mmu.get_byte(0xFF80), 0x12);
+        mmu.run_cycles(160 * 4);
+        assert_eq!(mmu.get_byte(0xC001), 0x01);
+        assert_eq!(mmu.get_byte(0xFE00), 0x00);
+        assert_eq!(mmu.get_byte(0xFE9F), 0x9F);
+        // Sources past WRAM do not panic
+        mmu.set_byte(0xFF46, 0xFF);
+        mmu.run_cycles(161 * 4);
+        assert_eq!(mmu.get_byte(0xFF46), 0xFF);
+    }
+
+    #[test]
+    fn ppu_access_blocking() {
+        let mut mmu = mmu();
+        assert!(mmu.ppu.get_access_blocking());
+        let mode = |mmu: &Mmu| mmu.get_byte(0xFF41) & 0x03;
+        while mode(&mmu) != 2 {
+            mmu.run_cycles(4);
+        }
+        mmu.set_byte(0xFE00, 0x12);
+        assert_eq!(mmu.get_byte(0xFE00), 0xFF);
+        mmu.set_byte(0x8000, 0x34);
+        assert_eq!(mmu.get_byte(0x8000), 0x34);
+        while mode(&mmu) != 3 {
+            mmu.run_cycles(4);
+        }
+        mmu.set_byte(0x8000, 0x56);
+        assert_eq!(mmu.get_byte(0x8000), 0xFF);
+        mmu.ppu.set_access_blocking(false);
+        assert_eq!(mmu.get_byte(0x8000), 0x34);
+        assert_eq!(mmu.get_byte(0xFE00), 0x00);
+    }
+
+    #[test]
+    fn bank_access() {
+        let mut mmu = mmu();
+        mmu.set_bus_byte(0xC000, 0x12);
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Wram, 0, 0x0000), Some(0x12));
+        assert!(mmu.set_bank_byte(MemoryRegion::Wram, 1, 0x0010, 0x34));
+        assert_eq!(mmu.get_bus_byte(0xD010), 0x34);
+        // A DMG has 2 WRAM banks and 1 VRAM bank.
+        assert_eq!(mmu.get_bank_count(MemoryRegion::Wram), 2);
+        assert!(!mmu.set_bank_byte(MemoryRegion::Wram, 2, 0x0010, 0x34));
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Wram, 0, 0x1000), None);
+        assert!(mmu.set_bank_byte(MemoryRegion::Vram, 0, 0x1800, 0x56));
+        assert_eq!(mmu.get_bus_byte(0x9800), 0x56);
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Vram, 1, 0x0000), None);
+        // Patching the ROM, which writes through the memory map can not do.
+        assert_eq!(mmu.get_bank_count(MemoryRegion::Rom), 2);
+        mmu.set_bus_byte(0x0100, 0x04);
+        assert_eq!(mmu.get_bus_byte(0x0100), 0x00);
+        assert!(mmu.set_bank_byte(MemoryRegion::Rom, 0, 0x0100, 0x04));
+        assert_eq!(mmu.get_bus_byte(0x0100), 0x04);
+        assert_eq!(mmu.get_bank_count(MemoryRegion::Sram), 0);
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Sram, 0, 0x0000), None);
+    }
+
+    #[test]
+    fn wram_bank_access() {
+        // A CGB game, SVBK is not available in DMG compatibility mode.
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0143] = 0x80;
+        let cartridge = cartridges::new(rom, "", &LoadOptions::skip_checks()).unwrap();
+        let mut mmu = Mmu::new(cartridge, HardwareModel::Cgb, None);
+        assert_eq!(mmu.get_bank_count(MemoryRegion::Wram), 8);
+        // Only bits 0-2 of SVBK select the bank.
+        mmu.set_bus_byte(0xFF70, 0x0F);
+        mmu.set_bus_byte(0xD000, 0x12);
+        assert_eq!(mmu.get_bus_byte(0xD000), 0x12);
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Wram, 7, 0x0000), Some(0x12));
+        mmu.set_bus_byte(0xFF70, 0x08);
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Wram, 1, 0x0000), Some(0x00));
+        mmu.set_bus_byte(0xD000, 0x34);
+        assert_eq!(mmu.get_bank_byte(MemoryRegion::Wram, 1, 0x0000), Some(0x34));
+    }
+}
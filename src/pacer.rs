@@ -0,0 +1,95 @@
+// Pacer
+// The emulator core runs as fast as the host allows. When presenting to a user the emulation has to be slowed down
+// to match the speed of real hardware, which produces a frame every 70224 cycles (~59.73 frames per second).
+// A speed factor scales this, allowing the emulator to run slower or faster than real hardware (or unlimited).
+// Speeds close to zero are limited, as the time per frame (and how often each audio sample is repeated) grows
+// without bound.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cpu::CLOCK_FREQUENCY;
+use crate::ppu::FRAME_DOTS;
+
+/// Pacer limits how often frames are run so that emulation happens at the speed of
+/// real hardware, or a multiple of it.
+pub struct Pacer {
+    frame_time: Duration,
+    next_frame: Instant,
+    speed: f64,
+}
+
+impl Pacer {
+    /// The slowest speed relative to real hardware, slower speeds (other than 0.0) are raised to it.
+    pub const MIN_SPEED: f64 = 0.01;
+
+    /// Create a new Pacer targeting the frame rate of real hardware.
+    pub fn new() -> Pacer {
+        Pacer {
+            frame_time: Duration::from_secs_f64(f64::from(FRAME_DOTS) / f64::from(CLOCK_FREQUENCY)),
+            next_frame: Instant::now(),
+            speed: 1.0,
+        }
+    }
+
+    /// Set the speed relative to real hardware, for example 2.0 runs twice as fast and
+    /// 0.5 at half speed. A speed of 0.0 (or less) runs as fast as possible, speeds below
+    /// `MIN_SPEED` are raised to it.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = clamp_speed(speed);
+        self.next_frame = Instant::now();
+    }
+
+    /// Get the speed relative to real hardware, 0.0 means unlimited.
+    pub fn get_speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Wait until it is time to run the next frame. This should be called once per
+    /// frame run.
+    pub fn wait(&mut self) {
+        if self.speed == 0.0 {
+            return;
+        }
+        self.next_frame += self.frame_time.div_f64(self.speed);
+        let now = Instant::now();
+        match self.next_frame.checked_duration_since(now) {
+            Some(duration) => thread::sleep(duration),
+            // If now is after the target frame time, reset to avoid drifting
+            None => self.next_frame = now,
+        }
+    }
+}
+
+// Limit a speed to 0.0 (unlimited) or MIN_SPEED and above.
+pub(crate) fn clamp_speed(speed: f64) -> f64 {
+    if speed > 0.0 {
+        speed.max(Pacer::MIN_SPEED)
+    } else {
+        0.0
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Pacer {
+        Pacer::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pacer;
+
+    #[test]
+    fn set_speed_limits() {
+        let mut pacer = Pacer::new();
+        pacer.set_speed(1e-300);
+        assert_eq!(pacer.get_speed(), Pacer::MIN_SPEED);
+        pacer.set_speed(-1.0);
+        assert_eq!(pacer.get_speed(), 0.0);
+        pacer.set_speed(f64::NAN);
+        assert_eq!(pacer.get_speed(), 0.0);
+        pacer.set_speed(2.0);
+        assert_eq!(pacer.get_speed(), 2.0);
+    }
+}
@@ -0,0 +1,188 @@
+// PNG
+// A minimal PNG encoder for screenshots. Images are written as 8 bit RGB without compression:
+//   Signature  89 50 4E 47 0D 0A 1A 0A
+//   IHDR       Width, height, bit depth (8), color type (2 = RGB), compression, filter, interlace (all 0)
+//   IDAT       zlib stream of the scanlines, each prefixed with filter type 0 (none)
+//   IEND
+// Every chunk is written as: 4 byte length, 4 byte type, data, 4 byte CRC-32 of type and data (big endian).
+//
+// The zlib stream uses stored (uncompressed) deflate blocks, each holding up to 65535 bytes:
+//   1 byte   BFINAL (bit 0) and BTYPE (bits 1-2, 00 = stored)
+//   2 bytes  LEN (little endian)
+//   2 bytes  NLEN, the one's complement of LEN
+//   LEN bytes of data
+// followed by the Adler-32 checksum of the uncompressed data (big endian).
+//
+// References:
+//  - https://www.w3.org/TR/png/
+//  - https://www.rfc-editor.org/rfc/rfc1950 (zlib)
+//  - https://www.rfc-editor.org/rfc/rfc1951 (deflate)
+
+use crate::ppu::Pixel;
+
+const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Encode an RGB image (3 bytes per pixel, rows from top to bottom) as PNG.
+pub fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        rgb.len(),
+        width * height * 3,
+        "png: image data does not match dimensions (width: {:?}, height: {:?})",
+        width,
+        height
+    );
+    let mut scanlines = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        scanlines.push(0x00);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Encode pixels (`width` per row, rows from top to bottom) as PNG, scaling each pixel up to a `scale` x `scale`
+// block. A scale of 0 is treated as 1.
+pub fn encode_pixels(width: usize, pixels: &[Pixel], scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let scaled_width = width * scale;
+    let mut rgb = Vec::with_capacity(pixels.len() * scale * scale * 3);
+    for row in pixels.chunks(width) {
+        let start = rgb.len();
+        for pixel in row {
+            for _ in 0..scale {
+                rgb.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+            }
+        }
+        for _ in 1..scale {
+            rgb.extend_from_within(start..start + scaled_width * 3);
+        }
+    }
+    encode(scaled_width, pixels.len() / width * scale, &rgb)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // CMF: deflate with a 32K window, FLG: no preset dictionary and a check value making CMF/FLG a
+    // multiple of 31.
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(u8::from(blocks.peek().is_none()));
+        let length = block.len() as u16;
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adler32, crc32, encode, encode_pixels};
+    use crate::ppu::Pixel;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn encode_image() {
+        let png = encode(2, 1, &[0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], 2u32.to_be_bytes());
+        assert_eq!(png[20..24], 1u32.to_be_bytes());
+        assert_eq!(&png[37..41], b"IDAT");
+        // zlib header, a single final stored block of 7 bytes (filter byte and 2 pixels)
+        assert_eq!(png[41..48], [0x78, 0x01, 0x01, 0x07, 0x00, 0xF8, 0xFF]);
+        assert_eq!(png[48..55], [0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(
+            png[png.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn encode_scaled_pixels() {
+        let red = Pixel {
+            r: 0xFF,
+            g: 0x00,
+            b: 0x00,
+        };
+        let blue = Pixel {
+            r: 0x00,
+            g: 0x00,
+            b: 0xFF,
+        };
+        let png = encode_pixels(2, &[red, blue], 0);
+        assert_eq!(png, encode(2, 1, &[0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF]));
+        let png = encode_pixels(2, &[red, blue], 2);
+        assert_eq!(png[16..24], [0, 0, 0, 4, 0, 0, 0, 2]);
+        // Each row is a filter byte followed by every pixel twice, repeated on 2 rows.
+        let row = [0x00, 0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0xFF];
+        assert_eq!(png[41..46], [0x78, 0x01, 0x01, 26, 0x00]);
+        assert_eq!(png[48..61], row);
+        assert_eq!(png[61..74], row);
+    }
+
+    #[test]
+    fn encode_multiple_blocks() {
+        // The 160x144 screen scaled by 3 needs 10 stored blocks.
+        let black = Pixel { r: 0, g: 0, b: 0 };
+        let png = encode_pixels(160, &[black; 160 * 144], 3);
+        assert_eq!(png[16..24], [0, 0, 0x01, 0xE0, 0, 0, 0x01, 0xB0]);
+        // Signature, IHDR, IDAT (zlib header, stored blocks, adler32) and IEND
+        let scanlines = (480 * 3 + 1) * 432;
+        assert_eq!(png.len(), 8 + 25 + 12 + 2 + scanlines + 5 * 10 + 4 + 12);
+    }
+}
@@ -22,9 +22,22 @@ impl From<u8> for Attribute {
             priority: value & (1 << 7) != 0,
             y_flip: value & (1 << 6) != 0,
             x_flip: value & (1 << 5) != 0,
-            palette_number: value as usize & (1 << 4),
+            palette_number: usize::from(value & (1 << 4) != 0),
             vram_bank: value & (1 << 3) != 0,
             cgb_palette_number: value as usize & 0x07,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Attribute;
+
+    #[test]
+    fn palette_number() {
+        assert_eq!(Attribute::from(0x00).palette_number, 0);
+        assert_eq!(Attribute::from(0x10).palette_number, 1);
+        assert_eq!(Attribute::from(0xEF).palette_number, 0);
+        assert_eq!(Attribute::from(0x17).cgb_palette_number, 7);
+    }
+}
@@ -0,0 +1,124 @@
+// Frame Blending
+// The LCD of the DMG (and to a lesser extent the CGB) is slow to respond, a pixel takes several frames to
+// fully change color. Games rely on this to show more sprites than the hardware allows or to fake transparency,
+// by showing objects only on every other frame. On a modern display this flickers, blending each frame with the
+// previous ones restores the intended look.
+// Blending is applied to the screen data when a frame completes (V-Blank is entered).
+
+use crate::ppu::Pixel;
+
+// The fraction of the new color reached by a pixel within one frame when modelling the LCD response. Older
+// frames fade out exponentially, each frame keeps 45% of the color of the previous frame.
+const LCD_RESPONSE: f32 = 0.55;
+
+/// FrameBlending selects how frames are blended with the previous frames, to
+/// emulate the persistence of the LCD.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum FrameBlending {
+    /// Show every frame as rendered, the default.
+    #[default]
+    None,
+    /// Show the average of each frame and the previous frame.
+    Mix,
+    /// Model the slow response of the DMG LCD, where each pixel moves part of the way
+    /// towards its new color every frame.
+    LcdResponse,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameBlender {
+    mode: FrameBlending,
+    // The previous frame (for Mix) or the previous output (for LcdResponse), empty until a frame has been
+    // blended.
+    history: Vec<[f32; 3]>,
+}
+
+impl FrameBlender {
+    pub fn new(mode: FrameBlending) -> FrameBlender {
+        FrameBlender {
+            mode,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn get_mode(&self) -> FrameBlending {
+        self.mode
+    }
+
+    pub fn apply(&mut self, data: &mut [Pixel]) {
+        if self.mode == FrameBlending::None {
+            return;
+        }
+        if self.history.len() != data.len() {
+            self.history = data.iter().map(to_f32).collect();
+        }
+        for (pixel, history) in data.iter_mut().zip(self.history.iter_mut()) {
+            let current = to_f32(pixel);
+            let blended = match self.mode {
+                FrameBlending::Mix => {
+                    let blended = std::array::from_fn(|i| (current[i] + history[i]) / 2.0);
+                    *history = current;
+                    blended
+                }
+                _ => {
+                    let blended: [f32; 3] = std::array::from_fn(|i| {
+                        history[i] + (current[i] - history[i]) * LCD_RESPONSE
+                    });
+                    *history = blended;
+                    blended
+                }
+            };
+            *pixel = Pixel {
+                r: blended[0].round() as u8,
+                g: blended[1].round() as u8,
+                b: blended[2].round() as u8,
+            };
+        }
+    }
+}
+
+fn to_f32(pixel: &Pixel) -> [f32; 3] {
+    [f32::from(pixel.r), f32::from(pixel.g), f32::from(pixel.b)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FrameBlender, FrameBlending};
+    use crate::ppu::Pixel;
+
+    const BLACK: Pixel = Pixel { r: 0, g: 0, b: 0 };
+    const WHITE: Pixel = Pixel {
+        r: 0xFF,
+        g: 0xFF,
+        b: 0xFF,
+    };
+
+    #[test]
+    fn mix() {
+        let mut blender = FrameBlender::new(FrameBlending::Mix);
+        let mut frame = [WHITE];
+        blender.apply(&mut frame);
+        assert_eq!(frame[0], WHITE);
+        let mut frame = [BLACK];
+        blender.apply(&mut frame);
+        assert_eq!(frame[0].r, 0x80);
+        let mut frame = [BLACK];
+        blender.apply(&mut frame);
+        assert_eq!(frame[0], BLACK);
+    }
+
+    #[test]
+    fn lcd_response() {
+        let mut blender = FrameBlender::new(FrameBlending::LcdResponse);
+        let mut frame = [WHITE];
+        blender.apply(&mut frame);
+        let mut previous = 0xFF;
+        for _ in 0..20 {
+            let mut frame = [BLACK];
+            blender.apply(&mut frame);
+            assert!(frame[0].r < previous || frame[0].r == 0);
+            previous = frame[0].r;
+        }
+        assert_eq!(previous, 0);
+    }
+}
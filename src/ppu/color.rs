@@ -0,0 +1,125 @@
+// Color Correction
+// CGB colors are 15 bit (5 bits per channel). The CGB LCD does not display them like a modern monitor: colors
+// bleed into each other and the response is not linear, so games designed on hardware look oversaturated and
+// too dark when the colors are simply scaled to 8 bits. A ColorCorrection selects how colors are converted,
+// every mode is precomputed into a table with an entry for each of the 32768 colors.
+
+use std::rc::Rc;
+
+use crate::ppu::Pixel;
+
+const COLORS: usize = 0x8000;
+
+/// ColorCorrection selects how the 15 bit colors of the CGB are converted to the
+/// 24 bit colors of the screen data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ColorCorrection {
+    /// Scale each channel from 5 to 8 bits, without any correction.
+    Raw,
+    /// Mix the channels like the CGB LCD, the default.
+    #[default]
+    Lcd,
+    /// Mix the channels like the CGB LCD in linear light, which keeps colors brighter
+    /// and more saturated than `Lcd`.
+    Modern,
+    /// Approximate the frontlit GBA SP screen (used to play CGB games), which has less
+    /// color bleeding and a darker response than the CGB.
+    GbaSp,
+}
+
+impl ColorCorrection {
+    // Build the table of colors, indexed by the 15 bit color as stored in palette memory
+    // (bit 0-4 red, bit 5-9 green, bit 10-14 blue). The table is shared by copies of the PPU.
+    pub fn build_table(self) -> Rc<[Pixel]> {
+        (0..COLORS)
+            .map(|color| {
+                let r = (color & 0x1F) as u8;
+                let g = ((color >> 5) & 0x1F) as u8;
+                let b = ((color >> 10) & 0x1F) as u8;
+                self.convert(r, g, b)
+            })
+            .collect()
+    }
+
+    fn convert(self, r: u8, g: u8, b: u8) -> Pixel {
+        match self {
+            ColorCorrection::Raw => Pixel {
+                r: (r << 3) | (r >> 2),
+                g: (g << 3) | (g >> 2),
+                b: (b << 3) | (b >> 2),
+            },
+            ColorCorrection::Lcd => {
+                let r = u32::from(r);
+                let g = u32::from(g);
+                let b = u32::from(b);
+                Pixel {
+                    r: ((r * 13 + g * 2 + b) >> 1) as u8,
+                    g: ((g * 3 + b) << 1) as u8,
+                    b: ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+                }
+            }
+            // The same mixing as the LCD mode, with the rows scaled to sum to 1 so that white stays white.
+            ColorCorrection::Modern => mix(
+                r,
+                g,
+                b,
+                [
+                    [13.0 / 16.0, 2.0 / 16.0, 1.0 / 16.0],
+                    [0.0, 12.0 / 16.0, 4.0 / 16.0],
+                    [3.0 / 16.0, 2.0 / 16.0, 11.0 / 16.0],
+                ],
+                2.2,
+            ),
+            ColorCorrection::GbaSp => mix(
+                r,
+                g,
+                b,
+                [[0.90, 0.07, 0.03], [0.05, 0.90, 0.05], [0.03, 0.07, 0.90]],
+                2.5,
+            ),
+        }
+    }
+}
+
+// Mix the channels in linear light. The color is decoded with the gamma of the emulated screen, mixed by
+// `matrix` (each row gives the amount of red, green and blue in an output channel) and encoded with the
+// gamma of a typical monitor (2.2).
+fn mix(r: u8, g: u8, b: u8, matrix: [[f64; 3]; 3], gamma: f64) -> Pixel {
+    let linear = [r, g, b].map(|c| (f64::from(c) / 31.0).powf(gamma));
+    let [r, g, b] = matrix.map(|row| {
+        let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+        (value.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8
+    });
+    Pixel { r, g, b }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ColorCorrection;
+    use crate::ppu::Pixel;
+
+    fn rgb(pixel: Pixel) -> (u8, u8, u8) {
+        (pixel.r, pixel.g, pixel.b)
+    }
+
+    #[test]
+    fn build_table() {
+        let white = 0x7FFF;
+        let red = 0x001F;
+        let raw = ColorCorrection::Raw.build_table();
+        assert_eq!(raw.len(), 0x8000);
+        assert_eq!(rgb(raw[white]), (0xFF, 0xFF, 0xFF));
+        assert_eq!(rgb(raw[red]), (0xFF, 0x00, 0x00));
+        assert_eq!(rgb(raw[0x0210]), (0x84, 0x84, 0x00));
+        let lcd = ColorCorrection::Lcd.build_table();
+        assert_eq!(rgb(lcd[white]), (0xF8, 0xF8, 0xF8));
+        assert_eq!(rgb(lcd[red]), (0xC9, 0x00, 0x2E));
+        for correction in [ColorCorrection::Modern, ColorCorrection::GbaSp] {
+            let table = correction.build_table();
+            assert_eq!(rgb(table[0]), (0x00, 0x00, 0x00));
+            assert_eq!(rgb(table[white]), (0xFF, 0xFF, 0xFF));
+            // Red bleeds into the other channels, but stays the strongest.
+            assert!(table[red].r > table[red].g && table[red].r > table[red].b);
+        }
+    }
+}
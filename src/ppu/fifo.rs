@@ -0,0 +1,607 @@
+// Pixel FIFO
+// The PPU does not draw a scanline at once, during mode 3 it fetches tiles and shifts out one pixel per dot:
+//   Fetcher      Fetches 8 background or window pixels at a time. A fetch takes 6 dots (2 to read the tile
+//                number, 2 for the low byte and 2 for the high byte of the tile data), the pixels are then
+//                pushed to the background FIFO as soon as it is empty.
+//   BG FIFO      Holds the color number, CGB palette and priority of the fetched background pixels.
+//   OBJ FIFO     Holds the pixels of the objects covering the next 8 pixels, they are mixed with the
+//                background pixels as those are shifted out.
+// The first fetch of a scanline is discarded, which with the 160 pixels gives the minimum mode 3 length of
+// 172 dots. Mode 3 is longer when:
+//   - SCX is not a multiple of 8, the first SCX % 8 pixels are shifted out and discarded.
+//   - The window starts, the BG FIFO is cleared and the fetcher restarts with the window (6 dots).
+//   - An object is reached, shifting stops until the fetcher finishes its current fetch and then while the
+//     object is fetched (6 to 11 dots per object).
+// Registers are read when they are used: the tile map, scroll and tile data when fetching a tile and the
+// palettes when a pixel is shifted out, so writes during mode 3 take effect from the next tile or pixel.
+//
+// References:
+//  - https://gbdev.io/pandocs/pixel_fifo.html
+//  - https://gbdev.io/pandocs/Rendering.html
+
+use std::collections::VecDeque;
+
+use crate::cartridges::CartridgeMode;
+use crate::ppu::attribute::Attribute;
+use crate::ppu::oam::Object;
+use crate::ppu::{Ppu, SCREEN_WIDTH};
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
+
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+/// Renderer selects how the PPU draws the screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Renderer {
+    /// Emulate the pixel FIFO dot by dot, so registers written during a scanline take
+    /// effect immediately and mode 3 lasts as long as on real hardware, the default.
+    #[default]
+    Fifo,
+    /// Draw each scanline at once when mode 3 ends. This is faster, but writes during the
+    /// scanline are ignored and mode 3 always lasts 172 dots.
+    Scanline,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct BgPixel {
+    color: usize,
+    palette: usize,
+    priority: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ObjPixel {
+    // Color 0 is transparent.
+    color: usize,
+    // The CGB palette in CGB mode, otherwise the DMG palette (OBP0 or OBP1).
+    palette: usize,
+    priority: bool,
+    index: usize,
+}
+
+const TRANSPARENT: ObjPixel = ObjPixel {
+    color: 0,
+    palette: 0,
+    priority: false,
+    index: usize::MAX,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    step: FetchStep,
+    step_dots: u8,
+    // The tile column fetched next, relative to SCX (or to the left edge of the window).
+    fetch_x: u8,
+    // The first fetch of a scanline is discarded.
+    first_fetch: bool,
+    tile_addr: u16,
+    tile_attribute: u8,
+    tile_row: u8,
+    tile_data: [u8; 2],
+    // The objects on the scanline (in OAM order), removed once fetched.
+    objects: Vec<Object>,
+    // The object being fetched and the dots left to fetch it.
+    object_fetch: Option<(usize, u8)>,
+    // The number of pixels shifted out to the LCD, the scanline is complete at SCREEN_WIDTH.
+    x: usize,
+    discard: usize,
+    window: bool,
+    // The window is only drawn once LY has been equal to WY in the frame. It has its own line counter, which
+    // only advances on scanlines the window is drawn.
+    window_y_reached: bool,
+    window_line: u8,
+}
+
+impl Fifo {
+    pub fn new() -> Fifo {
+        Fifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            fetch_x: 0,
+            first_fetch: true,
+            tile_addr: 0x9800,
+            tile_attribute: 0x00,
+            tile_row: 0,
+            tile_data: [0x00; 2],
+            objects: Vec::new(),
+            object_fetch: None,
+            x: SCREEN_WIDTH,
+            discard: 0,
+            window: false,
+            window_y_reached: false,
+            window_line: 0,
+        }
+    }
+
+    pub fn start_frame(&mut self) {
+        self.window_y_reached = false;
+        self.window_line = 0;
+    }
+}
+
+// Only the window state is saved, as it lasts for the whole frame. The rest of the FIFO is reset, a state loaded
+// during mode 3 finishes the scanline without drawing the rest of it.
+impl Stateful for Fifo {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.window_y_reached);
+        writer.write_u8(self.window_line);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        *self = Fifo::new();
+        self.window_y_reached = reader.read_bool()?;
+        self.window_line = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Ppu {
+    pub(super) fn run_fifo_dot(&mut self) {
+        self.dots += 1;
+        if self.dots >= 456 {
+            self.dots = 0;
+            self.next_line();
+        }
+        if self.lcdc_y >= 144 {
+            if self.lcd_status.mode != 1 {
+                self.enter_vblank();
+            }
+        } else if self.dots < 80 {
+            if self.lcd_status.mode != 2 && !self.first_line {
+                self.enter_oam_scan();
+            }
+        } else if self.dots == 80 {
+            self.lcd_status.mode = 3;
+            self.start_fifo_line();
+            self.step_fifo();
+        } else if self.lcd_status.mode == 3 {
+            self.step_fifo();
+        }
+        self.update_stat_line();
+    }
+
+    fn start_fifo_line(&mut self) {
+        if self.lcdc_y == self.window_y {
+            self.fifo.window_y_reached = true;
+        }
+        let mut objects = std::mem::take(&mut self.fifo.objects);
+        self.scan_oam(&mut objects);
+        let fifo = &mut self.fifo;
+        fifo.objects = objects;
+        fifo.object_fetch = None;
+        fifo.bg.clear();
+        fifo.obj.clear();
+        fifo.step = FetchStep::Tile;
+        fifo.step_dots = 0;
+        fifo.fetch_x = 0;
+        fifo.first_fetch = true;
+        fifo.x = 0;
+        fifo.discard = usize::from(self.scroll_x & 0x07);
+        fifo.window = false;
+    }
+
+    fn step_fifo(&mut self) {
+        if self.fifo.x < SCREEN_WIDTH {
+            self.run_fifo();
+            return;
+        }
+        // Mode 0 starts the dot after the last pixel is shifted out.
+        if self.fifo.window {
+            self.fifo.window_line = self.fifo.window_line.wrapping_add(1);
+        }
+        self.enter_hblank();
+    }
+
+    fn run_fifo(&mut self) {
+        // Shifting and the fetcher are stopped while an object is fetched.
+        if let Some((i, dots)) = self.fifo.object_fetch {
+            if dots > 1 {
+                self.fifo.object_fetch = Some((i, dots - 1));
+            } else {
+                self.fifo.object_fetch = None;
+                let object = self.fifo.objects.remove(i);
+                self.fetch_object(object);
+            }
+            return;
+        }
+
+        self.run_fetcher();
+
+        let window_enabled = self.lcd_control.has_bit5()
+            && (self.mode == CartridgeMode::Gbc || self.lcd_control.has_bit0());
+        if !self.fifo.window
+            && window_enabled
+            && self.fifo.window_y_reached
+            && self.fifo.x + 7 >= usize::from(self.window_x)
+        {
+            let fifo = &mut self.fifo;
+            fifo.window = true;
+            fifo.bg.clear();
+            fifo.step = FetchStep::Tile;
+            fifo.step_dots = 0;
+            fifo.fetch_x = 0;
+            // With WX < 7 the window starts left of the screen.
+            fifo.discard = if fifo.x == 0 {
+                7 - usize::from(self.window_x)
+            } else {
+                0
+            };
+        }
+
+        if self.fifo.discard == 0 && self.lcd_control.has_bit1() {
+            let fifo = &mut self.fifo;
+            let next = fifo
+                .objects
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| usize::from(o.x) <= fifo.x + 8)
+                .min_by_key(|(_, o)| o.x)
+                .map(|(i, _)| i);
+            if let Some(i) = next {
+                // The fetcher has to finish the tile it is fetching first.
+                if fifo.step == FetchStep::Push && !fifo.bg.is_empty() {
+                    fifo.object_fetch = Some((i, OBJECT_FETCH_DOTS - 1));
+                }
+                return;
+            }
+        }
+
+        let Some(bg) = self.fifo.bg.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj.pop_front();
+        self.shift_pixel(bg, obj);
+        self.fifo.x += 1;
+    }
+
+    fn run_fetcher(&mut self) {
+        if self.fifo.step == FetchStep::Push {
+            if !self.fifo.bg.is_empty() {
+                return;
+            }
+            if self.fifo.first_fetch {
+                self.fifo.first_fetch = false;
+            } else {
+                self.push_tile();
+            }
+            // The next fetch starts in the same dot.
+            self.fifo.step = FetchStep::Tile;
+            self.fifo.step_dots = 0;
+        }
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < 2 {
+            return;
+        }
+        self.fifo.step_dots = 0;
+        match self.fifo.step {
+            FetchStep::Tile => {
+                self.fetch_tile();
+                self.fifo.step = FetchStep::DataLow;
+            }
+            FetchStep::DataLow => {
+                self.fifo.tile_data[0] = self.fetch_tile_data(0);
+                self.fifo.step = FetchStep::DataHigh;
+            }
+            FetchStep::DataHigh => {
+                self.fifo.tile_data[1] = self.fetch_tile_data(1);
+                self.fifo.step = FetchStep::Push;
+            }
+            FetchStep::Push => {}
+        }
+    }
+
+    fn fetch_tile(&mut self) {
+        let (map, column, y) = if self.fifo.window {
+            let map = if self.lcd_control.has_bit6() {
+                0x9C00
+            } else {
+                0x9800
+            };
+            (map, self.fifo.fetch_x, self.fifo.window_line)
+        } else {
+            let map = if self.lcd_control.has_bit3() {
+                0x9C00
+            } else {
+                0x9800
+            };
+            let column = (self.scroll_x >> 3).wrapping_add(self.fifo.fetch_x);
+            (map, column, self.scroll_y.wrapping_add(self.lcdc_y))
+        };
+        let addr = map + (u16::from(y >> 3) & 31) * 32 + (u16::from(column) & 31);
+        self.fifo.tile_addr = addr;
+        // BG Map Attributes are only available in CGB Mode.
+        self.fifo.tile_attribute = if self.mode == CartridgeMode::Gbc {
+            self.get_vram(1, addr)
+        } else {
+            0x00
+        };
+        self.fifo.tile_row = y & 0x07;
+    }
+
+    fn fetch_tile_data(&self, byte: u16) -> u8 {
+        let attribute = Attribute::from(self.fifo.tile_attribute);
+        let tile_number = self.get_vram(0, self.fifo.tile_addr);
+        let tile_location = if self.lcd_control.has_bit4() {
+            0x8000 + u16::from(tile_number) * 16
+        } else {
+            0x8800 + (i16::from(tile_number as i8) + 128) as u16 * 16
+        };
+        let row = if attribute.y_flip {
+            7 - self.fifo.tile_row
+        } else {
+            self.fifo.tile_row
+        };
+        let addr = tile_location + u16::from(row) * 2 + byte;
+        if self.mode == CartridgeMode::Gbc && attribute.vram_bank {
+            self.get_vram(1, addr)
+        } else {
+            self.get_vram(0, addr)
+        }
+    }
+
+    fn push_tile(&mut self) {
+        let attribute = Attribute::from(self.fifo.tile_attribute);
+        let [low, high] = self.fifo.tile_data;
+        for i in 0..8 {
+            let bit = if attribute.x_flip { i } else { 7 - i };
+            let color = usize::from((low >> bit) & 0x01) | (usize::from((high >> bit) & 0x01) << 1);
+            self.fifo.bg.push_back(BgPixel {
+                color,
+                palette: attribute.cgb_palette_number,
+                priority: attribute.priority,
+            });
+        }
+        self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+    }
+
+    fn fetch_object(&mut self, object: Object) {
+        let attribute = Attribute::from(object.flags);
+        let sprite_size = object.height;
+        let tile_number = if sprite_size == 16 {
+            object.tile_number & 0xFE
+        } else {
+            object.tile_number
+        };
+        let row = self.lcdc_y.wrapping_add(16).wrapping_sub(object.y);
+        let row = if attribute.y_flip {
+            sprite_size - 1 - row
+        } else {
+            row
+        };
+        let addr = 0x8000 + u16::from(tile_number) * 16 + u16::from(row) * 2;
+        let bank = u8::from(self.mode == CartridgeMode::Gbc && attribute.vram_bank);
+        let low = self.get_vram(bank, addr);
+        let high = self.get_vram(bank, addr + 1);
+        let palette = if self.mode == CartridgeMode::Gbc {
+            attribute.cgb_palette_number
+        } else {
+            attribute.palette_number
+        };
+
+        // Pixels left of the current position (objects with X < 8) are not shown.
+        let offset = self.fifo.x + 8 - usize::from(object.x);
+        for i in offset..8 {
+            let bit = if attribute.x_flip { i } else { 7 - i };
+            let color = usize::from((low >> bit) & 0x01) | (usize::from((high >> bit) & 0x01) << 1);
+            let slot = i - offset;
+            while self.fifo.obj.len() <= slot {
+                self.fifo.obj.push_back(TRANSPARENT);
+            }
+            // Objects fetched first (lower X, then OAM order) are on top. In CGB mode only the OAM order counts.
+            let current = self.fifo.obj[slot];
+            let on_top = current.color == 0
+                || (self.mode == CartridgeMode::Gbc && object.index < current.index);
+            if color != 0 && on_top {
+                self.fifo.obj[slot] = ObjPixel {
+                    color,
+                    palette,
+                    priority: attribute.priority,
+                    index: object.index,
+                };
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self, bg: BgPixel, obj: Option<ObjPixel>) {
+        let x = self.fifo.x;
+        let bg_enabled = self.mode == CartridgeMode::Gbc || self.lcd_control.has_bit0();
+        let bg_color = if bg_enabled { bg.color } else { 0 };
+        let obj = obj.filter(|o| o.color != 0 && self.lcd_control.has_bit1());
+        let obj = obj.filter(|o| {
+            if self.mode == CartridgeMode::Gbc && !self.lcd_control.has_bit0() {
+                true
+            } else {
+                bg_color == 0 || (!bg.priority && !o.priority)
+            }
+        });
+
+        if let Some(obj) = obj {
+            if self.mode == CartridgeMode::Gbc {
+                let [r, g, b] = self.obp_data[obj.palette][obj.color];
+                self.set_rgb(x, r, g, b);
+            } else {
+                let palette = if obj.palette == 1 {
+                    self.object_pallete_1
+                } else {
+                    self.object_pallete_0
+                };
+                let shade = self.get_shade(palette, obj.color);
+                if self.dmg_compatibility {
+                    let [r, g, b] = self.obp_data[obj.palette][shade];
+                    self.set_rgb(x, r, g, b);
+                } else if obj.palette == 1 {
+                    self.set_pixel(x, self.dmg_palette.obp1[shade]);
+                } else {
+                    self.set_pixel(x, self.dmg_palette.obp0[shade]);
+                }
+            }
+        } else if self.mode == CartridgeMode::Gbc {
+            let [r, g, b] = self.bgp_data[bg.palette][bg_color];
+            self.set_rgb(x, r, g, b);
+        } else {
+            // A disabled background is blank (white).
+            let shade = if bg_enabled {
+                self.get_shade(self.bg_palette, bg_color)
+            } else {
+                0
+            };
+            if self.dmg_compatibility {
+                let [r, g, b] = self.bgp_data[0][shade];
+                self.set_rgb(x, r, g, b);
+            } else {
+                self.set_pixel(x, self.dmg_palette.bg[shade]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cartridges::CartridgeMode;
+    use crate::memory::Memory;
+    use crate::ppu::{DmgPalette, Ppu, Renderer, SCREEN_WIDTH};
+
+    // A PPU showing tile 0 everywhere, with tile 0 using color 3 for every pixel.
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new(CartridgeMode::Gb);
+        for addr in 0x8000..0x8010 {
+            ppu.set_byte(addr, 0xFF);
+        }
+        ppu.set_byte(0xFF47, 0x00);
+        ppu.set_byte(0xFF40, 0x93);
+        ppu
+    }
+
+    fn mode(ppu: &Ppu) -> u8 {
+        ppu.get_byte(0xFF41) & 0x03
+    }
+
+    // Run until mode 3 of the next scanline starts.
+    fn run_to_mode3(ppu: &mut Ppu) {
+        while mode(ppu) == 3 {
+            ppu.run_cycles(1);
+        }
+        while mode(ppu) != 3 {
+            ppu.run_cycles(1);
+        }
+    }
+
+    fn mode3_length(ppu: &mut Ppu) -> u32 {
+        run_to_mode3(ppu);
+        let mut dots = 1;
+        while {
+            ppu.run_cycles(1);
+            mode(ppu) == 3
+        } {
+            dots += 1;
+        }
+        dots
+    }
+
+    // Place object 0 at `x` on the next scanline.
+    fn set_object(ppu: &mut Ppu, x: u8) {
+        ppu.set_byte(0xFE00, ppu.lcdc_y + 1 + 16);
+        ppu.set_byte(0xFE01, x);
+    }
+
+    #[test]
+    fn mode3_length_varies() {
+        let mut ppu = ppu();
+        assert_eq!(mode3_length(&mut ppu), 172);
+        ppu.set_byte(0xFF43, 0x03);
+        assert_eq!(mode3_length(&mut ppu), 175);
+        ppu.set_byte(0xFF43, 0x00);
+        // Objects wait for the fetcher to finish the current tile, which has just started at X = 0
+        // and when aligned with a tile.
+        set_object(&mut ppu, 0);
+        assert_eq!(mode3_length(&mut ppu), 172 + 11);
+        set_object(&mut ppu, 8 + 16);
+        assert_eq!(mode3_length(&mut ppu), 172 + 11);
+        set_object(&mut ppu, 8 + 16 + 7);
+        assert_eq!(mode3_length(&mut ppu), 172 + 6);
+        ppu.set_byte(0xFE00, 0);
+        // The window restarts the fetcher.
+        ppu.set_byte(0xFF4A, ppu.lcdc_y + 1);
+        ppu.set_byte(0xFF4B, 7 + 80);
+        ppu.set_byte(0xFF40, 0xB3);
+        let length = mode3_length(&mut ppu);
+        assert!(length > 172 && length <= 172 + 8);
+    }
+
+    #[test]
+    fn same_frame_as_scanline_renderer() {
+        assert_eq!(Ppu::new(CartridgeMode::Gb).get_renderer(), Renderer::Fifo);
+        let frames = [Renderer::Fifo, Renderer::Scanline].map(|renderer| {
+            let mut ppu = Ppu::new(CartridgeMode::Gb);
+            ppu.set_renderer(renderer);
+            for (i, addr) in (0x8000..0x8010).enumerate() {
+                ppu.set_byte(addr, 0x0F ^ (i as u8 * 0x11));
+            }
+            ppu.set_byte(0xFF47, 0xE4);
+            ppu.set_byte(0xFF40, 0x91);
+            while ppu.lcdc_y != 144 {
+                ppu.run_cycles(1);
+            }
+            ppu.data
+        });
+        assert!(frames[0] == frames[1]);
+    }
+
+    #[test]
+    fn mid_scanline_writes() {
+        let mut ppu = ppu();
+        run_to_mode3(&mut ppu);
+        let line = usize::from(ppu.lcdc_y);
+        while ppu.fifo.x < 80 {
+            ppu.run_cycles(1);
+        }
+        ppu.set_byte(0xFF47, 0xFF);
+        while mode(&ppu) == 3 {
+            ppu.run_cycles(1);
+        }
+        let row = &ppu.data[line * SCREEN_WIDTH..(line + 1) * SCREEN_WIDTH];
+        assert!(row[..80].iter().all(|p| *p == DmgPalette::GREY.bg[0]));
+        assert!(row[80..].iter().all(|p| *p == DmgPalette::GREY.bg[3]));
+    }
+
+    #[test]
+    fn object_height_changed_in_mode3() {
+        let mut ppu = ppu();
+        run_to_mode3(&mut ppu);
+        // A y flipped 8x16 object using tiles 2-3, showing row 12 of the object (row 3 of tile 2) on the next
+        // scanline. Only that row uses color 3.
+        ppu.set_byte(0x8026, 0xFF);
+        ppu.set_byte(0x8027, 0xFF);
+        ppu.set_byte(0xFF48, 0xFF);
+        ppu.set_byte(0xFE00, ppu.lcdc_y + 1 + 16 - 12);
+        ppu.set_byte(0xFE01, 8 + 80);
+        ppu.set_byte(0xFE02, 0x02);
+        ppu.set_byte(0xFE03, 0x40);
+        ppu.set_byte(0xFF40, 0x97);
+        run_to_mode3(&mut ppu);
+        let line = usize::from(ppu.lcdc_y);
+        // The object keeps the height it was selected with.
+        ppu.set_byte(0xFF40, 0x93);
+        while mode(&ppu) == 3 {
+            ppu.run_cycles(1);
+        }
+        let row = &ppu.data[line * SCREEN_WIDTH..(line + 1) * SCREEN_WIDTH];
+        assert!(row[80..88].iter().all(|p| *p == DmgPalette::GREY.obp0[3]));
+        assert!(row[88..].iter().all(|p| *p == DmgPalette::GREY.bg[0]));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum HdmaMode {
     // When using this transfer method, all data is transferred at once. The execution of the program
     // is halted until the transfer has completed. Note that the General Purpose DMA blindly attempts
@@ -23,6 +24,7 @@ pub enum HdmaMode {
     Hdma,
 }
 
+#[derive(Clone)]
 pub struct Hdma {
     pub source: u16,
     pub destination: u16,
@@ -115,3 +117,26 @@ impl Memory for Hdma {
         }
     }
 }
+
+impl Stateful for Hdma {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.source);
+        writer.write_u16(self.destination);
+        writer.write_bool(self.active);
+        writer.write_bool(self.mode == HdmaMode::Hdma);
+        writer.write_u8(self.remain);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.source = reader.read_u16()?;
+        self.destination = reader.read_u16()?;
+        self.active = reader.read_bool()?;
+        self.mode = if reader.read_bool()? {
+            HdmaMode::Hdma
+        } else {
+            HdmaMode::Gdma
+        };
+        self.remain = reader.read_u8()?;
+        Ok(())
+    }
+}
@@ -1,19 +1,40 @@
 mod attribute;
+mod blend;
+mod color;
+mod fifo;
 pub mod hdma;
 mod lcd;
+mod oam;
+pub mod oam_dma;
+mod palette;
+mod viewer;
+
+pub use crate::ppu::blend::FrameBlending;
+pub use crate::ppu::color::ColorCorrection;
+pub use crate::ppu::fifo::Renderer;
+pub use crate::ppu::palette::DmgPalette;
+pub use crate::ppu::viewer::{Image, OamEntry};
+
+use std::rc::Rc;
 
 use crate::cartridges::CartridgeMode;
 use crate::memory::Memory;
 use crate::mmu::InterruptFlag;
 use crate::ppu::attribute::Attribute;
+use crate::ppu::blend::FrameBlender;
+use crate::ppu::fifo::Fifo;
 use crate::ppu::lcd::{Bgpi, LcdControl, LcdStatus};
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 // Resolution - 160x144 (20x18 tiles)
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
 
-// Represents a single pixel on the Gameboy's screen
-#[derive(Debug, Copy, Clone)]
+// An entire frame is 154 scanlines of 456 dots each.
+pub const FRAME_DOTS: u32 = 154 * 456;
+
+/// Represents a single pixel on the Gameboy's screen
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
@@ -28,20 +49,35 @@ impl Pixel {
             b: 0xFF,
         }
     }
-
-    fn from_greyscale(g: u8) -> Pixel {
-        Pixel { r: g, g, b: g }
-    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Ppu {
     // Digital image with mode RGB. Size = 144 * 160 * 3 (RGB).
     pub data: [Pixel; SCREEN_WIDTH * SCREEN_HEIGHT],
     mode: CartridgeMode,
+    // A CGB running a DMG game is in DMG compatibility mode. It renders like a DMG, except the DMG
+    // palettes (BGP, OBP0 and OBP1) select colors from the CGB palettes (BG palette 0, OBJ palette 0-1)
+    // which are setup by the boot ROM.
+    dmg_compatibility: bool,
+    // The colors used for the DMG shades, this is a setting of the frontend and not part of the state.
+    dmg_palette: DmgPalette,
+    // The colors used for the 15 bit CGB colors, built from the ColorCorrection (also a frontend setting).
+    color_correction: ColorCorrection,
+    color_table: Rc<[Pixel]>,
+    blender: FrameBlender,
+    // How the screen is drawn, a frontend setting. Only the window state of the pixel FIFO is saved, a state
+    // loaded during mode 3 finishes the scanline without drawing the rest of it.
+    renderer: Renderer,
+    fifo: Fifo,
+    // Whether CPU access to VRAM, OAM and the CGB palettes is blocked while the PPU is using them, this can be
+    // disabled for debugging.
+    access_blocking: bool,
     pub interrupt: u8,
     pub vblank: bool,
     pub hblank: bool,
+    // Number of frames completed (incremented each time V-Blank is entered)
+    pub frames: u64,
     lcd_control: LcdControl,
     lcd_status: LcdStatus,
     // FF42 - SCY - Scroll Y (R/W)
@@ -61,6 +97,12 @@ pub struct Ppu {
     // The gameboy permanently compares the value of the LYC and LY registers. When both values are identical, the
     // coincident bit in the STAT register becomes set, and (if enabled) a STAT interrupt is requested.
     ly_compare: u8,
+    // The STAT interrupt line. The enabled sources of STAT (LYC=LY and modes 0-2) are ORed into a single line, and
+    // the interrupt is only requested when the line goes from low to high. A source becoming active while another
+    // one keeps the line high does not request an interrupt (STAT blocking).
+    stat_line: bool,
+    // The first scanline after the LCD is enabled starts in mode 0 instead of mode 2, without an OAM scan.
+    first_line: bool,
     // FF4A - WY - Window Y Position (R/W)
     // FF4B - WX - Window X Position minus 7 (R/W)
     // Specifies the upper/left positions of the Window area. (The window is an alternate background area which can
@@ -176,15 +218,26 @@ impl Ppu {
         Ppu {
             data: [Pixel::new(); SCREEN_WIDTH * SCREEN_HEIGHT],
             mode,
+            dmg_compatibility: false,
+            dmg_palette: DmgPalette::default(),
+            color_correction: ColorCorrection::default(),
+            color_table: ColorCorrection::default().build_table(),
+            blender: FrameBlender::new(FrameBlending::default()),
+            renderer: Renderer::default(),
+            fifo: Fifo::new(),
+            access_blocking: true,
             interrupt: InterruptFlag::None as u8,
             vblank: false,
             hblank: false,
+            frames: 0,
             lcd_control: LcdControl::new(),
             lcd_status: LcdStatus::new(),
             scroll_x: 0x00,
             scroll_y: 0x00,
             lcdc_y: 0x00,
             ly_compare: 0x00,
+            stat_line: false,
+            first_line: false,
             window_y: 0x00,
             window_x: 0x00,
             bg_palette: 0x00,
@@ -202,11 +255,106 @@ impl Ppu {
         }
     }
 
+    pub fn get_mode(&self) -> CartridgeMode {
+        self.mode
+    }
+
+    // Both VRAM banks, regardless of the selected bank.
+    pub fn get_vram_data(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn get_vram_data_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+    }
+
+    pub fn get_dmg_palette(&self) -> DmgPalette {
+        self.dmg_palette
+    }
+
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        if color_correction != self.color_correction {
+            self.color_correction = color_correction;
+            self.color_table = color_correction.build_table();
+        }
+    }
+
+    pub fn get_color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+
+    pub fn set_frame_blending(&mut self, frame_blending: FrameBlending) {
+        self.blender = FrameBlender::new(frame_blending);
+    }
+
+    pub fn get_frame_blending(&self) -> FrameBlending {
+        self.blender.get_mode()
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        if renderer != self.renderer {
+            self.renderer = renderer;
+            self.fifo = Fifo::new();
+        }
+    }
+
+    pub fn get_renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn set_access_blocking(&mut self, enabled: bool) {
+        self.access_blocking = enabled;
+    }
+
+    pub fn get_access_blocking(&self) -> bool {
+        self.access_blocking
+    }
+
+    // Check if the CPU can access `addr`. While the LCD is on, VRAM and the CGB palette data can not be accessed
+    // during mode 3 and OAM can not be accessed during modes 2 and 3. Reads then return FFh and writes are
+    // ignored.
+    pub fn is_accessible(&self, addr: u16) -> bool {
+        if !self.access_blocking || !self.lcd_control.has_bit7() {
+            return true;
+        }
+        match addr {
+            0x8000..=0x9FFF => self.lcd_status.mode != 3,
+            0xFE00..=0xFE9F => self.lcd_status.mode != 2 && self.lcd_status.mode != 3,
+            0xFF69 | 0xFF6B if self.mode == CartridgeMode::Gbc => self.lcd_status.mode != 3,
+            _ => true,
+        }
+    }
+
+    // FF4C - KEY0 - CGB Boot ROM Only
+    // Written by the CGB boot ROM with the CGB flag of the cartridge header, bit 2 set selects DMG
+    // compatibility mode. This register is locked once the boot ROM is unmapped.
+    pub fn enable_dmg_compatibility(&mut self) {
+        if self.mode == CartridgeMode::Gbc {
+            self.mode = CartridgeMode::Gb;
+            self.dmg_compatibility = true;
+        }
+    }
+
     pub fn run_cycles(&mut self, cycles: u32) {
         if !self.lcd_control.has_bit7() {
             return;
         }
         self.hblank = false;
+        match self.renderer {
+            Renderer::Fifo => {
+                for _ in 0..cycles {
+                    self.run_fifo_dot();
+                }
+            }
+            Renderer::Scanline => self.run_scanline_cycles(cycles),
+        }
+    }
+
+    fn run_scanline_cycles(&mut self, cycles: u32) {
         if cycles == 0 {
             return;
         }
@@ -220,51 +368,82 @@ impl Ppu {
             let d = self.dots;
             self.dots %= 456;
             if d != self.dots {
-                self.lcdc_y = (self.lcdc_y + 1) % 154;
-                if self.lcd_status.lyc_interrupt_enabled && self.lcdc_y == self.ly_compare {
-                    self.interrupt |= InterruptFlag::LCDStat as u8;
-                }
+                self.next_line();
             }
             if self.lcdc_y >= 144 {
-                if self.lcd_status.mode == 1 {
-                    continue;
-                }
-                self.lcd_status.mode = 1;
-                self.vblank = true;
-                self.interrupt |= InterruptFlag::VBlank as u8;
-                if self.lcd_status.m1_vblank_interrupt_enabled {
-                    self.interrupt |= InterruptFlag::LCDStat as u8;
+                if self.lcd_status.mode != 1 {
+                    self.enter_vblank();
                 }
             } else if self.dots <= 80 {
-                if self.lcd_status.mode == 2 {
-                    continue;
-                }
-                self.lcd_status.mode = 2;
-                if self.lcd_status.m2_oam_interrupt_enabled {
-                    self.interrupt |= InterruptFlag::LCDStat as u8;
+                if self.lcd_status.mode != 2 && !self.first_line {
+                    self.enter_oam_scan();
                 }
             } else if self.dots <= (80 + 172) {
                 self.lcd_status.mode = 3;
-            } else {
-                if self.lcd_status.mode == 0 {
-                    continue;
-                }
-                self.lcd_status.mode = 0;
-                self.hblank = true;
-                if self.lcd_status.m0_hblank_interrupt_enabled {
-                    self.interrupt |= InterruptFlag::LCDStat as u8;
-                }
+            } else if self.lcd_status.mode != 0 {
+                self.enter_hblank();
                 // Render scanline
                 if self.mode == CartridgeMode::Gbc || self.lcd_control.has_bit0() {
                     self.draw_background();
+                } else {
+                    self.priorities = [(false, 0); SCREEN_WIDTH];
                 }
                 if self.lcd_control.has_bit1() {
                     self.draw_sprites();
                 }
             }
+            self.update_stat_line();
         }
     }
 
+    fn next_line(&mut self) {
+        self.lcdc_y = (self.lcdc_y + 1) % 154;
+        self.first_line = false;
+    }
+
+    // FF44 - LY as read by the CPU (and compared with LYC). On scanline 153 LY only reads 153 for the first 4 dots,
+    // it then reads 0 for the rest of the scanline (and scanline 0).
+    fn get_ly(&self) -> u8 {
+        if self.lcdc_y == 153 && self.dots >= 4 {
+            0
+        } else {
+            self.lcdc_y
+        }
+    }
+
+    // Re-evaluate the STAT interrupt line, requesting the interrupt on a rising edge. This is done after every
+    // change of the mode, LY, LYC or the enabled sources.
+    fn update_stat_line(&mut self) {
+        let status = &self.lcd_status;
+        let line = self.lcd_control.has_bit7()
+            && ((status.lyc_interrupt_enabled && self.get_ly() == self.ly_compare)
+                || (status.m2_oam_interrupt_enabled && status.mode == 2)
+                || (status.m1_vblank_interrupt_enabled && status.mode == 1)
+                || (status.m0_hblank_interrupt_enabled && status.mode == 0));
+        if line && !self.stat_line {
+            self.interrupt |= InterruptFlag::LCDStat as u8;
+        }
+        self.stat_line = line;
+    }
+
+    fn enter_vblank(&mut self) {
+        self.lcd_status.mode = 1;
+        self.blender.apply(&mut self.data);
+        self.fifo.start_frame();
+        self.vblank = true;
+        self.frames = self.frames.wrapping_add(1);
+        self.interrupt |= InterruptFlag::VBlank as u8;
+    }
+
+    fn enter_oam_scan(&mut self) {
+        self.lcd_status.mode = 2;
+    }
+
+    fn enter_hblank(&mut self) {
+        self.lcd_status.mode = 0;
+        self.hblank = true;
+    }
+
     // num can be 0 or 1 for each specific vram
     fn get_vram(&self, num: u8, addr: u16) -> u8 {
         match num {
@@ -284,13 +463,9 @@ impl Ppu {
     //  1  Light gray
     //  2  Dark gray
     //  3  Black
-    fn get_gray_shade(&self, value: u8, i: usize) -> u8 {
-        match value >> (2 * i) & 0x03 {
-            0x00 => 0xFF,
-            0x01 => 0xC0,
-            0x02 => 0x60,
-            _ => 0x00,
-        }
+    // The colors of the shades are set by the DmgPalette.
+    fn get_shade(&self, value: u8, i: usize) -> usize {
+        usize::from(value >> (2 * i) & 0x03)
     }
 
     // When developing graphics on PCs, note that the RGB values will have different appearance on CGB displays as on
@@ -301,25 +476,17 @@ impl Ppu {
     // intensity of only one R,G,B color will also influence the other two R,G,B colors. For example, a color setting
     // of 03EFh (Blue=0, Green=1Fh, Red=0Fh) will appear as Neon Green on VGA displays, but on the CGB it'll produce
     // a decently washed out Yellow
+    // The colors are converted using the table of the selected ColorCorrection.
     fn set_rgb(&mut self, index: usize, r: u8, g: u8, b: u8) {
         assert!(r <= 0x1F, "ppu: invalid rgb color (r: {:#04X?}", r);
         assert!(g <= 0x1F, "ppu: invalid rgb color (g: {:#04X?}", g);
         assert!(b <= 0x1F, "ppu: invalid rgb color (b: {:#04X?}", b);
-        let r = u32::from(r);
-        let g = u32::from(g);
-        let b = u32::from(b);
-        let lr = ((r * 13 + g * 2 + b) >> 1) as u8;
-        let lg = ((g * 3 + b) << 1) as u8;
-        let lb = ((r * 3 + g * 2 + b * 11) >> 1) as u8;
-        self.data[(self.lcdc_y as usize * SCREEN_WIDTH) + index] = Pixel {
-            r: lr,
-            g: lg,
-            b: lb,
-        };
+        let color = usize::from(r) | (usize::from(g) << 5) | (usize::from(b) << 10);
+        self.set_pixel(index, self.color_table[color]);
     }
 
-    fn set_greyscale(&mut self, index: usize, g: u8) {
-        self.data[(self.lcdc_y as usize * SCREEN_WIDTH) + index] = Pixel::from_greyscale(g);
+    fn set_pixel(&mut self, index: usize, pixel: Pixel) {
+        self.data[(self.lcdc_y as usize * SCREEN_WIDTH) + index] = pixel;
     }
 
     fn draw_background(&mut self) {
@@ -364,7 +531,12 @@ impl Ppu {
             } as u16
                 * 16;
             let tile_location = tile_base + tile_offset;
-            let tile_attribute = Attribute::from(self.get_vram(1, tile_addr));
+            // BG Map Attributes are only available in CGB Mode.
+            let tile_attribute = if self.mode == CartridgeMode::Gbc {
+                Attribute::from(self.get_vram(1, tile_addr))
+            } else {
+                Attribute::from(0x00)
+            };
             let tile_y = if tile_attribute.y_flip {
                 7 - picture_y % 8
             } else {
@@ -398,9 +570,13 @@ impl Ppu {
                 let g = self.bgp_data[tile_attribute.cgb_palette_number][color][1];
                 let b = self.bgp_data[tile_attribute.cgb_palette_number][color][2];
                 self.set_rgb(x, r, g, b);
+            } else if self.dmg_compatibility {
+                let shade = self.get_shade(self.bg_palette, color);
+                let [r, g, b] = self.bgp_data[0][shade];
+                self.set_rgb(x, r, g, b);
             } else {
-                let color = self.get_gray_shade(self.bg_palette, color);
-                self.set_greyscale(x, color);
+                let shade = self.get_shade(self.bg_palette, color);
+                self.set_pixel(x, self.dmg_palette.bg[shade]);
             }
         }
     }
@@ -445,26 +621,26 @@ impl Ppu {
     fn draw_sprites(&mut self) {
         // Sprite tile size 8x8 or 8x16(2 stacked vertically).
         let sprite_size = if self.lcd_control.has_bit2() { 16 } else { 8 };
-        for i in 0..40 {
-            let sprite_addr = 0xFE00 + (i as u16) * 4;
-            let picture_y = self.get_byte(sprite_addr).wrapping_sub(16);
-            let picture_x = self.get_byte(sprite_addr + 1).wrapping_sub(8);
-            let tile_number = self.get_byte(sprite_addr + 2)
+        let mut objects = Vec::with_capacity(10);
+        self.scan_oam(&mut objects);
+        // Order the objects from the highest priority. The sort is stable, objects with the same X coordinate
+        // stay in OAM order.
+        if self.mode != CartridgeMode::Gbc {
+            objects.sort_by_key(|object| object.x);
+        }
+        // The pixels covered by an object with a higher priority.
+        let mut covered = [false; SCREEN_WIDTH];
+        for object in objects {
+            let picture_y = object.y.wrapping_sub(16);
+            let picture_x = object.x.wrapping_sub(8);
+            let tile_number = object.tile_number
                 & if self.lcd_control.has_bit2() {
                     0xFE
                 } else {
                     0xFF
                 };
-            let tile_attribute = Attribute::from(self.get_byte(sprite_addr + 3));
+            let tile_attribute = Attribute::from(object.flags);
 
-            // If this is true the scanline is out of the area we care about
-            if picture_y <= 0xFF - sprite_size + 1 {
-                if self.lcdc_y < picture_y || self.lcdc_y > picture_y + sprite_size - 1 {
-                    continue;
-                }
-            } else if self.lcdc_y > picture_y.wrapping_add(sprite_size) - 1 {
-                continue;
-            }
             if picture_x >= (SCREEN_WIDTH as u8) && picture_x <= (0xFF - 7) {
                 continue;
             }
@@ -487,7 +663,8 @@ impl Ppu {
                 };
 
             for x in 0..8 {
-                if picture_x.wrapping_add(x) >= (SCREEN_WIDTH as u8) {
+                let screen_x = picture_x.wrapping_add(x) as usize;
+                if screen_x >= SCREEN_WIDTH || covered[screen_x] {
                     continue;
                 }
                 let tile_x = if tile_attribute.x_flip { 7 - x } else { x };
@@ -501,11 +678,13 @@ impl Ppu {
                 if color == 0 {
                     continue;
                 }
+                covered[screen_x] = true;
 
-                // Confirm the priority of background and sprite.
-                let priority = self.priorities[picture_x.wrapping_add(x) as usize];
+                // Confirm the priority of background and sprite. In CGB mode, LCDC bit 0 cleared gives
+                // the sprites priority over the background and window.
+                let priority = self.priorities[screen_x];
                 let skip = if self.mode == CartridgeMode::Gbc && !self.lcd_control.has_bit0() {
-                    priority.1 == 0
+                    false
                 } else if priority.0 {
                     priority.1 != 0
                 } else {
@@ -519,14 +698,23 @@ impl Ppu {
                     let r = self.obp_data[tile_attribute.cgb_palette_number][color][0];
                     let g = self.obp_data[tile_attribute.cgb_palette_number][color][1];
                     let b = self.obp_data[tile_attribute.cgb_palette_number][color][2];
-                    self.set_rgb(picture_x.wrapping_add(x) as usize, r, g, b);
+                    self.set_rgb(screen_x, r, g, b);
+                } else if self.dmg_compatibility {
+                    let palette = if tile_attribute.palette_number == 1 {
+                        self.object_pallete_1
+                    } else {
+                        self.object_pallete_0
+                    };
+                    let shade = self.get_shade(palette, color);
+                    let [r, g, b] = self.obp_data[tile_attribute.palette_number][shade];
+                    self.set_rgb(screen_x, r, g, b);
                 } else {
-                    let color = if tile_attribute.palette_number == 1 {
-                        self.get_gray_shade(self.object_pallete_1, color)
+                    let pixel = if tile_attribute.palette_number == 1 {
+                        self.dmg_palette.obp1[self.get_shade(self.object_pallete_1, color)]
                     } else {
-                        self.get_gray_shade(self.object_pallete_0, color)
+                        self.dmg_palette.obp0[self.get_shade(self.object_pallete_0, color)]
                     };
-                    self.set_greyscale(picture_x.wrapping_add(x) as usize, color);
+                    self.set_pixel(screen_x, pixel);
                 }
             }
         }
@@ -564,7 +752,7 @@ impl Memory for Ppu {
                 } else {
                     0x00
                 };
-                let bit2 = if self.lcdc_y == self.ly_compare {
+                let bit2 = if self.get_ly() == self.ly_compare {
                     0x04
                 } else {
                     0x00
@@ -576,7 +764,7 @@ impl Memory for Ppu {
             // FF43 - SCX - Scroll X (R/W)
             0xFF43 => self.scroll_x,
             // FF44 - LY - LCDC Y-Coordinate (R)
-            0xFF44 => self.lcdc_y,
+            0xFF44 => self.get_ly(),
             // FF45 - LYC - LY Compare (R/W)
             0xFF45 => self.ly_compare,
             // FF47 - BGP - BG Palette Data (R/W) - Non CGB Mode Only
@@ -635,15 +823,26 @@ impl Memory for Ppu {
             0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00] = value,
             // FF40 - LCDC - LCD Control (R/W)
             0xFF40 => {
+                let enabled = self.lcd_control.has_bit7();
                 self.lcd_control.data = value;
+                if !enabled && self.lcd_control.has_bit7() {
+                    self.first_line = true;
+                }
                 if !self.lcd_control.has_bit7() {
                     self.dots = 0;
                     self.lcdc_y = 0;
                     self.lcd_status.mode = 0;
+                    self.fifo = Fifo::new();
                     // Clean screen.
-                    self.data = [Pixel::new(); SCREEN_WIDTH * SCREEN_HEIGHT];
+                    let blank = if self.mode == CartridgeMode::Gb && !self.dmg_compatibility {
+                        self.dmg_palette.bg[0]
+                    } else {
+                        Pixel::new()
+                    };
+                    self.data = [blank; SCREEN_WIDTH * SCREEN_HEIGHT];
                     self.vblank = true;
                 }
+                self.update_stat_line();
             }
             // FF41 - STAT - LCDC Status (R/W)
             0xFF41 => {
@@ -651,6 +850,7 @@ impl Memory for Ppu {
                 self.lcd_status.m2_oam_interrupt_enabled = value & 0x20 != 0x00;
                 self.lcd_status.m1_vblank_interrupt_enabled = value & 0x10 != 0x00;
                 self.lcd_status.m0_hblank_interrupt_enabled = value & 0x08 != 0x00;
+                self.update_stat_line();
             }
             // FF42 - SCY - Scroll Y (R/W)
             0xFF42 => self.scroll_y = value,
@@ -659,7 +859,10 @@ impl Memory for Ppu {
             // FF44 - LY - LCDC Y-Coordinate (R)
             0xFF44 => {}
             // FF45 - LYC - LY Compare (R/W)
-            0xFF45 => self.ly_compare = value,
+            0xFF45 => {
+                self.ly_compare = value;
+                self.update_stat_line();
+            }
             // FF47 - BGP - BG Palette Data (R/W) - Non CGB Mode Only
             0xFF47 => self.bg_palette = value,
             // FF48 - OBP0 - Object Palette 0 Data (R/W) - Non CGB Mode Only
@@ -714,3 +917,280 @@ impl Memory for Ppu {
         }
     }
 }
+
+impl Stateful for Ppu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        let data: Vec<u8> = self.data.iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+        writer.write_bytes(&data);
+        writer.write_bool(self.mode == CartridgeMode::Gbc);
+        writer.write_bool(self.dmg_compatibility);
+        writer.write_u8(self.interrupt);
+        writer.write_bool(self.vblank);
+        writer.write_bool(self.hblank);
+        writer.write_u8(self.lcd_control.data);
+        writer.write_bool(self.lcd_status.lyc_interrupt_enabled);
+        writer.write_bool(self.lcd_status.m2_oam_interrupt_enabled);
+        writer.write_bool(self.lcd_status.m1_vblank_interrupt_enabled);
+        writer.write_bool(self.lcd_status.m0_hblank_interrupt_enabled);
+        writer.write_u8(self.lcd_status.mode);
+        writer.write_u8(self.scroll_y);
+        writer.write_u8(self.scroll_x);
+        writer.write_u8(self.lcdc_y);
+        writer.write_u8(self.ly_compare);
+        writer.write_u8(self.window_y);
+        writer.write_u8(self.window_x);
+        writer.write_u8(self.bg_palette);
+        writer.write_u8(self.object_pallete_0);
+        writer.write_u8(self.object_pallete_1);
+        writer.write_u8(self.bgpi.get());
+        writer.write_bytes(self.bgp_data.as_flattened().as_flattened());
+        writer.write_u8(self.obpi.get());
+        writer.write_bytes(self.obp_data.as_flattened().as_flattened());
+        writer.write_bytes(&self.vram);
+        writer.write_u8(self.vram_bank as u8);
+        writer.write_bytes(&self.oam);
+        writer.write_u32(self.dots);
+        writer.write_bool(self.stat_line);
+        writer.write_bool(self.first_line);
+        self.fifo.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let data = reader.read_bytes()?;
+        if data.len() != self.data.len() * 3 {
+            return Err(StateError::InvalidFormat);
+        }
+        for (pixel, rgb) in self.data.iter_mut().zip(data.chunks_exact(3)) {
+            *pixel = Pixel {
+                r: rgb[0],
+                g: rgb[1],
+                b: rgb[2],
+            };
+        }
+        self.mode = if reader.read_bool()? {
+            CartridgeMode::Gbc
+        } else {
+            CartridgeMode::Gb
+        };
+        self.dmg_compatibility = reader.read_bool()?;
+        self.interrupt = reader.read_u8()?;
+        self.vblank = reader.read_bool()?;
+        self.hblank = reader.read_bool()?;
+        self.lcd_control.data = reader.read_u8()?;
+        self.lcd_status.lyc_interrupt_enabled = reader.read_bool()?;
+        self.lcd_status.m2_oam_interrupt_enabled = reader.read_bool()?;
+        self.lcd_status.m1_vblank_interrupt_enabled = reader.read_bool()?;
+        self.lcd_status.m0_hblank_interrupt_enabled = reader.read_bool()?;
+        self.lcd_status.mode = reader.read_u8()?;
+        self.scroll_y = reader.read_u8()?;
+        self.scroll_x = reader.read_u8()?;
+        self.lcdc_y = reader.read_u8()?;
+        self.ly_compare = reader.read_u8()?;
+        self.window_y = reader.read_u8()?;
+        self.window_x = reader.read_u8()?;
+        self.bg_palette = reader.read_u8()?;
+        self.object_pallete_0 = reader.read_u8()?;
+        self.object_pallete_1 = reader.read_u8()?;
+        self.bgpi.set(reader.read_u8()?);
+        reader.read_bytes_into(self.bgp_data.as_flattened_mut().as_flattened_mut())?;
+        self.obpi.set(reader.read_u8()?);
+        reader.read_bytes_into(self.obp_data.as_flattened_mut().as_flattened_mut())?;
+        // The color components are 5 bits.
+        for component in self
+            .bgp_data
+            .as_flattened_mut()
+            .as_flattened_mut()
+            .iter_mut()
+            .chain(self.obp_data.as_flattened_mut().as_flattened_mut())
+        {
+            *component &= 0x1F;
+        }
+        reader.read_bytes_into(&mut self.vram)?;
+        self.vram_bank = usize::from(reader.read_u8()? & 0x01);
+        reader.read_bytes_into(&mut self.oam)?;
+        self.dots = reader.read_u32()?;
+        self.stat_line = reader.read_bool()?;
+        self.first_line = reader.read_bool()?;
+        self.fifo.load_state(reader)?;
+        // The mode is read back through STAT, LY and the dots index the 154 scanlines of 456 dots.
+        if self.lcd_status.mode > 3 || self.lcdc_y > 153 || self.dots >= 456 {
+            return Err(StateError::InvalidFormat);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DmgPalette, Pixel, Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::cartridges::CartridgeMode;
+    use crate::memory::Memory;
+    use crate::mmu::InterruptFlag;
+    use crate::state::{StateError, StateReader, StateWriter, Stateful};
+
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new(CartridgeMode::Gb);
+        ppu.set_byte(0xFF40, 0x91);
+        ppu
+    }
+
+    fn run_until(ppu: &mut Ppu, done: impl Fn(&Ppu) -> bool) {
+        while !done(ppu) {
+            ppu.run_cycles(1);
+        }
+    }
+
+    fn take_stat_interrupt(ppu: &mut Ppu) -> bool {
+        let requested = ppu.interrupt & InterruptFlag::LCDStat as u8 != 0;
+        ppu.interrupt = InterruptFlag::None as u8;
+        requested
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut ppu = ppu();
+        ppu.set_byte(0xFF45, 1);
+        ppu.set_byte(0xFF41, 0x48);
+        run_until(&mut ppu, |p| p.dots > 80 && p.lcd_status.mode == 0);
+        assert!(take_stat_interrupt(&mut ppu));
+        // The line is still high from mode 0 when LY becomes equal to LYC.
+        run_until(&mut ppu, |p| p.lcdc_y == 1);
+        assert_eq!(ppu.get_byte(0xFF41) & 0x04, 0x04);
+        assert!(!take_stat_interrupt(&mut ppu));
+        ppu.set_byte(0xFF45, 2);
+        ppu.set_byte(0xFF41, 0x40);
+        run_until(&mut ppu, |p| p.lcdc_y == 2);
+        assert!(take_stat_interrupt(&mut ppu));
+    }
+
+    #[test]
+    fn lyc_write() {
+        let mut ppu = ppu();
+        ppu.set_byte(0xFF45, 0x10);
+        ppu.set_byte(0xFF41, 0x40);
+        ppu.run_cycles(10);
+        assert!(!take_stat_interrupt(&mut ppu));
+        ppu.set_byte(0xFF45, 0x00);
+        assert!(take_stat_interrupt(&mut ppu));
+        assert_eq!(ppu.get_byte(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn ly_153() {
+        let mut ppu = ppu();
+        run_until(&mut ppu, |p| p.lcdc_y == 153);
+        assert_eq!(ppu.get_byte(0xFF44), 153);
+        ppu.set_byte(0xFF41, 0x40);
+        take_stat_interrupt(&mut ppu);
+        ppu.run_cycles(4);
+        assert_eq!(ppu.get_byte(0xFF44), 0);
+        assert_eq!(ppu.get_byte(0xFF41) & 0x04, 0x04);
+        assert!(take_stat_interrupt(&mut ppu));
+        // LY stays 0 on scanline 0, so there is no new interrupt.
+        run_until(&mut ppu, |p| p.lcdc_y == 0);
+        assert!(!take_stat_interrupt(&mut ppu));
+    }
+
+    #[test]
+    fn first_line_after_enable() {
+        let mut ppu = ppu();
+        ppu.set_byte(0xFF41, 0x20);
+        ppu.run_cycles(79);
+        assert_eq!(ppu.get_byte(0xFF41) & 0x03, 0);
+        assert!(!take_stat_interrupt(&mut ppu));
+        run_until(&mut ppu, |p| p.lcdc_y == 1);
+        assert_eq!(ppu.get_byte(0xFF41) & 0x03, 2);
+        assert!(take_stat_interrupt(&mut ppu));
+    }
+
+    #[test]
+    fn dmg_palette() {
+        let mut ppu = ppu();
+        ppu.set_dmg_palette(DmgPalette::CLASSIC_GREEN);
+        assert_eq!(ppu.get_dmg_palette(), DmgPalette::CLASSIC_GREEN);
+        // VRAM is empty, so the screen is color 0 which BGP (FC) maps to the lightest shade.
+        ppu.set_byte(0xFF47, 0xFC);
+        run_until(&mut ppu, |p| p.lcdc_y == 144);
+        assert!(
+            ppu.data
+                .iter()
+                .all(|p| *p == DmgPalette::CLASSIC_GREEN.bg[0])
+        );
+        // Map color 0 to black
+        ppu.set_byte(0xFF47, 0x03);
+        run_until(&mut ppu, |p| p.lcdc_y == 0);
+        run_until(&mut ppu, |p| p.lcdc_y == 144);
+        assert!(
+            ppu.data
+                .iter()
+                .all(|p| *p == DmgPalette::CLASSIC_GREEN.bg[3])
+        );
+    }
+
+    #[test]
+    fn load_state_masks_palettes() {
+        let mut ppu = Ppu::new(CartridgeMode::Gbc);
+        ppu.bgp_data[1][2] = [0xFF; 3];
+        ppu.obp_data[7][3] = [0x20, 0x3F, 0x1F];
+        let mut writer = StateWriter::new();
+        ppu.save_state(&mut writer);
+        let data = writer.into_inner();
+        let mut ppu = Ppu::new(CartridgeMode::Gbc);
+        ppu.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(ppu.bgp_data[1][2], [0x1F; 3]);
+        assert_eq!(ppu.obp_data[7][3], [0x00, 0x1F, 0x1F]);
+    }
+
+    #[test]
+    fn load_state_rejects_invalid_timing() {
+        let invalid: [fn(&mut Ppu); 3] = [
+            |ppu| ppu.lcd_status.mode = 4,
+            |ppu| ppu.lcdc_y = 154,
+            |ppu| ppu.dots = 456,
+        ];
+        for corrupt in invalid {
+            let mut ppu = ppu();
+            corrupt(&mut ppu);
+            let mut writer = StateWriter::new();
+            ppu.save_state(&mut writer);
+            let data = writer.into_inner();
+            assert_eq!(
+                ppu.load_state(&mut StateReader::new(&data)),
+                Err(StateError::InvalidFormat)
+            );
+        }
+    }
+
+    #[test]
+    fn load_state_keeps_window() {
+        // The window uses tile 1, with color 3 on even rows, from scanline 10. The background uses tile 0.
+        let mut ppu = Ppu::new(CartridgeMode::Gb);
+        for i in (0..16).step_by(4) {
+            ppu.set_byte(0x8010 + i, 0xFF);
+            ppu.set_byte(0x8011 + i, 0xFF);
+        }
+        for i in 0..0x400 {
+            ppu.set_byte(0x9C00 + i, 1);
+        }
+        ppu.set_byte(0xFF47, 0xE4);
+        ppu.set_byte(0xFF4A, 10);
+        ppu.set_byte(0xFF4B, 7 + 40);
+        ppu.set_byte(0xFF40, 0xF1);
+        run_until(&mut ppu, |p| p.lcdc_y == 72 && p.lcd_status.mode == 0);
+        let mut writer = StateWriter::new();
+        ppu.save_state(&mut writer);
+        let data = writer.into_inner();
+        run_until(&mut ppu, |p| p.lcdc_y == 144);
+        let frame = ppu.data;
+        ppu.load_state(&mut StateReader::new(&data)).unwrap();
+        // Clear the screen, so the rest of the frame must be drawn again.
+        ppu.data = [Pixel::new(); SCREEN_WIDTH * SCREEN_HEIGHT];
+        run_until(&mut ppu, |p| p.lcdc_y == 144);
+        assert!(frame[73 * SCREEN_WIDTH..] == ppu.data[73 * SCREEN_WIDTH..]);
+        // Check the window was drawn after the state was saved.
+        let black = DmgPalette::GREY.bg[3];
+        assert_eq!(frame[80 * SCREEN_WIDTH + 40], black);
+        assert_ne!(frame[81 * SCREEN_WIDTH + 40], black);
+        assert_ne!(frame[80 * SCREEN_WIDTH + 39], black);
+    }
+}
@@ -0,0 +1,161 @@
+// OAM Scan
+// During mode 2 the PPU searches the OAM for the objects on the current scanline, an object is selected when the
+// scanline is within its height (8 or 16 pixels, LCDC bit 2). The search stops after 10 objects, in OAM order,
+// any other objects on the scanline are not drawn. The X coordinate is not checked, so objects hidden by their
+// X coordinate (X=0 or X>=168) still count towards the limit.
+// When the selected objects overlap:
+//   - In Non CGB Mode the object with the smaller X coordinate is drawn on top, objects with the same X
+//     coordinate are ordered by their OAM position.
+//   - In CGB Mode only the OAM position counts, the first object in the OAM is drawn on top.
+// Only the object pixel on top is mixed with the background: when it has priority bit 7 set (behind BG colors
+// 1-3) the background hides it, even if an object below it would be drawn above the background.
+
+use crate::ppu::Ppu;
+
+// The number of objects selected by the OAM scan of a scanline.
+const OBJECTS_PER_LINE: usize = 10;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Object {
+    pub y: u8,
+    pub x: u8,
+    pub tile_number: u8,
+    pub flags: u8,
+    // The position in the OAM (0-39).
+    pub index: usize,
+    // The height (8 or 16) when the object was selected, LCDC bit 2 can change before it is fetched.
+    pub height: u8,
+}
+
+impl Ppu {
+    // Select the objects on the current scanline, in OAM order.
+    pub(super) fn scan_oam(&self, objects: &mut Vec<Object>) {
+        objects.clear();
+        let sprite_size = if self.lcd_control.has_bit2() { 16 } else { 8 };
+        let line = u16::from(self.lcdc_y) + 16;
+        for (index, object) in self.oam.chunks_exact(4).enumerate() {
+            let y = u16::from(object[0]);
+            if line >= y && line < y + sprite_size {
+                objects.push(Object {
+                    y: object[0],
+                    x: object[1],
+                    tile_number: object[2],
+                    flags: object[3],
+                    index,
+                    height: sprite_size as u8,
+                });
+                if objects.len() == OBJECTS_PER_LINE {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cartridges::CartridgeMode;
+    use crate::memory::Memory;
+    use crate::ppu::{DmgPalette, Pixel, Ppu, Renderer, SCREEN_WIDTH};
+
+    const RENDERERS: [Renderer; 2] = [Renderer::Fifo, Renderer::Scanline];
+    const RED: usize = 0x001F;
+    const BLUE: usize = 0x7C00;
+
+    // Tile 1 uses color 1 and tile 2 color 3 for every pixel. CGB palette 0 maps color 1 to red and
+    // color 3 to blue.
+    fn ppu(mode: CartridgeMode, renderer: Renderer) -> Ppu {
+        let mut ppu = Ppu::new(mode);
+        ppu.set_renderer(renderer);
+        for i in 0..16 {
+            ppu.set_byte(0x8010 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+            ppu.set_byte(0x8020 + i, 0xFF);
+        }
+        ppu.set_byte(0xFF47, 0xE4);
+        ppu.set_byte(0xFF48, 0xE4);
+        for index in [0xFF68, 0xFF6A] {
+            ppu.set_byte(index, 0x80);
+            for color in [0x0000, RED, 0x0000, BLUE] {
+                ppu.set_byte(index + 1, color as u8);
+                ppu.set_byte(index + 1, (color >> 8) as u8);
+            }
+        }
+        ppu
+    }
+
+    // Place an object on scanline 0.
+    fn set_object(ppu: &mut Ppu, index: u16, x: u8, tile_number: u8, flags: u8) {
+        let addr = 0xFE00 + index * 4;
+        ppu.set_byte(addr, 16);
+        ppu.set_byte(addr + 1, x);
+        ppu.set_byte(addr + 2, tile_number);
+        ppu.set_byte(addr + 3, flags);
+    }
+
+    // Enable the LCD and draw scanline 0.
+    fn draw_line(ppu: &mut Ppu, lcd_control: u8) -> Vec<Pixel> {
+        ppu.set_byte(0xFF40, lcd_control);
+        while ppu.lcdc_y == 0 {
+            ppu.run_cycles(1);
+        }
+        ppu.data[..SCREEN_WIDTH].to_vec()
+    }
+
+    #[test]
+    fn ten_objects_per_line() {
+        for renderer in RENDERERS {
+            let mut ppu = ppu(CartridgeMode::Gb, renderer);
+            for i in 0..11 {
+                set_object(&mut ppu, i, 8 + 8 * i as u8, 1, 0x00);
+            }
+            let line = draw_line(&mut ppu, 0x93);
+            assert_eq!(line[72], DmgPalette::GREY.obp0[1], "{:?}", renderer);
+            assert_eq!(line[80], DmgPalette::GREY.bg[0], "{:?}", renderer);
+        }
+    }
+
+    #[test]
+    fn dmg_x_priority() {
+        for renderer in RENDERERS {
+            let mut ppu = ppu(CartridgeMode::Gb, renderer);
+            set_object(&mut ppu, 0, 8 + 4, 1, 0x00);
+            set_object(&mut ppu, 1, 8, 2, 0x00);
+            let line = draw_line(&mut ppu, 0x93);
+            assert_eq!(line[4], DmgPalette::GREY.obp0[3], "{:?}", renderer);
+            assert_eq!(line[8], DmgPalette::GREY.obp0[1], "{:?}", renderer);
+        }
+    }
+
+    #[test]
+    fn cgb_oam_priority() {
+        for renderer in RENDERERS {
+            let mut ppu = ppu(CartridgeMode::Gbc, renderer);
+            set_object(&mut ppu, 0, 8 + 4, 1, 0x00);
+            set_object(&mut ppu, 1, 8, 2, 0x00);
+            let line = draw_line(&mut ppu, 0x93);
+            assert_eq!(line[2], ppu.color_table[BLUE], "{:?}", renderer);
+            assert_eq!(line[4], ppu.color_table[RED], "{:?}", renderer);
+        }
+    }
+
+    #[test]
+    fn cgb_master_priority() {
+        for renderer in RENDERERS {
+            // The background uses tile 0 with color 1, except for tile 3 (color 0) at X = 16. The first object
+            // is behind the background.
+            for lcd_control in [0x93, 0x92] {
+                let mut ppu = ppu(CartridgeMode::Gbc, renderer);
+                for i in (0..16).step_by(2) {
+                    ppu.set_byte(0x8000 + i, 0xFF);
+                }
+                ppu.set_byte(0x9802, 3);
+                set_object(&mut ppu, 0, 8, 2, 0x80);
+                set_object(&mut ppu, 1, 8 + 16, 2, 0x00);
+                let line = draw_line(&mut ppu, lcd_control);
+                let expected = if lcd_control & 0x01 != 0 { RED } else { BLUE };
+                assert_eq!(line[0], ppu.color_table[expected], "{:?}", renderer);
+                assert_eq!(line[16], ppu.color_table[BLUE], "{:?}", renderer);
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// FF46 - DMA - DMA Transfer and Start Address (R/W)
+// Writing to this register launches a DMA transfer from ROM or RAM to OAM memory (sprite attribute table). The
+// written value specifies the transfer source address divided by 100h, ie. source and destinations are:
+//   Source:      XX00-XX9F   ;XX in range from 00-FFh
+//   Destination: FE00-FE9F
+// The transfer starts one M-cycle after the write and copies one byte per M-cycle, taking 160 M-cycles (640 dots
+// in normal speed, 320 dots in double speed). While it runs the CPU can only access HRAM (and the I/O registers),
+// which is why the transfer is usually started by a routine copied to HRAM. Reads from other memory return FFh,
+// writes are ignored.
+// Sources E0-FF are past the end of WRAM, the DMA reads them from C000-DFFF (the same as the echo of WRAM, which
+// also covers FE00-FFFF for the DMA).
+
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
+
+const OAM_SIZE: u16 = 0xA0;
+const CYCLES_PER_BYTE: u32 = 4;
+
+#[derive(Clone)]
+pub struct OamDma {
+    // The value last written to FF46.
+    pub register: u8,
+    source: u16,
+    // The number of bytes transferred, the transfer is complete at OAM_SIZE.
+    index: u16,
+    // The M-cycles left before the transfer starts.
+    delay: u8,
+    // CPU cycles not yet used for a full M-cycle.
+    cycles: u32,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            source: 0x0000,
+            index: OAM_SIZE,
+            delay: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn start(&mut self, value: u8) {
+        self.register = value;
+        let page = if value >= 0xE0 { value - 0x20 } else { value };
+        self.source = u16::from(page) << 8;
+        self.index = 0;
+        self.delay = 1;
+        self.cycles = 0;
+    }
+
+    // The CPU is restricted to HRAM while the transfer is running.
+    pub fn is_active(&self) -> bool {
+        self.index < OAM_SIZE && self.delay == 0
+    }
+
+    // Advance by `cycles` CPU cycles, returning the number of bytes to transfer using `next`.
+    pub fn run_cycles(&mut self, cycles: u32) -> u16 {
+        if self.index >= OAM_SIZE {
+            return 0;
+        }
+        self.cycles += cycles;
+        let mut steps = self.cycles / CYCLES_PER_BYTE;
+        self.cycles %= CYCLES_PER_BYTE;
+        let delay = steps.min(u32::from(self.delay));
+        self.delay -= delay as u8;
+        steps -= delay;
+        steps.min(u32::from(OAM_SIZE - self.index)) as u16
+    }
+
+    // Get the source and destination address of the next byte.
+    pub fn next(&mut self) -> (u16, u16) {
+        let index = self.index;
+        self.index += 1;
+        (self.source + index, 0xFE00 + index)
+    }
+}
+
+impl Stateful for OamDma {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register);
+        writer.write_u16(self.source);
+        writer.write_u16(self.index);
+        writer.write_u8(self.delay);
+        writer.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.register = reader.read_u8()?;
+        self.source = reader.read_u16()?;
+        self.index = reader.read_u16()?.min(OAM_SIZE);
+        self.delay = reader.read_u8()?;
+        self.cycles = reader.read_u32()? % CYCLES_PER_BYTE;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OamDma;
+
+    #[test]
+    fn transfer() {
+        let mut dma = OamDma::new();
+        assert!(!dma.is_active());
+        dma.start(0xFE);
+        assert_eq!(dma.register, 0xFE);
+        // The first M-cycle is a delay
+        assert_eq!(dma.run_cycles(4), 0);
+        assert!(dma.is_active());
+        assert_eq!(dma.run_cycles(6), 1);
+        assert_eq!(dma.next(), (0xDE00, 0xFE00));
+        assert_eq!(dma.run_cycles(2), 1);
+        assert_eq!(dma.next(), (0xDE01, 0xFE01));
+        assert_eq!(dma.run_cycles(1000), 158);
+        for _ in 0..158 {
+            dma.next();
+        }
+        assert!(!dma.is_active());
+        assert_eq!(dma.run_cycles(4), 0);
+    }
+}
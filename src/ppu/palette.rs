@@ -0,0 +1,56 @@
+// DMG Palettes
+// The DMG has no colors, the palette registers (BGP, OBP0 and OBP1) map color numbers to one of four shades
+// (white, light grey, dark grey and black) which are then displayed by the LCD. How these shades look differs
+// between models: the original DMG has a green tinted screen, the Pocket a grey one and the Light a backlit
+// blue-green one. A DmgPalette maps the shades to colors, with a separate table for the background/window and
+// each object palette (this is also how the CGB colorizes DMG games).
+
+use crate::ppu::Pixel;
+
+const fn rgb(value: u32) -> Pixel {
+    Pixel {
+        r: (value >> 16) as u8,
+        g: (value >> 8) as u8,
+        b: value as u8,
+    }
+}
+
+/// DmgPalette maps the four shades of a DMG (white, light grey, dark grey and
+/// black) to colors, for the background/window (BGP) and both object palettes
+/// (OBP0 and OBP1). It has no effect in CGB mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DmgPalette {
+    pub bg: [Pixel; 4],
+    pub obp0: [Pixel; 4],
+    pub obp1: [Pixel; 4],
+}
+
+impl DmgPalette {
+    /// Plain greys, the default.
+    pub const GREY: DmgPalette =
+        DmgPalette::uniform([rgb(0xFFFFFF), rgb(0xC0C0C0), rgb(0x606060), rgb(0x000000)]);
+    /// The green tinted screen of the original Game Boy (DMG).
+    pub const CLASSIC_GREEN: DmgPalette =
+        DmgPalette::uniform([rgb(0x9BBC0F), rgb(0x8BAC0F), rgb(0x306230), rgb(0x0F380F)]);
+    /// The grey screen of the Game Boy Pocket (MGB).
+    pub const POCKET: DmgPalette =
+        DmgPalette::uniform([rgb(0xC4CFA1), rgb(0x8B956D), rgb(0x4D533C), rgb(0x1F1F1F)]);
+    /// The backlit screen of the Game Boy Light.
+    pub const LIGHT: DmgPalette =
+        DmgPalette::uniform([rgb(0x00B581), rgb(0x009A71), rgb(0x00694A), rgb(0x004F3B)]);
+
+    /// Create a palette using the same colors for the background and objects.
+    pub const fn uniform(colors: [Pixel; 4]) -> DmgPalette {
+        DmgPalette {
+            bg: colors,
+            obp0: colors,
+            obp1: colors,
+        }
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> DmgPalette {
+        DmgPalette::GREY
+    }
+}
@@ -0,0 +1,395 @@
+// VRAM Viewers
+// The viewers draw the contents of VRAM, OAM and the CGB palette memory as images, the way the PPU would use
+// them, to inspect the graphics of a game:
+//   - The tile sheet shows the 384 tiles of a VRAM bank (8000-97FF), 16 tiles per row.
+//   - A tile map shows one of the 32x32 maps (9800-9BFF or 9C00-9FFF) using the tile data selected by LCDC bit
+//     4. In CGB Mode each tile is drawn with the palette, VRAM bank and flips of its attribute in VRAM bank 1.
+//     The area of the map shown on the screen (SCX, SCY) can be outlined, wrapping around the edges.
+//   - The object sheet shows the 40 OAM entries, 8 per row, with their palette and flips. Each entry has room
+//     for an 8x16 object, 8x8 objects (LCDC bit 2) only use the top half.
+//   - The palette sheet shows the 8 background and 8 object palettes of the CGB, one palette per row.
+// In Non CGB Mode the tiles and maps use BGP and the objects use OBP0 and OBP1, mapped to colors like the screen.
+
+use crate::cartridges::CartridgeMode;
+use crate::ppu::attribute::Attribute;
+use crate::ppu::{Pixel, Ppu};
+
+// 384 tiles, 16 tiles per row.
+const TILES: usize = 384;
+const TILES_PER_ROW: usize = 16;
+// 40 objects, 8 per row. Each object is drawn in a cell with a 1 pixel border.
+const OBJECTS: usize = 40;
+const OBJECTS_PER_ROW: usize = 8;
+const OBJECT_CELL_WIDTH: usize = 8 + 2;
+const OBJECT_CELL_HEIGHT: usize = 16 + 2;
+// The color shown behind the transparent pixels of objects.
+const OBJECT_BACKGROUND: Pixel = Pixel {
+    r: 0x80,
+    g: 0x80,
+    b: 0x80,
+};
+// Each palette color is drawn as a square.
+const SWATCH_SIZE: usize = 8;
+
+/// Image is an RGB image drawn by the VRAM viewers, stored row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<Pixel>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize, pixel: Pixel) -> Image {
+        Image {
+            width,
+            height,
+            data: vec![pixel; width * height],
+        }
+    }
+
+    /// Get the pixel at column `x` of row `y`.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Pixel {
+        self.data[y * self.width + x]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) {
+        self.data[y * self.width + x] = pixel;
+    }
+
+    fn invert_pixel(&mut self, x: usize, y: usize) {
+        let pixel = self.get_pixel(x, y);
+        self.set_pixel(
+            x,
+            y,
+            Pixel {
+                r: !pixel.r,
+                g: !pixel.g,
+                b: !pixel.b,
+            },
+        );
+    }
+}
+
+/// OamEntry is an object of the OAM with its attributes decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamEntry {
+    /// The position in the OAM (0-39).
+    pub index: usize,
+    /// The vertical position on the screen plus 16.
+    pub y: u8,
+    /// The horizontal position on the screen plus 8.
+    pub x: u8,
+    pub tile_number: u8,
+    /// The object is behind background colors 1-3.
+    pub behind_bg: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    /// OBP0-1 in Non CGB Mode, OBP0-7 in CGB Mode.
+    pub palette: usize,
+    /// The VRAM bank of the tile, always 0 in Non CGB Mode.
+    pub vram_bank: usize,
+}
+
+impl Ppu {
+    // Get the color number (0-3) of a pixel of a tile, `tile` is the index of the tile in the bank (0-383).
+    fn get_tile_color(&self, bank: usize, tile: usize, x: usize, y: usize) -> usize {
+        let addr = bank * 0x2000 + tile * 16 + y * 2;
+        let low = self.vram[addr] >> (7 - x) & 0x01;
+        let high = self.vram[addr + 1] >> (7 - x) & 0x01;
+        usize::from((high << 1) | low)
+    }
+
+    fn get_cgb_color(&self, rgb: [u8; 3]) -> Pixel {
+        let [r, g, b] = rgb.map(usize::from);
+        self.color_table[r | (g << 5) | (b << 10)]
+    }
+
+    // The color of a background color number, `palette` is only used in CGB Mode.
+    fn get_bg_color(&self, palette: usize, color: usize) -> Pixel {
+        if self.mode == CartridgeMode::Gbc {
+            return self.get_cgb_color(self.bgp_data[palette][color]);
+        }
+        let shade = self.get_shade(self.bg_palette, color);
+        if self.dmg_compatibility {
+            self.get_cgb_color(self.bgp_data[0][shade])
+        } else {
+            self.dmg_palette.bg[shade]
+        }
+    }
+
+    fn get_object_color(&self, attribute: &Attribute, color: usize) -> Pixel {
+        if self.mode == CartridgeMode::Gbc {
+            return self.get_cgb_color(self.obp_data[attribute.cgb_palette_number][color]);
+        }
+        let (palette, colors) = if attribute.palette_number == 1 {
+            (self.object_pallete_1, self.dmg_palette.obp1)
+        } else {
+            (self.object_pallete_0, self.dmg_palette.obp0)
+        };
+        let shade = self.get_shade(palette, color);
+        if self.dmg_compatibility {
+            self.get_cgb_color(self.obp_data[attribute.palette_number][shade])
+        } else {
+            colors[shade]
+        }
+    }
+
+    // Draw the 384 tiles of a VRAM bank (0-1) with background palette 0.
+    pub fn get_tile_sheet(&self, bank: usize) -> Image {
+        assert!(bank <= 1, "ppu: invalid vram bank ({})", bank);
+        let rows = TILES / TILES_PER_ROW;
+        let mut image = Image::new(TILES_PER_ROW * 8, rows * 8, Pixel::new());
+        for tile in 0..TILES {
+            let tile_x = tile % TILES_PER_ROW * 8;
+            let tile_y = tile / TILES_PER_ROW * 8;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = self.get_tile_color(bank, tile, x, y);
+                    image.set_pixel(tile_x + x, tile_y + y, self.get_bg_color(0, color));
+                }
+            }
+        }
+        image
+    }
+
+    // Draw tile map 0 (9800-9BFF) or 1 (9C00-9FFF), outlining the area shown on the screen when `viewport` is set
+    // and the map is used for the background.
+    pub fn get_tile_map(&self, map: usize, viewport: bool) -> Image {
+        assert!(map <= 1, "ppu: invalid tile map ({})", map);
+        let mut image = Image::new(256, 256, Pixel::new());
+        let base = 0x1800 + map * 0x400;
+        for tile_y in 0..32 {
+            for tile_x in 0..32 {
+                let offset = base + tile_y * 32 + tile_x;
+                let tile_number = self.vram[offset];
+                let tile = if self.lcd_control.has_bit4() {
+                    usize::from(tile_number)
+                } else {
+                    (256 + i16::from(tile_number as i8)) as usize
+                };
+                // BG Map Attributes are only available in CGB Mode.
+                let attribute = if self.mode == CartridgeMode::Gbc {
+                    Attribute::from(self.vram[0x2000 + offset])
+                } else {
+                    Attribute::from(0x00)
+                };
+                let bank = usize::from(attribute.vram_bank);
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let tile_pixel_x = if attribute.x_flip { 7 - x } else { x };
+                        let tile_pixel_y = if attribute.y_flip { 7 - y } else { y };
+                        let color = self.get_tile_color(bank, tile, tile_pixel_x, tile_pixel_y);
+                        let pixel = self.get_bg_color(attribute.cgb_palette_number, color);
+                        image.set_pixel(tile_x * 8 + x, tile_y * 8 + y, pixel);
+                    }
+                }
+            }
+        }
+        if viewport && usize::from(self.lcd_control.has_bit3()) == map {
+            let scroll_x = usize::from(self.scroll_x);
+            let scroll_y = usize::from(self.scroll_y);
+            let bottom = scroll_y + super::SCREEN_HEIGHT - 1;
+            let right = scroll_x + super::SCREEN_WIDTH - 1;
+            for x in scroll_x..=right {
+                image.invert_pixel(x % 256, scroll_y);
+                image.invert_pixel(x % 256, bottom % 256);
+            }
+            for y in scroll_y + 1..bottom {
+                image.invert_pixel(scroll_x, y % 256);
+                image.invert_pixel(right % 256, y % 256);
+            }
+        }
+        image
+    }
+
+    // Decode the 40 OAM entries.
+    pub fn get_oam_entries(&self) -> Vec<OamEntry> {
+        self.oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(index, object)| {
+                let attribute = Attribute::from(object[3]);
+                let cgb = self.mode == CartridgeMode::Gbc;
+                OamEntry {
+                    index,
+                    y: object[0],
+                    x: object[1],
+                    tile_number: object[2],
+                    behind_bg: attribute.priority,
+                    y_flip: attribute.y_flip,
+                    x_flip: attribute.x_flip,
+                    palette: if cgb {
+                        attribute.cgb_palette_number
+                    } else {
+                        attribute.palette_number
+                    },
+                    vram_bank: usize::from(cgb && attribute.vram_bank),
+                }
+            })
+            .collect()
+    }
+
+    // Draw the 40 objects of the OAM with their palette and flips.
+    pub fn get_object_sheet(&self) -> Image {
+        let rows = OBJECTS / OBJECTS_PER_ROW;
+        let mut image = Image::new(
+            OBJECTS_PER_ROW * OBJECT_CELL_WIDTH,
+            rows * OBJECT_CELL_HEIGHT,
+            Pixel::new(),
+        );
+        let height = if self.lcd_control.has_bit2() { 16 } else { 8 };
+        for (index, object) in self.oam.chunks_exact(4).enumerate() {
+            let attribute = Attribute::from(object[3]);
+            let bank = usize::from(self.mode == CartridgeMode::Gbc && attribute.vram_bank);
+            let tile = if height == 16 {
+                usize::from(object[2] & 0xFE)
+            } else {
+                usize::from(object[2])
+            };
+            let cell_x = index % OBJECTS_PER_ROW * OBJECT_CELL_WIDTH + 1;
+            let cell_y = index / OBJECTS_PER_ROW * OBJECT_CELL_HEIGHT + 1;
+            for y in 0..height {
+                let object_y = if attribute.y_flip { height - 1 - y } else { y };
+                for x in 0..8 {
+                    let object_x = if attribute.x_flip { 7 - x } else { x };
+                    let color =
+                        self.get_tile_color(bank, tile + object_y / 8, object_x, object_y % 8);
+                    let pixel = if color == 0 {
+                        OBJECT_BACKGROUND
+                    } else {
+                        self.get_object_color(&attribute, color)
+                    };
+                    image.set_pixel(cell_x + x, cell_y + y, pixel);
+                }
+            }
+        }
+        image
+    }
+
+    // Draw the 8 background palettes followed by the 8 object palettes, one palette per row.
+    pub fn get_palette_sheet(&self) -> Image {
+        let mut image = Image::new(4 * SWATCH_SIZE, 16 * SWATCH_SIZE, Pixel::new());
+        let palettes = self.bgp_data.iter().chain(self.obp_data.iter());
+        for (row, palette) in palettes.enumerate() {
+            for (column, &rgb) in palette.iter().enumerate() {
+                let pixel = self.get_cgb_color(rgb);
+                for y in 0..SWATCH_SIZE {
+                    for x in 0..SWATCH_SIZE {
+                        image.set_pixel(column * SWATCH_SIZE + x, row * SWATCH_SIZE + y, pixel);
+                    }
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cartridges::CartridgeMode;
+    use crate::memory::Memory;
+    use crate::ppu::{DmgPalette, Ppu};
+
+    const RED: u16 = 0x001F;
+    const BLUE: u16 = 0x7C00;
+
+    // Tile 1 uses color 1 for every pixel except the top left one (color 3). In CGB Mode background palette 2
+    // and object palette 5 map color 1 to red and color 3 to blue.
+    fn ppu(mode: CartridgeMode) -> Ppu {
+        let mut ppu = Ppu::new(mode);
+        for i in 0..16 {
+            ppu.set_byte(0x8010 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        ppu.set_byte(0x8011, 0x80);
+        for (index, palette) in [(0xFF68, 2), (0xFF6A, 5)] {
+            ppu.set_byte(index, 0x80 | (palette * 8));
+            for color in [0x0000, RED, 0x0000, BLUE] {
+                ppu.set_byte(index + 1, color as u8);
+                ppu.set_byte(index + 1, (color >> 8) as u8);
+            }
+        }
+        ppu
+    }
+
+    #[test]
+    fn tile_sheet() {
+        let ppu = ppu(CartridgeMode::Gbc);
+        let sheet = ppu.get_tile_sheet(1);
+        assert_eq!((sheet.width, sheet.height), (128, 192));
+        // Background palette 0 is black.
+        assert_eq!(sheet.get_pixel(8, 0), ppu.color_table[0]);
+        let mut ppu = Ppu::new(CartridgeMode::Gb);
+        ppu.set_byte(0x8010, 0xFF);
+        ppu.set_byte(0x8011, 0x80);
+        ppu.set_byte(0xFF47, 0xE4);
+        let sheet = ppu.get_tile_sheet(0);
+        assert_eq!(sheet.get_pixel(8, 0), DmgPalette::GREY.bg[3]);
+        assert_eq!(sheet.get_pixel(9, 0), DmgPalette::GREY.bg[1]);
+        assert_eq!(sheet.get_pixel(0, 0), DmgPalette::GREY.bg[0]);
+    }
+
+    #[test]
+    fn tile_map() {
+        let mut ppu = ppu(CartridgeMode::Gbc);
+        // Map 1 uses tile 1 with palette 2, flipped horizontally, at tile (1, 0).
+        ppu.set_byte(0x9C01, 1);
+        ppu.set_byte(0xFF4F, 1);
+        ppu.set_byte(0x9C01, 0x22);
+        ppu.set_byte(0xFF40, 0x18);
+        ppu.set_byte(0xFF43, 250);
+        ppu.set_byte(0xFF42, 4);
+        let map = ppu.get_tile_map(1, false);
+        assert_eq!((map.width, map.height), (256, 256));
+        assert_eq!(map.get_pixel(15, 0), ppu.color_table[BLUE as usize]);
+        assert_eq!(map.get_pixel(8, 0), ppu.color_table[RED as usize]);
+        // The viewport wraps around the right edge.
+        let viewport = ppu.get_tile_map(1, true);
+        assert_eq!(viewport.get_pixel(15, 0), map.get_pixel(15, 0));
+        assert_ne!(viewport.get_pixel(15, 4), map.get_pixel(15, 4));
+        assert_ne!(viewport.get_pixel(250, 4), map.get_pixel(250, 4));
+        assert_ne!(viewport.get_pixel(153, 100), map.get_pixel(153, 100));
+        assert_eq!(viewport.get_pixel(154, 100), map.get_pixel(154, 100));
+        assert_ne!(viewport.get_pixel(0, 147), map.get_pixel(0, 147));
+        // Map 0 is not used for the background.
+        assert_eq!(ppu.get_tile_map(0, true), ppu.get_tile_map(0, false));
+    }
+
+    #[test]
+    fn objects() {
+        let mut ppu = ppu(CartridgeMode::Gbc);
+        ppu.set_byte(0xFE04, 16);
+        ppu.set_byte(0xFE05, 8);
+        ppu.set_byte(0xFE06, 1);
+        ppu.set_byte(0xFE07, 0xC5);
+        let entries = ppu.get_oam_entries();
+        assert_eq!(entries.len(), 40);
+        let entry = entries[1];
+        assert_eq!(
+            (entry.index, entry.y, entry.x, entry.tile_number),
+            (1, 16, 8, 1)
+        );
+        assert!(entry.behind_bg && entry.y_flip && !entry.x_flip);
+        assert_eq!((entry.palette, entry.vram_bank), (5, 0));
+        let sheet = ppu.get_object_sheet();
+        assert_eq!((sheet.width, sheet.height), (80, 90));
+        // Object 1 is flipped vertically, its top left pixel is on the last row.
+        assert_eq!(sheet.get_pixel(11, 8), ppu.color_table[BLUE as usize]);
+        assert_eq!(sheet.get_pixel(11, 1), ppu.color_table[RED as usize]);
+        // Object 0 uses tile 0, which is transparent.
+        assert_eq!(sheet.get_pixel(1, 1), super::OBJECT_BACKGROUND);
+    }
+
+    #[test]
+    fn palette_sheet() {
+        let ppu = ppu(CartridgeMode::Gbc);
+        let sheet = ppu.get_palette_sheet();
+        assert_eq!((sheet.width, sheet.height), (32, 128));
+        assert_eq!(sheet.get_pixel(8, 2 * 8), ppu.color_table[RED as usize]);
+        assert_eq!(
+            sheet.get_pixel(31, (8 + 5) * 8 + 7),
+            ppu.color_table[BLUE as usize]
+        );
+        assert_eq!(sheet.get_pixel(8, 0), ppu.color_table[0]);
+    }
+}
@@ -0,0 +1,198 @@
+// Rewind
+// Rewinding is built on top of save states. Every few frames a snapshot of the complete machine is captured and kept
+// in a ring buffer, rewinding then simply loads the snapshots in reverse order.
+//
+// A snapshot is well over 100KB, so only the newest snapshot is kept in full. Every older snapshot is stored as a
+// delta against the snapshot captured after it: the XOR of both snapshots (which is mostly zeros, as consecutive
+// snapshots only differ in a few places) run length encoded as follows (all values are little endian):
+//   4 bytes  Length of the older snapshot
+//   Followed by runs of:
+//     4 bytes  Number of bytes that are unchanged
+//     4 bytes  Number of bytes that changed (N)
+//     N bytes  The changed bytes XOR'd with the newer snapshot
+// When the memory budget is exceeded the oldest deltas are discarded, as they are not needed to restore newer
+// snapshots.
+
+use std::collections::VecDeque;
+
+// Changes separated by fewer unchanged bytes than this are merged into a single run, as starting a new run
+// costs 8 bytes.
+const MIN_UNCHANGED_RUN: usize = 8;
+
+/// RewindOptions control how often snapshots are captured for rewinding and how
+/// much memory they may use.
+#[derive(Debug, Clone, Copy)]
+pub struct RewindOptions {
+    /// Capture a snapshot every `interval` frames. Each call to `rewind_step`
+    /// moves back by this many frames.
+    pub interval: u32,
+    /// The maximum number of bytes used to store snapshots. The oldest snapshots
+    /// are discarded when this is exceeded.
+    pub memory_budget: usize,
+}
+
+impl Default for RewindOptions {
+    fn default() -> RewindOptions {
+        RewindOptions {
+            interval: 4,
+            memory_budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct Rewind {
+    options: RewindOptions,
+    frames: u32,
+    // The newest snapshot, stored in full.
+    latest: Option<Vec<u8>>,
+    // Each delta restores the snapshot before the one it is applied to, the newest delta is at the back.
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+}
+
+impl Rewind {
+    pub fn new(options: RewindOptions) -> Rewind {
+        Rewind {
+            options,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+
+    // Called once per frame, returning true when a snapshot should be captured.
+    pub fn tick(&mut self) -> bool {
+        self.frames += 1;
+        if self.frames < self.options.interval.max(1) {
+            return false;
+        }
+        self.frames = 0;
+        true
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            let delta = encode(&snapshot, &latest);
+            self.deltas_size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(snapshot);
+        let latest_size = self.latest.as_ref().map_or(0, Vec::len);
+        while self.deltas_size + latest_size > self.options.memory_budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Take the newest snapshot, the one before it becomes the newest.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let snapshot = self.latest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.deltas_size -= delta.len();
+            self.latest = Some(decode(&snapshot, &delta));
+        }
+        self.frames = 0;
+        Some(snapshot)
+    }
+}
+
+// Create a delta which turns `from` into `to`.
+fn encode(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = to
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ from.get(i).copied().unwrap_or(0x00))
+        .collect();
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(to.len() as u32).to_le_bytes());
+    let mut i = 0;
+    while i < xor.len() {
+        let start = i;
+        while i < xor.len() && xor[i] == 0x00 {
+            i += 1;
+        }
+        let unchanged = i - start;
+        if i == xor.len() {
+            break;
+        }
+        let literal_start = i;
+        let mut zeros = 0;
+        while i < xor.len() && zeros < MIN_UNCHANGED_RUN {
+            zeros = if xor[i] == 0x00 { zeros + 1 } else { 0 };
+            i += 1;
+        }
+        let literal_end = i - zeros;
+        i = literal_end;
+        delta.extend_from_slice(&(unchanged as u32).to_le_bytes());
+        delta.extend_from_slice(&((literal_end - literal_start) as u32).to_le_bytes());
+        delta.extend_from_slice(&xor[literal_start..literal_end]);
+    }
+    delta
+}
+
+// Apply a delta created by `encode` to `from`.
+fn decode(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u32 = |position: usize| {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(&delta[position..position + 4]);
+        u32::from_le_bytes(b) as usize
+    };
+    let mut to = from.to_vec();
+    to.resize(read_u32(0), 0x00);
+    let mut position = 4;
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_u32(position);
+        let changed = read_u32(position + 4);
+        position += 8;
+        for b in &delta[position..position + changed] {
+            to[i] ^= b;
+            i += 1;
+        }
+        position += changed;
+    }
+    to
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rewind, RewindOptions, decode, encode};
+
+    #[test]
+    fn delta_round_trip() {
+        let from: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut to = from.clone();
+        to[0] = 0xFF;
+        to[10] = 0x00;
+        to[12] = 0x00;
+        to[500..520].fill(0xAA);
+        to[999] = 0x42;
+        let delta = encode(&from, &to);
+        assert!(delta.len() < 100);
+        assert_eq!(decode(&from, &delta), to);
+        assert_eq!(decode(&to, &encode(&to, &from)), from);
+        assert_eq!(decode(&from, &encode(&from, &to[..600])), &to[..600]);
+        assert_eq!(decode(&to[..600], &encode(&to[..600], &from)), from);
+    }
+
+    #[test]
+    fn respects_memory_budget() {
+        let mut rewind = Rewind::new(RewindOptions {
+            interval: 1,
+            memory_budget: 1100,
+        });
+        for i in 0..10u8 {
+            let mut snapshot = vec![0x00; 1000];
+            snapshot[0] = i;
+            rewind.push(snapshot);
+        }
+        // The newest snapshot is 1000 bytes and each delta is 4 + 8 + 1 bytes, so only 7 deltas fit.
+        for i in (2..10u8).rev() {
+            assert_eq!(rewind.pop().unwrap()[0], i);
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+}
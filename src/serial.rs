@@ -51,6 +51,7 @@
 
 use crate::memory::Memory;
 use crate::mmu::InterruptFlag;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Serial {
@@ -93,6 +94,21 @@ impl Memory for Serial {
     }
 }
 
+impl Stateful for Serial {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_u8(self.control);
+        writer.write_u8(self.interrupt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.data = reader.read_u8()?;
+        self.control = reader.read_u8()?;
+        self.interrupt = reader.read_u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::InterruptFlag;
@@ -0,0 +1,251 @@
+// Save States
+// A save state is a snapshot of the complete machine (CPU, MMU, PPU, APU, Timer, Serial, Joypad and the
+// cartridge MBC including its RAM and RTC). Each component knows how to write and read its own state using
+// the Stateful trait, the Gameboy then simply chains them together in a fixed order.
+//
+// Layout (all values are little endian):
+//   0x00  4 bytes   Magic ("GBRS")
+//   0x04  4 bytes   Format version
+//   0x08  28 bytes  Cartridge header (0134-014F), used to reject states made with a different ROM
+//   0x24  ...       Component data
+//
+// Variable length data (RAM, VRAM, etc) is written with a 4 byte length prefix.
+
+use std::fmt;
+
+const STATE_MAGIC: [u8; 4] = *b"GBRS";
+const STATE_VERSION: u32 = 1;
+
+// 0134-014F - Title, Manufacturer Code, CGB Flag, Licensee Code, SGB Flag, Cartridge Type, ROM Size,
+// RAM Size, Destination Code, Old Licensee Code, Mask ROM Version, Header Checksum and Global Checksum.
+pub const HEADER_START: u16 = 0x0134;
+pub const HEADER_END: u16 = 0x014F;
+
+/// StateError represents the reasons a save state can fail to load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data is not a save state or has been corrupted.
+    InvalidFormat,
+    /// The save state was created by an incompatible version of the emulator.
+    UnsupportedVersion(u32),
+    /// The save state was created using a different ROM.
+    RomMismatch,
+    /// The save state ended before all data could be read.
+    UnexpectedEnd,
+    /// The save state was created while running a boot ROM, but no boot ROM is loaded.
+    BootRomRequired,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InvalidFormat => write!(f, "invalid save state format"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::RomMismatch => write!(f, "save state was created with a different rom"),
+            StateError::UnexpectedEnd => write!(f, "save state ended unexpectedly"),
+            StateError::BootRomRequired => write!(f, "save state requires a boot rom"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub trait Stateful {
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_header(&mut self, rom_header: &[u8]) {
+        self.data.extend_from_slice(&STATE_MAGIC);
+        self.write_u32(STATE_VERSION);
+        self.data.extend_from_slice(rom_header);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(u8::from(value));
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    pub fn read_header(&mut self, rom_header: &[u8]) -> Result<(), StateError> {
+        if self.take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError::InvalidFormat);
+        }
+        let version = self.read_u32()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if self.take(rom_header.len())? != rom_header {
+            return Err(StateError::RomMismatch);
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position + len;
+        if end > self.data.len() {
+            return Err(StateError::UnexpectedEnd);
+        }
+        let slice = &self.data[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0x00 => Ok(false),
+            0x01 => Ok(true),
+            _ => Err(StateError::InvalidFormat),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut b = [0u8; 2];
+        b.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(b))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    // Read length prefixed bytes into a fixed size buffer, the length must match exactly.
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::InvalidFormat);
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), StateError> {
+        if self.position != self.data.len() {
+            return Err(StateError::InvalidFormat);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StateError, StateReader, StateWriter};
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_header(&[0x12; 28]);
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789A_BCDE);
+        writer.write_u64(0x0123_4567_89AB_CDEF);
+        writer.write_bytes(&[0x01, 0x02, 0x03]);
+        let data = writer.into_inner();
+
+        let mut reader = StateReader::new(&data);
+        reader.read_header(&[0x12; 28]).unwrap();
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.read_u64(), Ok(0x0123_4567_89AB_CDEF));
+        let mut buffer = [0u8; 3];
+        reader.read_bytes_into(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x01, 0x02, 0x03]);
+        assert_eq!(reader.finish(), Ok(()));
+        assert_eq!(reader.read_u8(), Err(StateError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn rejects_invalid_header() {
+        let mut writer = StateWriter::new();
+        writer.write_header(&[0x12; 28]);
+        let data = writer.into_inner();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(
+            reader.read_header(&[0x34; 28]),
+            Err(StateError::RomMismatch)
+        );
+        let mut reader = StateReader::new(&data[1..]);
+        assert_eq!(
+            reader.read_header(&[0x12; 28]),
+            Err(StateError::InvalidFormat)
+        );
+        let mut reader = StateReader::new(&data[..6]);
+        assert_eq!(
+            reader.read_header(&[0x12; 28]),
+            Err(StateError::UnexpectedEnd)
+        );
+    }
+}
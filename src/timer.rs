@@ -26,6 +26,7 @@
 use crate::clock::Clock;
 use crate::memory::Memory;
 use crate::mmu::InterruptFlag;
+use crate::state::{StateError, StateReader, StateWriter, Stateful};
 
 #[derive(Debug, Copy, Clone)]
 struct Registers {
@@ -137,3 +138,26 @@ impl Memory for Timer {
         }
     }
 }
+
+impl Stateful for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.registers.div);
+        writer.write_u8(self.registers.tima);
+        writer.write_u8(self.registers.tma);
+        writer.write_u8(self.registers.tac);
+        self.div_clock.save_state(writer);
+        self.tma_clock.save_state(writer);
+        writer.write_u8(self.interrupt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.registers.div = reader.read_u8()?;
+        self.registers.tima = reader.read_u8()?;
+        self.registers.tma = reader.read_u8()?;
+        self.registers.tac = reader.read_u8()?;
+        self.div_clock.load_state(reader)?;
+        self.tma_clock.load_state(reader)?;
+        self.interrupt = reader.read_u8()?;
+        Ok(())
+    }
+}